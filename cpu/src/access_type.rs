@@ -0,0 +1,7 @@
+// Memory accesses are either non-sequential (N) or sequential (S), the latter being an access to
+// the address directly following the previous one. Sequential accesses are faster on some buses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessType {
+    NonSequential,
+    Sequential,
+}
@@ -0,0 +1,197 @@
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
+
+use std::convert::TryInto;
+
+// A serial EEPROM of 512 bytes or 8 KiB, accessed one bit at a time through bit 0 of halfword
+// reads and writes (in practice, always with DMA3). Requests are sent MSB-first:
+//   Read:  0b11, address, 0b0; then 68 bits are read back, 4 dummy bits followed by 64 data bits
+//   Write: 0b10, address, 64 data bits, 0b0
+// Addresses are 6 bits wide for 512 byte parts and 14 bits wide for 8 KiB parts, each addressing
+// a 64-bit block.
+pub struct Eeprom {
+    memory: Vec<u8>,
+    // The bits of the request being received, and how many have been received
+    request: u128,
+    request_len: u32,
+    // The block being read out, and how many of its 68 bits have been read, if a read is underway
+    read_data: u64,
+    read_progress: Option<u32>,
+    // Set once the size is known, from a loaded save or the first request, so it can't change
+    size_locked: bool,
+}
+
+impl Eeprom {
+    pub const SIZE_512: usize = 0x200;
+    pub const SIZE_8K: usize = 0x2000;
+
+    pub fn new(size: usize) -> Self {
+        Self {
+            memory: vec![0xFF; size],
+            request: 0,
+            request_len: 0,
+            read_data: 0,
+            read_progress: None,
+            size_locked: false,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Loads a save, whose size determines the size of the EEPROM
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() == Self::SIZE_512 || data.len() == Self::SIZE_8K {
+            self.memory = data.to_vec();
+            self.size_locked = true;
+        } else {
+            let len = std::cmp::min(data.len(), self.memory.len());
+            self.memory.fill(0xFF);
+            self.memory[..len].copy_from_slice(&data[..len]);
+        }
+    }
+
+    // Requests are sent with a single DMA, so its length (in halfwords) reveals the address width
+    // and therefore the size of the part. Read requests are 9 or 17 bits long, and write requests
+    // are 73 or 81 bits long. Only the first request is used, so that a stray transfer can't
+    // shrink the EEPROM and lose the rest of the save.
+    pub fn detect_size(&mut self, request_dma_len: u32) {
+        if self.size_locked {
+            return;
+        }
+        let size = match request_dma_len {
+            9 | 73 => Self::SIZE_512,
+            17 | 81 => Self::SIZE_8K,
+            _ => return,
+        };
+        if size != self.memory.len() {
+            self.memory.resize(size, 0xFF);
+        }
+        self.size_locked = true;
+    }
+
+    fn address_width(&self) -> u32 {
+        if self.memory.len() == Self::SIZE_512 {
+            6
+        } else {
+            14
+        }
+    }
+
+    fn block_range(&self, address: usize) -> std::ops::Range<usize> {
+        let start = (address * 8) % self.memory.len();
+        start..start + 8
+    }
+
+    fn peek_bit(&self) -> u8 {
+        match self.read_progress {
+            Some(progress) if progress < 4 => 0,
+            Some(progress) => ((self.read_data >> (63 - (progress - 4))) & 1) as u8,
+            // Writes complete instantly, so the EEPROM always reports that it's ready
+            None => 1,
+        }
+    }
+
+    fn read_bit(&mut self) -> u8 {
+        let bit = self.peek_bit();
+        if let Some(progress) = self.read_progress {
+            self.read_progress = if progress + 1 < 68 {
+                Some(progress + 1)
+            } else {
+                None
+            };
+        }
+        bit
+    }
+
+    fn write_bit(&mut self, bit: u8) {
+        self.request = (self.request << 1) | (bit & 1) as u128;
+        self.request_len += 1;
+        if self.request_len < 2 {
+            return;
+        }
+
+        let address_width = self.address_width();
+        let request_type = (self.request >> (self.request_len - 2)) & 0b11;
+        match (request_type, self.request_len) {
+            (0b11, len) if len == 2 + address_width + 1 => {
+                let address = ((self.request >> 1) & ((1 << address_width) - 1)) as usize;
+                let block = &self.memory[self.block_range(address)];
+                self.read_data = u64::from_be_bytes(block.try_into().unwrap());
+                self.read_progress = Some(0);
+                self.end_request();
+            }
+            (0b10, len) if len == 2 + address_width + 64 + 1 => {
+                let address = ((self.request >> 65) & ((1 << address_width) - 1)) as usize;
+                let data = ((self.request >> 1) as u64).to_be_bytes();
+                let range = self.block_range(address);
+                self.memory[range].copy_from_slice(&data);
+                self.end_request();
+            }
+            (0b10 | 0b11, len) if len < 2 + address_width + 64 + 1 => {}
+            // Requests always start with a 1 bit, so anything else is ignored
+            _ => self.end_request(),
+        }
+    }
+
+    fn end_request(&mut self) {
+        self.request = 0;
+        self.request_len = 0;
+    }
+}
+
+// Only bit 0 of each halfword is connected, so the odd bytes read as 0 and ignore writes
+impl Memory for Eeprom {
+    fn read(&mut self, addr: usize) -> u8 {
+        if addr.is_multiple_of(2) {
+            self.read_bit()
+        } else {
+            0
+        }
+    }
+
+    fn peek(&self, addr: usize) -> u8 {
+        if addr.is_multiple_of(2) {
+            self.peek_bit()
+        } else {
+            0
+        }
+    }
+
+    fn write(&mut self, addr: usize, data: u8) {
+        if addr.is_multiple_of(2) {
+            self.write_bit(data);
+        }
+    }
+}
+
+impl SaveState for Eeprom {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.memory);
+        state.write_u128(self.request);
+        state.write_u32(self.request_len);
+        state.write_u64(self.read_data);
+        state.write_bool(self.read_progress.is_some());
+        state.write_u32(self.read_progress.unwrap_or(0));
+        state.write_bool(self.size_locked);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.memory)?;
+        self.request = state.read_u128()?;
+        self.request_len = state.read_u32()?;
+        self.read_data = state.read_u64()?;
+        let reading = state.read_bool()?;
+        let progress = state.read_u32()?;
+        self.read_progress = reading.then_some(progress);
+        self.size_locked = state.read_bool()?;
+        if self.request_len > 2 + 14 + 64 || progress >= 68 {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
+
+const BANK_SIZE: usize = 0x10000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashState {
+    Ready,
+    // Command sequences start by writing AAh to 5555h, then 55h to 2AAAh
+    Unlocking,
+    Unlocked,
+    // After command A0h, the next write programs a byte
+    Program,
+    // After command B0h, the next write to 0000h selects the bank
+    SelectBank,
+}
+
+impl FlashState {
+    fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Ready),
+            1 => Some(Self::Unlocking),
+            2 => Some(Self::Unlocked),
+            3 => Some(Self::Program),
+            4 => Some(Self::SelectBank),
+            _ => None,
+        }
+    }
+}
+
+// A 64K or 128K flash chip. The 128K parts are split into two 64K banks, since only 64K of the
+// chip is mapped at a time.
+pub struct Flash {
+    memory: Vec<u8>,
+    bank: usize,
+    state: FlashState,
+    // Whether reads from 0000h and 0001h return the manufacturer and device IDs
+    id_mode: bool,
+    // Set by command 80h, so the following command sequence can erase the chip or a sector
+    erase_armed: bool,
+}
+
+impl Flash {
+    pub fn new(n_banks: usize) -> Self {
+        Self {
+            memory: vec![0xFF; n_banks * BANK_SIZE],
+            bank: 0,
+            state: FlashState::Ready,
+            id_mode: false,
+            erase_armed: false,
+        }
+    }
+
+    pub fn n_banks(&self) -> usize {
+        self.memory.len() / BANK_SIZE
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        let len = std::cmp::min(data.len(), self.memory.len());
+        self.memory.fill(0xFF);
+        self.memory[..len].copy_from_slice(&data[..len]);
+    }
+
+    // The (manufacturer, device) ID pair. 64K chips identify as a Panasonic MN63F805MNP, and 128K
+    // chips as a Macronix MX29L010.
+    fn id(&self) -> (u8, u8) {
+        if self.n_banks() == 2 {
+            (0xC2, 0x09)
+        } else {
+            (0x32, 0x1B)
+        }
+    }
+
+    fn command(&mut self, command: u8) {
+        self.state = FlashState::Ready;
+        // Only the command straight after 80h can erase
+        let erase_armed = std::mem::take(&mut self.erase_armed);
+        match command {
+            0x90 => self.id_mode = true,
+            0xF0 => self.id_mode = false,
+            0x80 => self.erase_armed = true,
+            0x10 if erase_armed => self.memory.fill(0xFF),
+            0xA0 => self.state = FlashState::Program,
+            0xB0 if self.n_banks() == 2 => self.state = FlashState::SelectBank,
+            _ => {}
+        }
+    }
+}
+
+impl Memory for Flash {
+    fn peek(&self, addr: usize) -> u8 {
+        let addr = addr % BANK_SIZE;
+        if self.id_mode && addr < 2 {
+            let (manufacturer, device) = self.id();
+            if addr == 0 {
+                manufacturer
+            } else {
+                device
+            }
+        } else {
+            self.memory[self.bank * BANK_SIZE + addr]
+        }
+    }
+
+    fn write(&mut self, addr: usize, data: u8) {
+        let addr = addr % BANK_SIZE;
+        match (self.state, addr, data) {
+            (FlashState::Program, _, _) => {
+                self.memory[self.bank * BANK_SIZE + addr] = data;
+                self.state = FlashState::Ready;
+            }
+            (FlashState::SelectBank, 0x0000, _) => {
+                self.bank = (data & 1) as usize;
+                self.state = FlashState::Ready;
+            }
+            (FlashState::Ready, 0x5555, 0xAA) => self.state = FlashState::Unlocking,
+            (FlashState::Unlocking, 0x2AAA, 0x55) => self.state = FlashState::Unlocked,
+            (FlashState::Unlocked, 0x5555, command) => self.command(command),
+            // Sector erase takes the address of the 4K sector, rather than 5555h
+            (FlashState::Unlocked, _, 0x30) if self.erase_armed => {
+                let sector_start = self.bank * BANK_SIZE + (addr & 0xF000);
+                self.memory[sector_start..sector_start + 0x1000].fill(0xFF);
+                self.erase_armed = false;
+                self.state = FlashState::Ready;
+            }
+            // Any other write ends the command sequence
+            (FlashState::Unlocked, _, _) => {
+                self.erase_armed = false;
+                self.state = FlashState::Ready;
+            }
+            // Some chips also leave ID mode when F0h is written without a command sequence
+            (FlashState::Ready, _, 0xF0) => self.id_mode = false,
+            _ => self.state = FlashState::Ready,
+        }
+    }
+}
+
+impl SaveState for Flash {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.memory);
+        state.write_u8(self.bank as u8);
+        state.write_u8(self.state.to_u8());
+        state.write_bool(self.id_mode);
+        state.write_bool(self.erase_armed);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.memory)?;
+        self.bank = state.read_u8()? as usize;
+        self.state = FlashState::from_u8(state.read_u8()?).ok_or(StateError::Corrupt)?;
+        self.id_mode = state.read_bool()?;
+        self.erase_armed = state.read_bool()?;
+        if self.bank >= self.n_banks() {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+}
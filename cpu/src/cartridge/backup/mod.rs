@@ -0,0 +1,164 @@
+mod eeprom;
+mod flash;
+
+use self::eeprom::Eeprom;
+use self::flash::Flash;
+
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
+
+// The kinds of battery-backed memory a cartridge can have
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BackupType {
+    Sram,
+    Flash64K,
+    Flash128K,
+    Eeprom512,
+    Eeprom8K,
+}
+
+impl BackupType {
+    // Detects the backup type by searching the ROM for the ID string that Nintendo's save library
+    // embeds, which is word-aligned. ROMs without one are assumed to use SRAM, which behaves like
+    // no backup at all if the game never accesses it.
+    pub fn detect(rom: &[u8]) -> Self {
+        const IDS: [(&[u8], BackupType); 6] = [
+            (b"EEPROM_V", BackupType::Eeprom8K),
+            (b"SRAM_V", BackupType::Sram),
+            (b"SRAM_F_V", BackupType::Sram),
+            (b"FLASH_V", BackupType::Flash64K),
+            (b"FLASH512_V", BackupType::Flash64K),
+            (b"FLASH1M_V", BackupType::Flash128K),
+        ];
+
+        for i in (0..rom.len()).step_by(4) {
+            if !matches!(rom[i], b'E' | b'S' | b'F') {
+                continue;
+            }
+            for (id, backup_type) in IDS {
+                if rom[i..].starts_with(id) {
+                    return backup_type;
+                }
+            }
+        }
+        Self::Sram
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Sram => 0,
+            Self::Flash64K => 1,
+            Self::Flash128K => 2,
+            Self::Eeprom512 => 3,
+            Self::Eeprom8K => 4,
+        }
+    }
+
+    fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Sram),
+            1 => Some(Self::Flash64K),
+            2 => Some(Self::Flash128K),
+            3 => Some(Self::Eeprom512),
+            4 => Some(Self::Eeprom8K),
+            _ => None,
+        }
+    }
+}
+
+// The backup device mapped at 0E000000h. Addresses are relative to the start of the region.
+// EEPROM is instead mapped at 0D000000h, so the CPU accesses it directly.
+pub enum Backup {
+    Sram(Vec<u8>),
+    Flash(Flash),
+    Eeprom(Eeprom),
+}
+
+impl Backup {
+    // SRAM is mirrored through the rest of the region
+    const SRAM_SIZE: usize = 0x8000;
+
+    pub fn new(backup_type: BackupType) -> Self {
+        match backup_type {
+            BackupType::Sram => Self::Sram(vec![0; Self::SRAM_SIZE]),
+            BackupType::Flash64K => Self::Flash(Flash::new(1)),
+            BackupType::Flash128K => Self::Flash(Flash::new(2)),
+            BackupType::Eeprom512 => Self::Eeprom(Eeprom::new(Eeprom::SIZE_512)),
+            BackupType::Eeprom8K => Self::Eeprom(Eeprom::new(Eeprom::SIZE_8K)),
+        }
+    }
+
+    pub fn backup_type(&self) -> BackupType {
+        match self {
+            Self::Sram(_) => BackupType::Sram,
+            Self::Flash(flash) if flash.n_banks() == 2 => BackupType::Flash128K,
+            Self::Flash(_) => BackupType::Flash64K,
+            Self::Eeprom(eeprom) if eeprom.size() == Eeprom::SIZE_512 => BackupType::Eeprom512,
+            Self::Eeprom(_) => BackupType::Eeprom8K,
+        }
+    }
+
+    // The raw contents of the device, as stored in a .sav file
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Self::Sram(sram) => sram,
+            Self::Flash(flash) => flash.data(),
+            Self::Eeprom(eeprom) => eeprom.data(),
+        }
+    }
+
+    // Replaces the contents of the device. Smaller saves fill the start of the memory, and the
+    // remainder is cleared.
+    pub fn load(&mut self, data: &[u8]) {
+        match self {
+            Self::Sram(sram) => {
+                let len = std::cmp::min(data.len(), sram.len());
+                sram.fill(0);
+                sram[..len].copy_from_slice(&data[..len]);
+            }
+            Self::Flash(flash) => flash.load(data),
+            Self::Eeprom(eeprom) => eeprom.load(data),
+        }
+    }
+}
+
+impl Memory for Backup {
+    fn peek(&self, addr: usize) -> u8 {
+        match self {
+            Self::Sram(sram) => sram[addr % Self::SRAM_SIZE],
+            Self::Flash(flash) => flash.peek(addr),
+            Self::Eeprom(_) => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: usize, data: u8) {
+        match self {
+            Self::Sram(sram) => sram[addr % Self::SRAM_SIZE] = data,
+            Self::Flash(flash) => flash.write(addr, data),
+            Self::Eeprom(_) => {}
+        }
+    }
+}
+
+impl SaveState for Backup {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.backup_type().to_u8());
+        match self {
+            Self::Sram(sram) => state.write_bytes(sram),
+            Self::Flash(flash) => flash.save_state(state),
+            Self::Eeprom(eeprom) => eeprom.save_state(state),
+        }
+    }
+
+    // The state determines the type of the device, so it's replaced if the type differs
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let backup_type = BackupType::from_u8(state.read_u8()?).ok_or(StateError::Corrupt)?;
+        if backup_type != self.backup_type() {
+            *self = Self::new(backup_type);
+        }
+        match self {
+            Self::Sram(sram) => state.read_bytes_into(sram),
+            Self::Flash(flash) => flash.load_state(state),
+            Self::Eeprom(eeprom) => eeprom.load_state(state),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::ops::Range;
+
+// The 192-byte header at the start of every ROM
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub game_code: String,
+    pub maker_code: String,
+    pub version: u8,
+    pub complement_check: u8,
+    // Whether the complement check matches the rest of the header, which the BIOS requires to
+    // boot. Homebrew often doesn't bother to fill it in.
+    pub complement_check_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub const SIZE: usize = 0xC0;
+
+    // Parses the header from the start of a ROM. ROMs too small to contain a header are treated as
+    // if they were padded with zeros.
+    pub fn parse(rom: &[u8]) -> Self {
+        let mut header = [0; Self::SIZE];
+        let len = std::cmp::min(rom.len(), Self::SIZE);
+        header[..len].copy_from_slice(&rom[..len]);
+
+        // Text fields are uppercase ASCII, padded with zeros
+        let text = |range: Range<usize>| {
+            String::from_utf8_lossy(&header[range])
+                .trim_end_matches('\0')
+                .to_string()
+        };
+
+        let complement_check = header[0xBD];
+        let computed_check = header[0xA0..=0xBC]
+            .iter()
+            .fold(0u8, |check, &byte| check.wrapping_sub(byte))
+            .wrapping_sub(0x19);
+
+        Self {
+            title: text(0xA0..0xAC),
+            game_code: text(0xAC..0xB0),
+            maker_code: text(0xB0..0xB2),
+            version: header[0xBC],
+            complement_check,
+            complement_check_valid: complement_check == computed_check,
+        }
+    }
+}
@@ -0,0 +1,258 @@
+mod backup;
+mod header;
+mod peripherals;
+
+pub use self::backup::BackupType;
+pub use self::header::CartridgeHeader;
+pub use self::peripherals::Peripherals;
+
+use self::backup::Backup;
+
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum CartridgeError {
+    // The ROM doesn't fit in the 32 MiB cartridge address space
+    TooLarge(usize),
+    // The backup type can't be changed, since it would discard the save held in the current one
+    BackupInUse(BackupType),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TooLarge(size) => write!(f, "ROM is too large ({} bytes)", size),
+            Self::BackupInUse(backup_type) => write!(
+                f,
+                "the cartridge's {:?} backup holds a save, which its new type can't keep",
+                backup_type
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+// A Game Pak, made up of the ROM and the backup device used for saves
+pub struct Cartridge {
+    rom: Vec<u8>,
+    header: CartridgeHeader,
+    peripherals: Peripherals,
+    // The CRC-32 of the ROM, used to tell ROMs apart
+    checksum: u32,
+
+    backup: Backup,
+    // Set whenever the backup memory is written, so the frontend knows when to persist it
+    backup_dirty: bool,
+    // Whether the backup memory holds a save, having been loaded or written
+    has_save: bool,
+
+    // Halfwords read in place of the ROM's, keyed by their offset into it, e.g. for cheats
+    rom_patches: BTreeMap<usize, u16>,
+}
+
+impl Cartridge {
+    pub const MAX_ROM_SIZE: usize = 0x2000000;
+
+    // Creates a cartridge from a ROM image, detecting its backup type from the ROM
+    pub fn new(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        if rom.len() > Self::MAX_ROM_SIZE {
+            return Err(CartridgeError::TooLarge(rom.len()));
+        }
+
+        let header = CartridgeHeader::parse(&rom);
+        Ok(Self {
+            peripherals: Peripherals::detect(&rom, &header.game_code),
+            header,
+            checksum: crc32(&rom),
+            backup: Backup::new(BackupType::detect(&rom)),
+            backup_dirty: false,
+            has_save: false,
+            rom_patches: BTreeMap::new(),
+            rom,
+        })
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    pub fn peripherals(&self) -> Peripherals {
+        self.peripherals
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn backup_type(&self) -> BackupType {
+        self.backup.backup_type()
+    }
+
+    // Replaces the backup device with one of the given type. The save is kept if the new device is
+    // the same size, and otherwise the device can only be replaced while it doesn't hold one.
+    pub fn set_backup_type(&mut self, backup_type: BackupType) -> Result<(), CartridgeError> {
+        if backup_type == self.backup_type() {
+            return Ok(());
+        }
+        let mut backup = Backup::new(backup_type);
+        if backup.data().len() == self.backup.data().len() {
+            backup.load(self.backup.data());
+        } else if self.has_save {
+            return Err(CartridgeError::BackupInUse(self.backup_type()));
+        }
+        self.backup = backup;
+        Ok(())
+    }
+
+    // The raw contents of the battery-backed memory, as stored in a .sav file
+    pub fn backup(&self) -> &[u8] {
+        self.backup.data()
+    }
+
+    // Replaces the contents of the battery-backed memory
+    pub fn load_backup(&mut self, data: &[u8]) {
+        self.backup.load(data);
+        self.backup_dirty = false;
+        self.has_save = true;
+    }
+
+    // Returns whether the battery-backed memory has been written since the last call
+    pub fn take_backup_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.backup_dirty)
+    }
+
+    // EEPROM requests are always sent with DMA3, so the length of a transfer to the EEPROM reveals
+    // its address width, and therefore its size
+    pub fn on_dma3_transfer(&mut self, dest: usize, n_units: u32) {
+        if let Backup::Eeprom(eeprom) = &mut self.backup {
+            if (0x0D000000..=0x0DFFFFFF).contains(&dest) {
+                eeprom.detect_size(n_units);
+            }
+        }
+    }
+
+    // The value read from the ROM regions where there's no ROM to respond. The cartridge bus is
+    // shared between the address and data, so the data read back is the low bits of the halfword
+    // address that was last put on it.
+    pub fn open_bus(addr: usize) -> u8 {
+        let halfword = (addr / 2) & 0xFFFF;
+        (halfword >> ((addr & 1) * 8)) as u8
+    }
+
+    // The offset into the ROM read from the given address, or None if reading it returns
+    // something other than the ROM's contents, e.g. the EEPROM's output
+    pub fn rom_offset(&self, addr: usize) -> Option<usize> {
+        match addr {
+            0x08000000..=0x0DFFFFFF if !self.is_eeprom_addr(addr) => {
+                let offset = (addr - 0x08000000) % Self::MAX_ROM_SIZE;
+                Some(offset).filter(|&offset| offset < self.rom.len())
+            }
+            _ => None,
+        }
+    }
+
+    // Replaces the halfwords read from the ROM at the given offsets, which are rounded down to
+    // halfwords. The ROM itself is left unchanged, so the patches can be undone.
+    pub fn set_rom_patches(&mut self, patches: BTreeMap<usize, u16>) {
+        self.rom_patches = patches
+            .into_iter()
+            .map(|(offset, value)| (offset & !1, value))
+            .collect();
+    }
+
+    // The ROM is mirrored in each of the three wait state regions
+    fn read_rom(&self, addr: usize) -> u8 {
+        let offset = (addr - 0x08000000) % Self::MAX_ROM_SIZE;
+        if !self.rom_patches.is_empty() {
+            if let Some(&value) = self.rom_patches.get(&(offset & !1)) {
+                return (value >> ((offset & 1) * 8)) as u8;
+            }
+        }
+        match self.rom.get(offset) {
+            Some(&data) => data,
+            None => Self::open_bus(addr),
+        }
+    }
+
+    // The EEPROM takes up the whole of the last region, unless the ROM is large enough to reach
+    // into it, in which case it only takes up the last 256 bytes
+    fn is_eeprom_addr(&self, addr: usize) -> bool {
+        match addr {
+            0x0DFFFF00..=0x0DFFFFFF => true,
+            0x0D000000..=0x0DFFFFFF => self.rom.len() <= 0x1000000,
+            _ => false,
+        }
+    }
+}
+
+// Addresses are absolute, since the cartridge spans several regions of the bus
+impl Memory for Cartridge {
+    fn read(&mut self, addr: usize) -> u8 {
+        let is_eeprom_addr = self.is_eeprom_addr(addr);
+        match &mut self.backup {
+            // Reading from the EEPROM advances its serial output
+            Backup::Eeprom(eeprom) if is_eeprom_addr => eeprom.read(addr - 0x0D000000),
+            _ => self.peek(addr),
+        }
+    }
+
+    fn peek(&self, addr: usize) -> u8 {
+        match (addr, &self.backup) {
+            (_, Backup::Eeprom(eeprom)) if self.is_eeprom_addr(addr) => {
+                eeprom.peek(addr - 0x0D000000)
+            }
+            (0x08000000..=0x0DFFFFFF, _) => self.read_rom(addr),
+            (0x0E000000..=0x0FFFFFFF, backup) => backup.peek(addr - 0x0E000000),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: usize, data: u8) {
+        let is_eeprom_addr = self.is_eeprom_addr(addr);
+        match (addr, &mut self.backup) {
+            (_, Backup::Eeprom(eeprom)) if is_eeprom_addr => {
+                eeprom.write(addr - 0x0D000000, data);
+                self.backup_dirty = true;
+                self.has_save = true;
+            }
+            (0x0E000000..=0x0FFFFFFF, backup) => {
+                backup.write(addr - 0x0E000000, data);
+                self.backup_dirty = true;
+                self.has_save = true;
+            }
+            // The ROM is read-only
+            _ => {}
+        }
+    }
+}
+
+// The ROM isn't saved, since it can't change while running
+impl SaveState for Cartridge {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.backup.save_state(state);
+        state.write_bool(self.has_save);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.backup.load_state(state)?;
+        self.has_save = state.read_bool()?;
+        Ok(())
+    }
+}
+
+// The standard (IEEE 802.3) CRC-32
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
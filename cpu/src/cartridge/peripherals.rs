@@ -0,0 +1,93 @@
+// Hardware built into some cartridges besides the ROM and backup memory. Most of it is wired to
+// the GPIO port at 080000C4h, and none of it is emulated yet, so this is only used to warn that a
+// game may not work.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Peripherals {
+    pub rtc: bool,
+    pub solar_sensor: bool,
+    pub tilt_sensor: bool,
+    pub gyro_sensor: bool,
+    pub rumble: bool,
+}
+
+impl Peripherals {
+    const NONE: Peripherals = Peripherals {
+        rtc: false,
+        solar_sensor: false,
+        tilt_sensor: false,
+        gyro_sensor: false,
+        rumble: false,
+    };
+
+    const RTC: Peripherals = Peripherals {
+        rtc: true,
+        ..Self::NONE
+    };
+    const RTC_AND_SOLAR_SENSOR: Peripherals = Peripherals {
+        solar_sensor: true,
+        ..Self::RTC
+    };
+    const TILT_SENSOR: Peripherals = Peripherals {
+        tilt_sensor: true,
+        ..Self::NONE
+    };
+    const RUMBLE: Peripherals = Peripherals {
+        rumble: true,
+        ..Self::NONE
+    };
+    const GYRO_SENSOR_AND_RUMBLE: Peripherals = Peripherals {
+        gyro_sensor: true,
+        ..Self::RUMBLE
+    };
+
+    // Games are identified by the first three characters of their game code, since the last one
+    // is the region
+    const GAMES: [(&'static str, Peripherals); 10] = [
+        // Pokemon Ruby, Sapphire and Emerald
+        ("AXV", Self::RTC),
+        ("AXP", Self::RTC),
+        ("BPE", Self::RTC),
+        // Rockman EXE 4.5: Real Operation
+        ("BR4", Self::RTC),
+        // The Boktai series, which also uses the clock to follow the time of day
+        ("U3I", Self::RTC_AND_SOLAR_SENSOR),
+        ("U32", Self::RTC_AND_SOLAR_SENSOR),
+        ("U33", Self::RTC_AND_SOLAR_SENSOR),
+        // Yoshi Topsy-Turvy
+        ("KYG", Self::TILT_SENSOR),
+        // WarioWare: Twisted!
+        ("RZW", Self::GYRO_SENSOR_AND_RUMBLE),
+        // Drill Dozer
+        ("V49", Self::RUMBLE),
+    ];
+
+    // Detects a cartridge's peripherals from its game code. Other games with a real-time clock are
+    // found by the ID string of Nintendo's RTC library, which is word-aligned like the backup IDs.
+    pub fn detect(rom: &[u8], game_code: &str) -> Self {
+        let known = Self::GAMES
+            .iter()
+            .find(|(code, _)| game_code.starts_with(code))
+            .map(|&(_, peripherals)| peripherals);
+        known.unwrap_or_else(|| Peripherals {
+            rtc: (0..rom.len())
+                .step_by(4)
+                .any(|i| rom[i..].starts_with(b"SIIRTC_V")),
+            ..Self::NONE
+        })
+    }
+
+    // The names of the peripherals present
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.rtc, "real-time clock"),
+            (self.solar_sensor, "solar sensor"),
+            (self.tilt_sensor, "tilt sensor"),
+            (self.gyro_sensor, "gyro sensor"),
+            (self.rumble, "rumble"),
+        ]
+        .iter()
+        .filter(|&&(present, _)| present)
+        .map(|&(_, name)| name)
+        .collect()
+    }
+}
@@ -1,5 +1,5 @@
 // https://www.ecs.csun.edu/~smirzaei/docs/ece425/arm7tdmi_instruction_set_reference.pdf page 2
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Condition {
     EQ, // Equal: Z==1
     NE, // Not equal: Z==0
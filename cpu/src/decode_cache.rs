@@ -0,0 +1,75 @@
+use crate::{Condition, Instruction};
+
+// An instruction along with the opcode and mode it was decoded from
+#[derive(Clone, Copy)]
+pub struct DecodedInstruction {
+    pub opcode: u32,
+    pub thumb: bool,
+    pub condition: Condition,
+    pub instruction: Instruction,
+}
+
+impl DecodedInstruction {
+    pub fn decode(opcode: u32, thumb: bool) -> Self {
+        let (condition, instruction) = if thumb {
+            Instruction::decode_thumb(opcode as u16)
+        } else {
+            Instruction::decode_arm(opcode)
+        };
+        Self {
+            opcode,
+            thumb,
+            condition,
+            instruction,
+        }
+    }
+}
+
+// Caches decoded instructions from the memory code usually runs from: the BIOS, IWRAM and the
+// cartridge ROM. Instructions are keyed by their location in the backing memory rather than by
+// address, so mirrors share entries. The locations of the three memories are laid out one after
+// another, and entries are allocated a page at a time as code is run.
+pub struct DecodeCache {
+    pages: Vec<Option<Box<[Option<DecodedInstruction>]>>>,
+}
+
+impl DecodeCache {
+    pub const BIOS_BASE: usize = 0;
+    pub const IWRAM_BASE: usize = 0x4000;
+    pub const ROM_BASE: usize = 0xC000;
+    const SIZE: usize = Self::ROM_BASE + 0x2000000;
+
+    // The number of bytes of memory covered by a page, with an entry for each halfword
+    const PAGE_SIZE: usize = 0x400;
+
+    pub fn new() -> Self {
+        Self {
+            pages: vec![None; Self::SIZE / Self::PAGE_SIZE],
+        }
+    }
+
+    pub fn get(&self, location: usize) -> Option<DecodedInstruction> {
+        let page = self.pages[location / Self::PAGE_SIZE].as_ref()?;
+        page[(location % Self::PAGE_SIZE) / 2]
+    }
+
+    pub fn insert(&mut self, location: usize, decoded: DecodedInstruction) {
+        let page = self.pages[location / Self::PAGE_SIZE]
+            .get_or_insert_with(|| vec![None; Self::PAGE_SIZE / 2].into_boxed_slice());
+        page[(location % Self::PAGE_SIZE) / 2] = Some(decoded);
+    }
+
+    // Drops the instructions overlapping the byte at the given location, i.e. the Thumb
+    // instruction in its halfword and the ARM instruction in its word
+    pub fn invalidate(&mut self, location: usize) {
+        if let Some(page) = &mut self.pages[location / Self::PAGE_SIZE] {
+            let offset = location % Self::PAGE_SIZE;
+            page[offset / 2] = None;
+            page[(offset & !0b11) / 2] = None;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.iter_mut().for_each(|page| *page = None);
+    }
+}
@@ -0,0 +1,528 @@
+// Disassembly into the mnemonics of the ARM7TDMI data sheet, e.g. `ldmia r0!, {r1-r4}` and
+// `msr cpsr_fc, r0`. Branch targets are shown as absolute addresses, immediates in hexadecimal
+// and shift amounts in decimal.
+// https://www.ecs.csun.edu/~smirzaei/docs/ece425/arm7tdmi_instruction_set_reference.pdf
+use crate::condition::Condition;
+use crate::instruction::{
+    AluOpcode, HalfwordKind, Indexing, Instruction, PsrOperand, ShiftAmount, ShiftType,
+    ShifterOperand, TransferOffset,
+};
+
+const REGISTER_NAMES: [&str; 16] = [
+    "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12", "sp", "lr",
+    "pc",
+];
+
+// Disassembles the ARM instruction with the given encoding, located at `addr`
+pub fn disassemble_arm(encoding: u32, addr: u32) -> String {
+    let (condition, instruction) = Instruction::decode_arm(encoding);
+    let cond = condition_suffix(condition);
+
+    match instruction {
+        Instruction::DataProcessing {
+            opcode,
+            set_flags,
+            rn,
+            rd,
+            operand,
+        } => {
+            // The test operations always set the flags, so they don't take an S suffix
+            let s = if set_flags && !opcode.is_test() {
+                "s"
+            } else {
+                ""
+            };
+            let mnemonic = format!("{}{}{}", alu_mnemonic(opcode), cond, s);
+            let operand = shifter_operand(operand);
+            match opcode {
+                AluOpcode::Mov | AluOpcode::Mvn => format!("{} {}, {}", mnemonic, reg(rd), operand),
+                _ if opcode.is_test() => format!("{} {}, {}", mnemonic, reg(rn), operand),
+                _ => format!("{} {}, {}, {}", mnemonic, reg(rd), reg(rn), operand),
+            }
+        }
+        Instruction::Mrs { use_spsr, rd } => {
+            format!("mrs{} {}, {}", cond, reg(rd), psr_name(use_spsr))
+        }
+        Instruction::Msr {
+            use_spsr,
+            field_mask,
+            operand,
+        } => {
+            // The fields are listed in the order the assembler conventionally accepts them
+            let fields: String = [(3, 'f'), (2, 's'), (1, 'x'), (0, 'c')]
+                .iter()
+                .filter(|(bit, _)| (field_mask >> bit) & 1 == 1)
+                .map(|&(_, field)| field)
+                .collect();
+            let operand = match operand {
+                PsrOperand::Immediate(val) => imm(val),
+                PsrOperand::Register(rm) => reg(rm).to_string(),
+            };
+            format!("msr{} {}_{}, {}", cond, psr_name(use_spsr), fields, operand)
+        }
+        Instruction::Multiply {
+            accumulate,
+            set_flags,
+            rd,
+            rn,
+            rs,
+            rm,
+        } => {
+            let s = if set_flags { "s" } else { "" };
+            if accumulate {
+                let regs = format!("{}, {}, {}, {}", reg(rd), reg(rm), reg(rs), reg(rn));
+                format!("mla{}{} {}", cond, s, regs)
+            } else {
+                format!("mul{}{} {}, {}, {}", cond, s, reg(rd), reg(rm), reg(rs))
+            }
+        }
+        Instruction::MultiplyLong {
+            signed,
+            accumulate,
+            set_flags,
+            rd_hi,
+            rd_lo,
+            rs,
+            rm,
+        } => {
+            let mnemonic = match (signed, accumulate) {
+                (false, false) => "umull",
+                (false, true) => "umlal",
+                (true, false) => "smull",
+                (true, true) => "smlal",
+            };
+            let s = if set_flags { "s" } else { "" };
+            let regs = format!("{}, {}, {}, {}", reg(rd_lo), reg(rd_hi), reg(rm), reg(rs));
+            format!("{}{}{} {}", mnemonic, cond, s, regs)
+        }
+        Instruction::SingleSwap { byte, rn, rd, rm } => {
+            let b = if byte { "b" } else { "" };
+            format!("swp{}{} {}, {}, [{}]", cond, b, reg(rd), reg(rm), reg(rn))
+        }
+        Instruction::BranchExchange { rm } => format!("bx{} {}", cond, reg(rm)),
+        Instruction::HalfwordTransfer {
+            load,
+            kind,
+            rn,
+            rd,
+            indexing,
+            offset,
+        } => {
+            let kind = match kind {
+                HalfwordKind::UnsignedHalfword => "h",
+                HalfwordKind::SignedByte => "sb",
+                HalfwordKind::SignedHalfword => "sh",
+            };
+            let mnemonic = format!("{}{}{}", if load { "ldr" } else { "str" }, cond, kind);
+            let address = transfer_address(rn, indexing, offset);
+            format!("{} {}, {}", mnemonic, reg(rd), address)
+        }
+        Instruction::SingleTransfer {
+            load,
+            byte,
+            rn,
+            rd,
+            indexing,
+            offset,
+        } => {
+            let b = if byte { "b" } else { "" };
+            // Post-indexed transfers with the write-back bit set use user mode permissions
+            let t = if !indexing.pre_index && indexing.write_back {
+                "t"
+            } else {
+                ""
+            };
+            let mnemonic = format!("{}{}{}{}", if load { "ldr" } else { "str" }, cond, b, t);
+            let address = transfer_address(rn, indexing, offset);
+            format!("{} {}, {}", mnemonic, reg(rd), address)
+        }
+        Instruction::BlockTransfer {
+            load,
+            psr_force_user,
+            rn,
+            register_list,
+            indexing,
+        } => {
+            let mode = match (indexing.pre_index, indexing.up) {
+                (false, true) => "ia",
+                (true, true) => "ib",
+                (false, false) => "da",
+                (true, false) => "db",
+            };
+            format!(
+                "{}{}{} {}{}, {}{}",
+                if load { "ldm" } else { "stm" },
+                cond,
+                mode,
+                reg(rn),
+                if indexing.write_back { "!" } else { "" },
+                register_list_string(register_list),
+                if psr_force_user { "^" } else { "" }
+            )
+        }
+        Instruction::Branch { link, offset } => {
+            let target = addr.wrapping_add(8).wrapping_add(offset as u32);
+            let l = if link { "l" } else { "" };
+            format!("b{}{} 0x{:08X}", l, cond, target)
+        }
+        Instruction::CoprocDataTransfer {
+            load,
+            long,
+            cp_num,
+            crd,
+            rn,
+            indexing,
+            offset,
+        } => {
+            let mnemonic = format!(
+                "{}{}{}",
+                if load { "ldc" } else { "stc" },
+                cond,
+                if long { "l" } else { "" }
+            );
+            let address = transfer_address(rn, indexing, TransferOffset::Immediate(offset));
+            format!("{} p{}, c{}, {}", mnemonic, cp_num, crd, address)
+        }
+        Instruction::CoprocOperation {
+            cp_opcode,
+            cp_num,
+            crd,
+            crn,
+            crm,
+            cp_info,
+        } => format!(
+            "cdp{} p{}, {}, c{}, c{}, c{}, {}",
+            cond, cp_num, cp_opcode, crd, crn, crm, cp_info
+        ),
+        Instruction::CoprocRegTransfer {
+            load,
+            cp_opcode,
+            cp_num,
+            rd,
+            crn,
+            crm,
+            cp_info,
+        } => format!(
+            "{}{} p{}, {}, {}, c{}, c{}, {}",
+            if load { "mrc" } else { "mcr" },
+            cond,
+            cp_num,
+            cp_opcode,
+            reg(rd),
+            crn,
+            crm,
+            cp_info
+        ),
+        Instruction::SoftwareInterrupt { comment } => format!("swi{} {}", cond, imm(comment)),
+        Instruction::Undefined
+        | Instruction::ThumbBranchPrefix { .. }
+        | Instruction::ThumbBranchSuffix { .. } => format!(".word 0x{:08X}", encoding),
+    }
+}
+
+// Disassembles the Thumb instruction with the given encoding, located at `addr`. A long branch
+// with link is made up of two instructions, so `next` is the halfword following this one, which
+// gives the target of a BL starting here.
+pub fn disassemble_thumb(encoding: u16, next: u16, addr: u32) -> String {
+    let e = encoding as u32;
+    let bits = |lo: u32, len: u32| (e >> lo) & ((1 << len) - 1);
+    let low_reg = |lo: u32| reg(bits(lo, 3) as usize);
+
+    match e >> 11 {
+        // Add/subtract
+        0b00011 => {
+            let mnemonic = if bits(9, 1) == 1 { "sub" } else { "add" };
+            let operand = if bits(10, 1) == 1 {
+                imm(bits(6, 3))
+            } else {
+                low_reg(6).to_string()
+            };
+            format!("{} {}, {}, {}", mnemonic, low_reg(0), low_reg(3), operand)
+        }
+        // Move shifted register, where LSR #0 and ASR #0 encode shifts by 32
+        0b00000..=0b00010 => {
+            let shift = ShiftType::from_u32(bits(11, 2));
+            let amount = match (shift, bits(6, 5)) {
+                (ShiftType::Lsl, amount) => amount,
+                (_, 0) => 32,
+                (_, amount) => amount,
+            };
+            let mnemonic = shift_mnemonic(shift);
+            format!("{} {}, {}, #{}", mnemonic, low_reg(0), low_reg(3), amount)
+        }
+        // Move/compare/add/subtract immediate
+        0b00100..=0b00111 => {
+            let mnemonic = ["mov", "cmp", "add", "sub"][bits(11, 2) as usize];
+            format!("{} {}, {}", mnemonic, low_reg(8), imm(bits(0, 8)))
+        }
+        // ALU operations
+        0b01000 if bits(10, 1) == 0 => {
+            let mnemonic = [
+                "and", "eor", "lsl", "lsr", "asr", "adc", "sbc", "ror", "tst", "neg", "cmp", "cmn",
+                "orr", "mul", "bic", "mvn",
+            ][bits(6, 4) as usize];
+            format!("{} {}, {}", mnemonic, low_reg(0), low_reg(3))
+        }
+        // Hi register operations/branch exchange
+        0b01000 => {
+            let rd = reg(((bits(7, 1) << 3) | bits(0, 3)) as usize);
+            let rs = reg(bits(3, 4) as usize);
+            match bits(8, 2) {
+                0b00 => format!("add {}, {}", rd, rs),
+                0b01 => format!("cmp {}, {}", rd, rs),
+                0b10 => format!("mov {}, {}", rd, rs),
+                _ => format!("bx {}", rs),
+            }
+        }
+        // PC-relative load
+        0b01001 => format!("ldr {}, [pc, {}]", low_reg(8), imm(bits(0, 8) << 2)),
+        // Load/store with register offset, and load/store sign-extended byte/halfword
+        0b01010 | 0b01011 => {
+            let mnemonic = [
+                "str", "strh", "strb", "ldrsb", "ldr", "ldrh", "ldrb", "ldrsh",
+            ][bits(9, 3) as usize];
+            let (rd, rb, ro) = (low_reg(0), low_reg(3), low_reg(6));
+            format!("{} {}, [{}, {}]", mnemonic, rd, rb, ro)
+        }
+        // Load/store with immediate offset
+        0b01100..=0b01111 => {
+            let byte = bits(12, 1) == 1;
+            let mnemonic = match (bits(11, 1) == 1, byte) {
+                (false, false) => "str",
+                (true, false) => "ldr",
+                (false, true) => "strb",
+                (true, true) => "ldrb",
+            };
+            let offset = if byte { bits(6, 5) } else { bits(6, 5) << 2 };
+            format!(
+                "{} {}, {}",
+                mnemonic,
+                low_reg(0),
+                offset_address(low_reg(3), offset)
+            )
+        }
+        // Load/store halfword
+        0b10000 | 0b10001 => {
+            let mnemonic = if bits(11, 1) == 1 { "ldrh" } else { "strh" };
+            let address = offset_address(low_reg(3), bits(6, 5) << 1);
+            format!("{} {}, {}", mnemonic, low_reg(0), address)
+        }
+        // SP-relative load/store
+        0b10010 | 0b10011 => {
+            let mnemonic = if bits(11, 1) == 1 { "ldr" } else { "str" };
+            let address = offset_address("sp", bits(0, 8) << 2);
+            format!("{} {}, {}", mnemonic, low_reg(8), address)
+        }
+        // Load address
+        0b10100 | 0b10101 => {
+            let base = if bits(11, 1) == 1 { "sp" } else { "pc" };
+            format!("add {}, {}, {}", low_reg(8), base, imm(bits(0, 8) << 2))
+        }
+        // Add offset to stack pointer
+        0b10110 if bits(8, 4) == 0b0000 => {
+            let mnemonic = if bits(7, 1) == 1 { "sub" } else { "add" };
+            format!("{} sp, {}", mnemonic, imm(bits(0, 7) << 2))
+        }
+        // Push/pop registers, optionally with LR or PC respectively
+        0b10110 | 0b10111 if bits(9, 2) == 0b10 => {
+            let load = bits(11, 1) == 1;
+            let extra = if load { 1 << 15 } else { 1 << 14 };
+            let list = bits(0, 8) | if bits(8, 1) == 1 { extra } else { 0 };
+            let mnemonic = if load { "pop" } else { "push" };
+            format!("{} {}", mnemonic, register_list_string(list as u16))
+        }
+        // Multiple load/store
+        0b11000 | 0b11001 => {
+            let mnemonic = if bits(11, 1) == 1 { "ldmia" } else { "stmia" };
+            let list = register_list_string(bits(0, 8) as u16);
+            format!("{} {}!, {}", mnemonic, low_reg(8), list)
+        }
+        // Software interrupt
+        0b11011 if bits(8, 3) == 0b111 => format!("swi {}", imm(bits(0, 8))),
+        // Conditional branch, where the AL condition is undefined
+        0b11010 | 0b11011 if bits(8, 4) != 0b1110 => {
+            let offset = (((e << 24) as i32) >> 23) as u32;
+            let target = addr.wrapping_add(4).wrapping_add(offset);
+            let cond = condition_suffix(Condition::from_u8(bits(8, 4) as u8));
+            format!("b{} 0x{:08X}", cond, target)
+        }
+        // Unconditional branch
+        0b11100 => {
+            let offset = (((e << 21) as i32) >> 20) as u32;
+            format!("b 0x{:08X}", addr.wrapping_add(4).wrapping_add(offset))
+        }
+        // Long branch with link, first half. On its own, it adds the upper half of the offset to
+        // the PC and stores the result in LR.
+        0b11110 => {
+            let offset = ((e << 21) as i32) >> 9;
+            if next >> 11 == 0b11111 {
+                let offset = offset.wrapping_add(((next & 0x7FF) << 1) as i32) as u32;
+                format!("bl 0x{:08X}", addr.wrapping_add(4).wrapping_add(offset))
+            } else {
+                format!(
+                    "add lr, pc, {}",
+                    signed_imm(offset >= 0, offset.unsigned_abs())
+                )
+            }
+        }
+        // Long branch with link, second half, which branches relative to LR
+        0b11111 => format!("bl lr+0x{:X}", bits(0, 11) << 1),
+        _ => format!(".hword 0x{:04X}", encoding),
+    }
+}
+
+fn reg(n: usize) -> &'static str {
+    REGISTER_NAMES[n]
+}
+
+fn imm(val: u32) -> String {
+    format!("#0x{:X}", val)
+}
+
+fn signed_imm(up: bool, val: u32) -> String {
+    format!("#{}0x{:X}", if up { "" } else { "-" }, val)
+}
+
+fn condition_suffix(condition: Condition) -> &'static str {
+    match condition {
+        Condition::EQ => "eq",
+        Condition::NE => "ne",
+        Condition::CS => "cs",
+        Condition::CC => "cc",
+        Condition::MI => "mi",
+        Condition::PL => "pl",
+        Condition::VS => "vs",
+        Condition::VC => "vc",
+        Condition::HI => "hi",
+        Condition::LS => "ls",
+        Condition::GE => "ge",
+        Condition::LT => "lt",
+        Condition::GT => "gt",
+        Condition::LE => "le",
+        Condition::AL => "",
+        Condition::NV => "nv",
+    }
+}
+
+fn alu_mnemonic(opcode: AluOpcode) -> &'static str {
+    match opcode {
+        AluOpcode::And => "and",
+        AluOpcode::Eor => "eor",
+        AluOpcode::Sub => "sub",
+        AluOpcode::Rsb => "rsb",
+        AluOpcode::Add => "add",
+        AluOpcode::Adc => "adc",
+        AluOpcode::Sbc => "sbc",
+        AluOpcode::Rsc => "rsc",
+        AluOpcode::Tst => "tst",
+        AluOpcode::Teq => "teq",
+        AluOpcode::Cmp => "cmp",
+        AluOpcode::Cmn => "cmn",
+        AluOpcode::Orr => "orr",
+        AluOpcode::Mov => "mov",
+        AluOpcode::Bic => "bic",
+        AluOpcode::Mvn => "mvn",
+    }
+}
+
+fn shift_mnemonic(shift: ShiftType) -> &'static str {
+    match shift {
+        ShiftType::Lsl => "lsl",
+        ShiftType::Lsr => "lsr",
+        ShiftType::Asr => "asr",
+        ShiftType::Ror => "ror",
+    }
+}
+
+fn psr_name(use_spsr: bool) -> &'static str {
+    if use_spsr {
+        "spsr"
+    } else {
+        "cpsr"
+    }
+}
+
+// Formats a register shifted by an immediate, where LSL #0 is no shift, and an amount of 0
+// encodes LSR #32, ASR #32 and RRX for the other shifts
+fn shifted_reg(rm: usize, shift: ShiftType, amount: u32) -> String {
+    match (shift, amount) {
+        (ShiftType::Lsl, 0) => reg(rm).to_string(),
+        (ShiftType::Ror, 0) => format!("{}, rrx", reg(rm)),
+        (_, 0) => format!("{}, {} #32", reg(rm), shift_mnemonic(shift)),
+        _ => format!("{}, {} #{}", reg(rm), shift_mnemonic(shift), amount),
+    }
+}
+
+fn shifter_operand(operand: ShifterOperand) -> String {
+    match operand {
+        ShifterOperand::Immediate { imm: val, rotate } => imm(val.rotate_right(rotate)),
+        ShifterOperand::Register {
+            rm,
+            shift,
+            amount: ShiftAmount::Immediate(amount),
+        } => shifted_reg(rm, shift, amount),
+        ShifterOperand::Register {
+            rm,
+            shift,
+            amount: ShiftAmount::Register(rs),
+        } => format!("{}, {} {}", reg(rm), shift_mnemonic(shift), reg(rs)),
+    }
+}
+
+fn offset_address(base: &str, offset: u32) -> String {
+    if offset == 0 {
+        format!("[{}]", base)
+    } else {
+        format!("[{}, {}]", base, imm(offset))
+    }
+}
+
+fn transfer_address(rn: usize, indexing: Indexing, offset: TransferOffset) -> String {
+    let offset = match offset {
+        TransferOffset::Immediate(0) if indexing.pre_index => None,
+        TransferOffset::Immediate(val) => Some(signed_imm(indexing.up, val)),
+        TransferOffset::Register { rm, shift, amount } => {
+            let sign = if indexing.up { "" } else { "-" };
+            Some(format!("{}{}", sign, shifted_reg(rm, shift, amount)))
+        }
+    };
+
+    match (indexing.pre_index, offset) {
+        (true, None) => format!(
+            "[{}]{}",
+            reg(rn),
+            if indexing.write_back { "!" } else { "" }
+        ),
+        (true, Some(offset)) => format!(
+            "[{}, {}]{}",
+            reg(rn),
+            offset,
+            if indexing.write_back { "!" } else { "" }
+        ),
+        (false, Some(offset)) => format!("[{}], {}", reg(rn), offset),
+        (false, None) => unreachable!(),
+    }
+}
+
+// Formats a register list, collapsing runs of three or more registers into ranges
+fn register_list_string(list: u16) -> String {
+    let mut parts = Vec::new();
+    let mut n = 0;
+    while n < 16 {
+        if (list >> n) & 1 == 0 {
+            n += 1;
+            continue;
+        }
+
+        let start = n;
+        while n < 16 && (list >> n) & 1 == 1 {
+            n += 1;
+        }
+        match n - start {
+            1 => parts.push(reg(start).to_string()),
+            2 => parts.extend([reg(start).to_string(), reg(start + 1).to_string()]),
+            _ => parts.push(format!("{}-{}", reg(start), reg(n - 1))),
+        }
+    }
+    format!("{{{}}}", parts.join(", "))
+}
@@ -0,0 +1,8 @@
+// The low-power modes entered by writing to HALTCNT. Halt pauses the CPU until an enabled
+// interrupt is requested, while Stop also pauses the video, sound and timers until a keypad,
+// cartridge or serial interrupt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HaltMode {
+    Halt,
+    Stop,
+}
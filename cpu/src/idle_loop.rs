@@ -0,0 +1,70 @@
+// Detects idle loops: short loops that spin waiting for memory to change, such as polling VCOUNT
+// or a flag set by an IRQ handler. If an iteration of the loop has no side effects and leaves the
+// registers exactly as the previous iteration did, every later iteration will too, until an
+// event (an interrupt, DMA or the PPU advancing) changes the memory it reads.
+pub struct IdleLoopDetector {
+    enabled: bool,
+    // The start and end addresses of the loop being watched
+    loop_range: Option<(u32, u32)>,
+    // The registers and CPSR at the end of the previous iteration
+    snapshot: Option<([u32; 16], u32)>,
+    // Whether the current iteration has done anything besides reading memory that only changes
+    // through events
+    side_effects: bool,
+}
+
+impl IdleLoopDetector {
+    // The longest loop considered, in bytes
+    const MAX_LOOP_SIZE: u32 = 0x40;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            loop_range: None,
+            snapshot: None,
+            side_effects: false,
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.reset();
+    }
+
+    // Stops watching the current loop, e.g. when an interrupt is taken
+    pub fn reset(&mut self) {
+        self.loop_range = None;
+        self.snapshot = None;
+    }
+
+    pub fn on_side_effect(&mut self) {
+        self.side_effects = true;
+    }
+
+    // Called whenever a branch is taken, with the register state after the branch. Returns
+    // whether the branch closes an idle loop.
+    pub fn on_branch(&mut self, from: u32, to: u32, state: ([u32; 16], u32)) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let side_effects = std::mem::take(&mut self.side_effects);
+        if to > from || from - to > Self::MAX_LOOP_SIZE {
+            self.reset();
+            return false;
+        }
+
+        if self.loop_range != Some((to, from)) || side_effects {
+            self.loop_range = Some((to, from));
+            self.snapshot = Some(state);
+            return false;
+        }
+
+        if self.snapshot == Some(state) {
+            true
+        } else {
+            self.snapshot = Some(state);
+            false
+        }
+    }
+}
@@ -0,0 +1,744 @@
+// Structured decoding of ARMv4T instructions. ARM and Thumb encodings decode to the same
+// representation: Thumb instructions are expressed as their ARM equivalents, except for the two
+// halves of the Thumb long branch with link, which have no ARM counterpart.
+// https://www.ecs.csun.edu/~smirzaei/docs/ece425/arm7tdmi_instruction_set_reference.pdf page 1
+use crate::condition::Condition;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AluOpcode {
+    And,
+    Eor,
+    Sub,
+    Rsb,
+    Add,
+    Adc,
+    Sbc,
+    Rsc,
+    Tst,
+    Teq,
+    Cmp,
+    Cmn,
+    Orr,
+    Mov,
+    Bic,
+    Mvn,
+}
+
+impl AluOpcode {
+    pub fn from_u32(val: u32) -> Self {
+        match val & 0b1111 {
+            0b0000 => Self::And,
+            0b0001 => Self::Eor,
+            0b0010 => Self::Sub,
+            0b0011 => Self::Rsb,
+            0b0100 => Self::Add,
+            0b0101 => Self::Adc,
+            0b0110 => Self::Sbc,
+            0b0111 => Self::Rsc,
+            0b1000 => Self::Tst,
+            0b1001 => Self::Teq,
+            0b1010 => Self::Cmp,
+            0b1011 => Self::Cmn,
+            0b1100 => Self::Orr,
+            0b1101 => Self::Mov,
+            0b1110 => Self::Bic,
+            _ => Self::Mvn,
+        }
+    }
+
+    // Whether the operation only updates the condition flags, without writing a result
+    pub fn is_test(self) -> bool {
+        matches!(self, Self::Tst | Self::Teq | Self::Cmp | Self::Cmn)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShiftType {
+    Lsl,
+    Lsr,
+    Asr,
+    Ror,
+}
+
+impl ShiftType {
+    pub fn from_u32(val: u32) -> Self {
+        match val & 0b11 {
+            0b00 => Self::Lsl,
+            0b01 => Self::Lsr,
+            0b10 => Self::Asr,
+            _ => Self::Ror,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShiftAmount {
+    // A 5-bit amount, where 0 encodes LSR #32, ASR #32 and RRX for the respective shifts
+    Immediate(u32),
+    // The bottom byte of a register
+    Register(usize),
+}
+
+// The second operand of a data processing instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShifterOperand {
+    // An 8-bit immediate, rotated right by an even number of bits
+    Immediate {
+        imm: u32,
+        rotate: u32,
+    },
+    Register {
+        rm: usize,
+        shift: ShiftType,
+        amount: ShiftAmount,
+    },
+}
+
+// The source operand of an MSR instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PsrOperand {
+    Immediate(u32),
+    Register(usize),
+}
+
+// The offset from the base register of a load or store
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferOffset {
+    Immediate(u32),
+    Register {
+        rm: usize,
+        shift: ShiftType,
+        amount: u32,
+    },
+}
+
+// The addressing mode flags shared by all loads and stores
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Indexing {
+    pub pre_index: bool,
+    pub up: bool,
+    pub write_back: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HalfwordKind {
+    UnsignedHalfword,
+    SignedByte,
+    SignedHalfword,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    DataProcessing {
+        opcode: AluOpcode,
+        set_flags: bool,
+        rn: usize,
+        rd: usize,
+        operand: ShifterOperand,
+    },
+    Mrs {
+        use_spsr: bool,
+        rd: usize,
+    },
+    Msr {
+        use_spsr: bool,
+        // One bit per byte of the PSR, from the control field (bit 0) to the flags field (bit 3)
+        field_mask: u32,
+        operand: PsrOperand,
+    },
+    Multiply {
+        accumulate: bool,
+        set_flags: bool,
+        rd: usize,
+        rn: usize,
+        rs: usize,
+        rm: usize,
+    },
+    MultiplyLong {
+        signed: bool,
+        accumulate: bool,
+        set_flags: bool,
+        rd_hi: usize,
+        rd_lo: usize,
+        rs: usize,
+        rm: usize,
+    },
+    SingleSwap {
+        byte: bool,
+        rn: usize,
+        rd: usize,
+        rm: usize,
+    },
+    BranchExchange {
+        rm: usize,
+    },
+    HalfwordTransfer {
+        load: bool,
+        kind: HalfwordKind,
+        rn: usize,
+        rd: usize,
+        indexing: Indexing,
+        offset: TransferOffset,
+    },
+    SingleTransfer {
+        load: bool,
+        byte: bool,
+        rn: usize,
+        rd: usize,
+        indexing: Indexing,
+        offset: TransferOffset,
+    },
+    BlockTransfer {
+        load: bool,
+        psr_force_user: bool,
+        rn: usize,
+        register_list: u16,
+        indexing: Indexing,
+    },
+    Branch {
+        link: bool,
+        // In bytes, relative to r15
+        offset: i32,
+    },
+    CoprocDataTransfer {
+        load: bool,
+        long: bool,
+        cp_num: u32,
+        crd: u32,
+        rn: usize,
+        indexing: Indexing,
+        offset: u32,
+    },
+    CoprocOperation {
+        cp_opcode: u32,
+        cp_num: u32,
+        crd: u32,
+        crn: u32,
+        crm: u32,
+        cp_info: u32,
+    },
+    CoprocRegTransfer {
+        load: bool,
+        cp_opcode: u32,
+        cp_num: u32,
+        rd: usize,
+        crn: u32,
+        crm: u32,
+        cp_info: u32,
+    },
+    SoftwareInterrupt {
+        comment: u32,
+    },
+    Undefined,
+
+    // The first half of a Thumb BL sets lr to r15 plus the upper half of the offset
+    ThumbBranchPrefix {
+        offset: i32,
+    },
+    // The second half branches to lr plus the lower half of the offset
+    ThumbBranchSuffix {
+        offset: u32,
+    },
+}
+
+impl Instruction {
+    // Decodes an ARM instruction, returning it along with the condition it executes under
+    pub fn decode_arm(encoding: u32) -> (Condition, Self) {
+        let condition = Condition::from_u8((encoding >> 28) as u8);
+        (condition, Self::decode_arm_unconditional(encoding))
+    }
+
+    fn decode_arm_unconditional(encoding: u32) -> Self {
+        let bit = |n: u32| (encoding >> n) & 1 == 1;
+        let reg = |n: u32| ((encoding >> n) & 0b1111) as usize;
+        let indexing = Indexing {
+            pre_index: bit(24),
+            up: bit(23),
+            write_back: bit(21),
+        };
+        // TST, TEQ, CMP and CMN without the S bit set encode PSR transfers instead
+        let psr_transfer = (encoding >> 23) & 0b11 == 0b10 && !bit(20);
+
+        match (encoding >> 25) & 0b111 {
+            0b000 if bit(7) && bit(4) => Self::decode_arm_extension(encoding),
+            0b000 if encoding & 0x0FFFFFF0 == 0x012FFF10 => Self::BranchExchange { rm: reg(0) },
+            0b000 if psr_transfer => {
+                // The rest of this space holds ARMv5 additions such as CLZ, BLX and QADD
+                if (encoding >> 4) & 0b1111 != 0b0000 {
+                    Self::Undefined
+                } else if bit(21) {
+                    Self::Msr {
+                        use_spsr: bit(22),
+                        field_mask: (encoding >> 16) & 0b1111,
+                        operand: PsrOperand::Register(reg(0)),
+                    }
+                } else {
+                    Self::Mrs {
+                        use_spsr: bit(22),
+                        rd: reg(12),
+                    }
+                }
+            }
+            0b000 => Self::DataProcessing {
+                opcode: AluOpcode::from_u32(encoding >> 21),
+                set_flags: bit(20),
+                rn: reg(16),
+                rd: reg(12),
+                operand: ShifterOperand::Register {
+                    rm: reg(0),
+                    shift: ShiftType::from_u32(encoding >> 5),
+                    amount: if bit(4) {
+                        ShiftAmount::Register(reg(8))
+                    } else {
+                        ShiftAmount::Immediate((encoding >> 7) & 0b11111)
+                    },
+                },
+            },
+            0b001 if psr_transfer => {
+                if bit(21) {
+                    let (imm, rotate) = (encoding & 0xFF, ((encoding >> 8) & 0b1111) * 2);
+                    Self::Msr {
+                        use_spsr: bit(22),
+                        field_mask: (encoding >> 16) & 0b1111,
+                        operand: PsrOperand::Immediate(imm.rotate_right(rotate)),
+                    }
+                } else {
+                    Self::Undefined
+                }
+            }
+            0b001 => Self::DataProcessing {
+                opcode: AluOpcode::from_u32(encoding >> 21),
+                set_flags: bit(20),
+                rn: reg(16),
+                rd: reg(12),
+                operand: ShifterOperand::Immediate {
+                    imm: encoding & 0xFF,
+                    rotate: ((encoding >> 8) & 0b1111) * 2,
+                },
+            },
+            0b011 if bit(4) => Self::Undefined,
+            0b010 | 0b011 => Self::SingleTransfer {
+                load: bit(20),
+                byte: bit(22),
+                rn: reg(16),
+                rd: reg(12),
+                indexing,
+                offset: if bit(25) {
+                    TransferOffset::Register {
+                        rm: reg(0),
+                        shift: ShiftType::from_u32(encoding >> 5),
+                        amount: (encoding >> 7) & 0b11111,
+                    }
+                } else {
+                    TransferOffset::Immediate(encoding & 0xFFF)
+                },
+            },
+            0b100 => Self::BlockTransfer {
+                load: bit(20),
+                psr_force_user: bit(22),
+                rn: reg(16),
+                register_list: encoding as u16,
+                indexing,
+            },
+            0b101 => Self::Branch {
+                link: bit(24),
+                // Sign-extend the 24-bit word offset and convert it to bytes
+                offset: ((encoding << 8) as i32) >> 6,
+            },
+            0b110 => Self::CoprocDataTransfer {
+                load: bit(20),
+                long: bit(22),
+                cp_num: (encoding >> 8) & 0b1111,
+                crd: (encoding >> 12) & 0b1111,
+                rn: reg(16),
+                indexing,
+                offset: (encoding & 0xFF) << 2,
+            },
+            _ if bit(24) => Self::SoftwareInterrupt {
+                comment: encoding & 0xFFFFFF,
+            },
+            _ if bit(4) => Self::CoprocRegTransfer {
+                load: bit(20),
+                cp_opcode: (encoding >> 21) & 0b111,
+                cp_num: (encoding >> 8) & 0b1111,
+                rd: reg(12),
+                crn: (encoding >> 16) & 0b1111,
+                crm: encoding & 0b1111,
+                cp_info: (encoding >> 5) & 0b111,
+            },
+            _ => Self::CoprocOperation {
+                cp_opcode: (encoding >> 20) & 0b1111,
+                cp_num: (encoding >> 8) & 0b1111,
+                crd: (encoding >> 12) & 0b1111,
+                crn: (encoding >> 16) & 0b1111,
+                crm: encoding & 0b1111,
+                cp_info: (encoding >> 5) & 0b111,
+            },
+        }
+    }
+
+    // Decodes the space of data processing encodings with bits 7 and 4 set, which holds the
+    // multiplies, swaps and halfword transfers
+    fn decode_arm_extension(encoding: u32) -> Self {
+        let bit = |n: u32| (encoding >> n) & 1 == 1;
+        let reg = |n: u32| ((encoding >> n) & 0b1111) as usize;
+
+        let sh = (encoding >> 5) & 0b11;
+        if sh == 0b00 {
+            return match (encoding >> 23) & 0b11 {
+                0b00 if !bit(24) && !bit(22) => Self::Multiply {
+                    accumulate: bit(21),
+                    set_flags: bit(20),
+                    rd: reg(16),
+                    rn: reg(12),
+                    rs: reg(8),
+                    rm: reg(0),
+                },
+                0b01 if !bit(24) => Self::MultiplyLong {
+                    signed: bit(22),
+                    accumulate: bit(21),
+                    set_flags: bit(20),
+                    rd_hi: reg(16),
+                    rd_lo: reg(12),
+                    rs: reg(8),
+                    rm: reg(0),
+                },
+                0b10 if bit(24) && (encoding >> 20) & 0b11 == 0b00 => Self::SingleSwap {
+                    byte: bit(22),
+                    rn: reg(16),
+                    rd: reg(12),
+                    rm: reg(0),
+                },
+                _ => Self::Undefined,
+            };
+        }
+
+        // Signed stores are the ARMv5 doubleword transfers
+        let load = bit(20);
+        if !load && sh != 0b01 {
+            return Self::Undefined;
+        }
+
+        Self::HalfwordTransfer {
+            load,
+            kind: match sh {
+                0b01 => HalfwordKind::UnsignedHalfword,
+                0b10 => HalfwordKind::SignedByte,
+                _ => HalfwordKind::SignedHalfword,
+            },
+            rn: reg(16),
+            rd: reg(12),
+            indexing: Indexing {
+                pre_index: bit(24),
+                up: bit(23),
+                write_back: bit(21),
+            },
+            offset: if bit(22) {
+                TransferOffset::Immediate(((encoding >> 4) & 0xF0) | (encoding & 0xF))
+            } else {
+                TransferOffset::Register {
+                    rm: reg(0),
+                    shift: ShiftType::Lsl,
+                    amount: 0,
+                }
+            },
+        }
+    }
+
+    // Decodes a Thumb instruction, returning it along with the condition it executes under
+    pub fn decode_thumb(encoding: u16) -> (Condition, Self) {
+        let encoding = encoding as u32;
+        let bit = |n: u32| (encoding >> n) & 1 == 1;
+        let low_reg = |n: u32| ((encoding >> n) & 0b111) as usize;
+        let reg_operand = |rm: usize| ShifterOperand::Register {
+            rm,
+            shift: ShiftType::Lsl,
+            amount: ShiftAmount::Immediate(0),
+        };
+        let reg_offset = |rm: usize| TransferOffset::Register {
+            rm,
+            shift: ShiftType::Lsl,
+            amount: 0,
+        };
+        let data_proc =
+            |opcode: AluOpcode, set_flags: bool, rn, rd, operand| Self::DataProcessing {
+                opcode,
+                set_flags,
+                rn,
+                rd,
+                operand,
+            };
+        // Every Thumb load and store with an offset is pre-indexed without write-back
+        let offset_indexing = Indexing {
+            pre_index: true,
+            up: true,
+            write_back: false,
+        };
+        // LDMIA, STMIA and POP
+        let increment_after = Indexing {
+            pre_index: false,
+            up: true,
+            write_back: true,
+        };
+
+        let instruction = match encoding >> 11 {
+            0b00000..=0b00010 => {
+                // Shift by immediate
+                // MOVS <Rd>, <Rm>, <shift> #<immed_5>
+                let operand = ShifterOperand::Register {
+                    rm: low_reg(3),
+                    shift: ShiftType::from_u32(encoding >> 11),
+                    amount: ShiftAmount::Immediate((encoding >> 6) & 0b11111),
+                };
+                data_proc(AluOpcode::Mov, true, 0, low_reg(0), operand)
+            }
+            0b00011 => {
+                // Add/subtract register or 3-bit immediate
+                // ADDS/SUBS <Rd>, <Rn>, <Rm>|#<immed_3>
+                let opcode = if bit(9) {
+                    AluOpcode::Sub
+                } else {
+                    AluOpcode::Add
+                };
+                let operand = if bit(10) {
+                    ShifterOperand::Immediate {
+                        imm: (encoding >> 6) & 0b111,
+                        rotate: 0,
+                    }
+                } else {
+                    reg_operand(low_reg(6))
+                };
+                data_proc(opcode, true, low_reg(3), low_reg(0), operand)
+            }
+            0b00100..=0b00111 => {
+                // Add/subtract/compare/move immediate
+                // MOVS/CMP/ADDS/SUBS <Rd>|<Rn>, #<immed_8>
+                let reg = low_reg(8);
+                let operand = ShifterOperand::Immediate {
+                    imm: encoding & 0xFF,
+                    rotate: 0,
+                };
+                match (encoding >> 11) & 0b11 {
+                    0b00 => data_proc(AluOpcode::Mov, true, 0, reg, operand),
+                    0b01 => data_proc(AluOpcode::Cmp, true, reg, 0, operand),
+                    0b10 => data_proc(AluOpcode::Add, true, reg, reg, operand),
+                    _ => data_proc(AluOpcode::Sub, true, reg, reg, operand),
+                }
+            }
+            0b01000 if !bit(10) => {
+                // Data-processing register
+                let rm_rs = low_reg(3);
+                let rd_rn = low_reg(0);
+                let shift_by_reg = |shift| ShifterOperand::Register {
+                    rm: rd_rn,
+                    shift,
+                    amount: ShiftAmount::Register(rm_rs),
+                };
+                let operand = reg_operand(rm_rs);
+                match (encoding >> 6) & 0b1111 {
+                    0b0000 => data_proc(AluOpcode::And, true, rd_rn, rd_rn, operand),
+                    0b0001 => data_proc(AluOpcode::Eor, true, rd_rn, rd_rn, operand),
+                    0b0010 => {
+                        data_proc(AluOpcode::Mov, true, 0, rd_rn, shift_by_reg(ShiftType::Lsl))
+                    }
+                    0b0011 => {
+                        data_proc(AluOpcode::Mov, true, 0, rd_rn, shift_by_reg(ShiftType::Lsr))
+                    }
+                    0b0100 => {
+                        data_proc(AluOpcode::Mov, true, 0, rd_rn, shift_by_reg(ShiftType::Asr))
+                    }
+                    0b0101 => data_proc(AluOpcode::Adc, true, rd_rn, rd_rn, operand),
+                    0b0110 => data_proc(AluOpcode::Sbc, true, rd_rn, rd_rn, operand),
+                    0b0111 => {
+                        data_proc(AluOpcode::Mov, true, 0, rd_rn, shift_by_reg(ShiftType::Ror))
+                    }
+                    0b1000 => data_proc(AluOpcode::Tst, true, rd_rn, 0, operand),
+                    0b1001 => {
+                        // NEG is RSBS <Rd>, <Rm>, #0
+                        let zero = ShifterOperand::Immediate { imm: 0, rotate: 0 };
+                        data_proc(AluOpcode::Rsb, true, rm_rs, rd_rn, zero)
+                    }
+                    0b1010 => data_proc(AluOpcode::Cmp, true, rd_rn, 0, operand),
+                    0b1011 => data_proc(AluOpcode::Cmn, true, rd_rn, 0, operand),
+                    0b1100 => data_proc(AluOpcode::Orr, true, rd_rn, rd_rn, operand),
+                    0b1101 => Self::Multiply {
+                        // MULS <Rd>, <Rm>, <Rd>
+                        accumulate: false,
+                        set_flags: true,
+                        rd: rd_rn,
+                        rn: 0,
+                        rs: rd_rn,
+                        rm: rm_rs,
+                    },
+                    0b1110 => data_proc(AluOpcode::Bic, true, rd_rn, rd_rn, operand),
+                    _ => data_proc(AluOpcode::Mvn, true, 0, rd_rn, operand),
+                }
+            }
+            0b01000 => {
+                // Special data processing and branch/exchange, which can access the high registers
+                let rm = ((encoding >> 3) & 0b1111) as usize; // (H2 << 3) | Rm
+                let rd_rn = ((bit(7) as usize) << 3) | low_reg(0); // (H1 << 3) | (Rd or Rn)
+                match (encoding >> 8) & 0b11 {
+                    0b00 => data_proc(AluOpcode::Add, false, rd_rn, rd_rn, reg_operand(rm)),
+                    0b01 => data_proc(AluOpcode::Cmp, true, rd_rn, 0, reg_operand(rm)),
+                    0b10 => data_proc(AluOpcode::Mov, false, 0, rd_rn, reg_operand(rm)),
+                    // With H1 set this is the ARMv5 BLX, which behaves as BX on the ARM7TDMI
+                    _ => Self::BranchExchange { rm },
+                }
+            }
+            0b01001 => Self::SingleTransfer {
+                // Load from literal pool
+                // LDR <Rd>, [PC, #<immed_8> * 4]
+                load: true,
+                byte: false,
+                rn: 15,
+                rd: low_reg(8),
+                indexing: offset_indexing,
+                offset: TransferOffset::Immediate((encoding & 0xFF) << 2),
+            },
+            0b01010 | 0b01011 => {
+                // Load/store register offset
+                let (rn, rd, offset) = (low_reg(3), low_reg(0), reg_offset(low_reg(6)));
+                let single = |load, byte| Self::SingleTransfer {
+                    load,
+                    byte,
+                    rn,
+                    rd,
+                    indexing: offset_indexing,
+                    offset,
+                };
+                let halfword = |load, kind| Self::HalfwordTransfer {
+                    load,
+                    kind,
+                    rn,
+                    rd,
+                    indexing: offset_indexing,
+                    offset,
+                };
+                match (encoding >> 9) & 0b111 {
+                    0b000 => single(false, false),                            // STR
+                    0b001 => halfword(false, HalfwordKind::UnsignedHalfword), // STRH
+                    0b010 => single(false, true),                             // STRB
+                    0b011 => halfword(true, HalfwordKind::SignedByte),        // LDRSB
+                    0b100 => single(true, false),                             // LDR
+                    0b101 => halfword(true, HalfwordKind::UnsignedHalfword),  // LDRH
+                    0b110 => single(true, true),                              // LDRB
+                    _ => halfword(true, HalfwordKind::SignedHalfword),        // LDRSH
+                }
+            }
+            0b01100..=0b01111 => {
+                // Load/store word/byte immediate offset
+                let byte = bit(12);
+                let immed_5 = (encoding >> 6) & 0b11111;
+                Self::SingleTransfer {
+                    load: bit(11),
+                    byte,
+                    rn: low_reg(3),
+                    rd: low_reg(0),
+                    indexing: offset_indexing,
+                    offset: TransferOffset::Immediate(if byte { immed_5 } else { immed_5 << 2 }),
+                }
+            }
+            0b10000 | 0b10001 => Self::HalfwordTransfer {
+                // Load/store halfword immediate offset
+                load: bit(11),
+                kind: HalfwordKind::UnsignedHalfword,
+                rn: low_reg(3),
+                rd: low_reg(0),
+                indexing: offset_indexing,
+                offset: TransferOffset::Immediate(((encoding >> 6) & 0b11111) << 1),
+            },
+            0b10010 | 0b10011 => Self::SingleTransfer {
+                // Load/store to/from stack
+                load: bit(11),
+                byte: false,
+                rn: 13,
+                rd: low_reg(8),
+                indexing: offset_indexing,
+                offset: TransferOffset::Immediate((encoding & 0xFF) << 2),
+            },
+            0b10100 | 0b10101 => {
+                // Add to SP or PC
+                // ADD <Rd>, PC|SP, #<immed_8> * 4
+                let rn = if bit(11) { 13 } else { 15 };
+                let operand = ShifterOperand::Immediate {
+                    imm: encoding & 0xFF,
+                    rotate: 30,
+                };
+                data_proc(AluOpcode::Add, false, rn, low_reg(8), operand)
+            }
+            0b10110 | 0b10111 if (encoding >> 8) & 0b1111 == 0b0000 => {
+                // Adjust stack pointer
+                // ADD/SUB SP, SP, #<immed_7> * 4
+                let opcode = if bit(7) {
+                    AluOpcode::Sub
+                } else {
+                    AluOpcode::Add
+                };
+                let operand = ShifterOperand::Immediate {
+                    imm: encoding & 0b1111111,
+                    rotate: 30,
+                };
+                data_proc(opcode, false, 13, 13, operand)
+            }
+            0b10110 | 0b10111 if (encoding >> 9) & 0b11 == 0b10 => {
+                // Push/pop register list, optionally including lr or pc respectively
+                let load = bit(11);
+                let extra_reg = if load { 15 } else { 14 };
+                Self::BlockTransfer {
+                    load,
+                    psr_force_user: false,
+                    rn: 13,
+                    register_list: ((encoding & 0xFF) | ((bit(8) as u32) << extra_reg)) as u16,
+                    indexing: if load {
+                        increment_after
+                    } else {
+                        Indexing {
+                            pre_index: true,
+                            up: false,
+                            write_back: true,
+                        }
+                    },
+                }
+            }
+            0b11000 | 0b11001 => Self::BlockTransfer {
+                // Load/store multiple
+                load: bit(11),
+                psr_force_user: false,
+                rn: low_reg(8),
+                register_list: (encoding & 0xFF) as u16,
+                indexing: increment_after,
+            },
+            0b11010 | 0b11011 => match (encoding >> 8) & 0b1111 {
+                0b1110 => Self::Undefined,
+                0b1111 => Self::SoftwareInterrupt {
+                    comment: encoding & 0xFF,
+                },
+                cond => {
+                    // Conditional branch
+                    let offset = ((encoding as u8) as i8 as i32) << 1;
+                    let instruction = Self::Branch {
+                        link: false,
+                        offset,
+                    };
+                    return (Condition::from_u8(cond as u8), instruction);
+                }
+            },
+            0b11100 => Self::Branch {
+                // Unconditional branch
+                link: false,
+                offset: ((encoding << 21) as i32) >> 20,
+            },
+            0b11110 => Self::ThumbBranchPrefix {
+                offset: ((encoding << 21) as i32) >> 9,
+            },
+            0b11111 => Self::ThumbBranchSuffix {
+                offset: (encoding & 0b11111111111) << 1,
+            },
+            // Includes the ARMv5 BLX suffix and the unused miscellaneous encodings
+            _ => Self::Undefined,
+        };
+
+        (Condition::AL, instruction)
+    }
+}
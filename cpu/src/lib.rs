@@ -1,16 +1,48 @@
+#[macro_use]
+extern crate bitfield;
+
+mod access_type;
+mod cartridge;
 mod condition;
-mod instruction_type;
+mod decode_cache;
+mod disassembler;
+mod halt_mode;
+mod hle_bios;
+mod idle_loop;
+mod instruction;
 mod operating_mode;
+mod prefetch_buffer;
 mod status_register;
+mod tracer;
+mod wait_control_reg;
+mod watchpoint;
+
+pub use crate::access_type::AccessType;
+pub use crate::cartridge::{BackupType, Cartridge, CartridgeError, CartridgeHeader, Peripherals};
+pub use crate::condition::Condition;
+pub use crate::disassembler::{disassemble_arm, disassemble_thumb};
+pub use crate::halt_mode::HaltMode;
+pub use crate::instruction::{
+    AluOpcode, HalfwordKind, Indexing, Instruction, PsrOperand, ShiftAmount, ShiftType,
+    ShifterOperand, TransferOffset,
+};
+pub use crate::operating_mode::OperatingMode;
+pub use crate::tracer::{TraceEntry, TraceFilter, Tracer};
+pub use crate::watchpoint::{WatchKind, Watchpoint, WatchpointHit};
 
 use crate::{
-    condition::Condition, instruction_type::InstructionType, operating_mode::OperatingMode,
+    decode_cache::{DecodeCache, DecodedInstruction},
+    idle_loop::IdleLoopDetector,
+    prefetch_buffer::PrefetchBuffer,
     status_register::StatusRegister,
+    wait_control_reg::WaitControlReg,
 };
 
-use memory::Memory;
+use bitfield::BitRange;
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
 
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
 // https://developer.arm.com/documentation/ddi0210/c/Programmer-s-Model/Exceptions/Exception-vectors
@@ -43,13 +75,55 @@ pub struct CPU {
     und_register_bank: [u32; 2],
 
     memory: Rc<RefCell<dyn Memory>>,
-    bios_rom: Vec<u8>,  // Bios ROM
-    ewram: Vec<u8>,     // External work RAM
-    iwram: Vec<u8>,     // Internal work RAM
-    cart_rom: Vec<u8>,  // Cartridge ROM
-    cart_sram: Vec<u8>, // Cartridge SRAM
-
-    log: bool,
+    bios_rom: Vec<u8>, // Bios ROM
+    // Whether software interrupts are emulated natively instead of running the BIOS ROM
+    hle_bios: bool,
+    // Set while an HLE IntrWait call is waiting for an interrupt
+    hle_intr_waiting: bool,
+    // The most recently fetched BIOS opcode, which reads from the BIOS return when the CPU isn't
+    // executing the BIOS
+    bios_latch: u32,
+    // The most recently fetched opcode, which reads from unmapped memory return
+    last_fetched: u32,
+    // The address of the instruction being executed, which decides whether data reads can see
+    // the BIOS
+    executing: u32,
+
+    halt_mode: Option<HaltMode>,
+    // POSTFLG, set by the BIOS after the first boot
+    post_flag: u8,
+
+    idle_loop: IdleLoopDetector,
+    // The start address of the idle loop closed by the last instruction, if any
+    idle_loop_detected: Option<u32>,
+    ewram: Vec<u8>, // External work RAM
+    iwram: Vec<u8>, // Internal work RAM
+    cartridge: Option<Cartridge>,
+
+    // The instructions in the decode and fetch stages of the 3-stage pipeline, respectively.
+    // While an instruction executes, r15 holds the address being fetched, i.e. the executing
+    // instruction's address plus two instruction widths.
+    pipeline: [u32; 2],
+    // The decoded forms of the pipeline's instructions, for those fetched from cached memory
+    pipeline_decoded: [Option<DecodedInstruction>; 2],
+    // Set whenever r15 is written, so the pipeline is refilled from the new address
+    pipeline_flushed: bool,
+    decode_cache: DecodeCache,
+
+    wait_control_reg: WaitControlReg,
+    prefetch_buffer: PrefetchBuffer,
+    // The cycles taken so far by the current instruction
+    cycles: u32,
+    // Whether the next code fetch directly follows the previous one
+    sequential_fetch: bool,
+    // Set while fetching an opcode, since code fetches don't trigger watchpoints
+    fetching: bool,
+
+    watchpoints: Vec<Watchpoint>,
+    // The first access to trigger a watchpoint since the last call to take_watchpoint_hit
+    watchpoint_hit: Option<WatchpointHit>,
+
+    tracer: Option<Tracer>,
 }
 
 impl CPU {
@@ -70,105 +144,545 @@ impl CPU {
             und_register_bank: [0; 2],
 
             memory,
-            bios_rom: vec![0; 0x4000],
+            bios_rom: hle_bios::image(),
+            hle_bios: true,
+            hle_intr_waiting: false,
+            bios_latch: 0,
+            last_fetched: 0,
+            executing: 0,
+
+            halt_mode: None,
+            post_flag: 0,
+
+            idle_loop: IdleLoopDetector::new(),
+            idle_loop_detected: None,
             ewram: vec![0; 0x40000],
             iwram: vec![0; 0x8000],
-            cart_rom: Vec::new(),
-            cart_sram: vec![0; 0x10000],
+            cartridge: None,
+
+            pipeline: [0; 2],
+            pipeline_decoded: [None; 2],
+            pipeline_flushed: true,
+            decode_cache: DecodeCache::new(),
+
+            wait_control_reg: WaitControlReg(0),
+            prefetch_buffer: PrefetchBuffer::new(),
+            cycles: 0,
+            sequential_fetch: false,
+            fetching: false,
 
-            log: false,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+
+            tracer: None,
         }
     }
 
-    pub fn tick(&mut self) {
-        // TODO: Implement 3-stage pipeline
-        let (pc, encoding, instr_type) = if self.cpsr.get_t() {
-            // Thumb mode
-            // In Thumb mode, only the bottom bit is unused
-            let pc = self.get_register(15) & !0b1;
-            let encoding = self.read_u16(pc as usize);
-            let (instr_type, translated) = InstructionType::from_thumb_encoding(encoding);
-            (pc, translated, instr_type)
-        } else {
-            // ARM mode
-            // In ARM mode, the bottom two bytes of the PC aren't used, so PC selects a word
-            let pc = self.get_register(15) & !0b11;
-            let encoding = self.read_u32(pc as usize);
-            let instr_type = InstructionType::from_encoding(encoding);
-            (pc, encoding, instr_type)
+    // Executes a single instruction, returning the number of cycles it took
+    pub fn tick(&mut self) -> u32 {
+        if self.pipeline_flushed {
+            self.flush_pipeline();
+        }
+
+        // The fetch stage reads the instruction at r15 while the oldest instruction executes
+        let width = self.mode_instr_width();
+        let fetch_addr = self.get_register(15);
+        let (fetched, fetched_decoded) = self.fetch(fetch_addr);
+        let pc = fetch_addr.wrapping_sub(2 * width);
+        self.executing = pc;
+        let thumb = self.cpsr.get_t();
+        let DecodedInstruction {
+            condition,
+            instruction,
+            ..
+        } = match self.pipeline_decoded[0] {
+            Some(decoded) if decoded.thumb == thumb => decoded,
+            _ => DecodedInstruction::decode(self.pipeline[0], thumb),
         };
-        let condition = match instr_type {
-            InstructionType::ThumbBranchPrefix | InstructionType::ThumbBranchSuffix => {
-                Condition::AL
+
+        if self.tracer.is_some() {
+            self.trace(pc);
+        }
+
+        if self.eval_condition(condition) {
+            match instruction {
+                Instruction::DataProcessing {
+                    opcode,
+                    set_flags,
+                    rn,
+                    rd,
+                    operand,
+                } => self.data_proc_instr(opcode, set_flags, rn, rd, operand),
+                Instruction::Mrs { use_spsr, rd } => self.move_psr_into_reg(use_spsr, rd),
+                Instruction::Msr {
+                    use_spsr,
+                    field_mask,
+                    operand,
+                } => self.move_into_psr(use_spsr, field_mask, operand),
+                Instruction::Multiply { .. } | Instruction::MultiplyLong { .. } => {
+                    self.multiply_instr(instruction)
+                }
+                Instruction::BranchExchange { rm } => self.branch_exchange(rm),
+                Instruction::SingleSwap { byte, rn, rd, rm } => {
+                    self.single_swap_instr(byte, rn, rd, rm)
+                }
+                Instruction::HalfwordTransfer {
+                    load,
+                    kind,
+                    rn,
+                    rd,
+                    indexing,
+                    offset,
+                } => self.halfword_transfer_instr(load, kind, rn, rd, indexing, offset),
+                Instruction::SingleTransfer {
+                    load,
+                    byte,
+                    rn,
+                    rd,
+                    indexing,
+                    offset,
+                } => self.single_transfer_instr(load, byte, rn, rd, indexing, offset),
+                Instruction::BlockTransfer {
+                    load,
+                    psr_force_user,
+                    rn,
+                    register_list,
+                    indexing,
+                } => self.block_transfer(load, psr_force_user, rn, register_list, indexing),
+                Instruction::Branch { link, offset } => self.branch_instr(link, offset),
+                Instruction::CoprocDataTransfer { .. } => {}
+                Instruction::CoprocOperation { .. } => {}
+                Instruction::CoprocRegTransfer { .. } => {}
+                Instruction::SoftwareInterrupt { comment } => self.software_interrupt(comment),
+                Instruction::Undefined => self.undefined_interrupt(),
+
+                Instruction::ThumbBranchPrefix { offset } => self.thumb_branch_prefix(offset),
+                Instruction::ThumbBranchSuffix { offset } => self.thumb_branch_suffix(offset),
             }
-            _ => Condition::from_u8(((encoding >> 28) & 0xF) as u8),
+        }
+
+        if self.pipeline_flushed {
+            if let Instruction::Branch { .. } = instruction {
+                let target = self.registers[15];
+                let state = (
+                    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+                        .map(|n| self.get_register(n)),
+                    self.cpsr.raw,
+                );
+                if self.idle_loop.on_branch(pc, target, state) {
+                    self.idle_loop_detected = Some(target);
+                }
+            }
+            self.flush_pipeline();
+        } else {
+            self.pipeline = [self.pipeline[1], fetched];
+            self.pipeline_decoded = [self.pipeline_decoded[1], fetched_decoded];
+            self.registers[15] = fetch_addr.wrapping_add(width);
+        }
+
+        std::mem::take(&mut self.cycles)
+    }
+
+    // Discards the pipeline contents and refills it starting at r15, leaving r15 pointing at the
+    // next fetch address
+    fn flush_pipeline(&mut self) {
+        self.sequential_fetch = false;
+        let width = self.mode_instr_width();
+        let addr = self.get_register(15) & !(width - 1);
+        let (first, second) = (self.fetch(addr), self.fetch(addr.wrapping_add(width)));
+        self.pipeline = [first.0, second.0];
+        self.pipeline_decoded = [first.1, second.1];
+        self.registers[15] = addr.wrapping_add(2 * width);
+        self.pipeline_flushed = false;
+    }
+
+    // Reads the opcode at the given address, along with its decoded form if the address is in
+    // cached memory
+    fn fetch(&mut self, addr: u32) -> (u32, Option<DecodedInstruction>) {
+        let access = if self.sequential_fetch {
+            AccessType::Sequential
+        } else {
+            AccessType::NonSequential
         };
+        self.sequential_fetch = true;
+        let width = self.mode_instr_width();
 
-        if pc >= 0x08000000 {
-            // self.log = true;
-        }
-        // if self.log && pc == 0 {
-        //     std::process::abort();
-        // }
-
-        // if self.log && !(0x0804F670..=0x0804F674).contains(&pc) && (pc / 0x100) != 0x2 {
-        if self.log {
-            print!(
-                "{:08X}: {:08X} {:<19} {:?} {:08X} {:08X?}",
-                pc,
-                encoding,
-                format!("{:?}", instr_type),
-                condition,
-                self.cpsr.raw,
-                (0..16).map(|i| self.get_register(i)).collect::<Vec<u32>>()
-            );
-            if self.cpsr.get_t() {
-                println!(" THUMB({:04X})", self.read_u16(pc as usize));
+        if Self::is_cart_rom(addr as usize) && self.wait_control_reg.prefetch() {
+            let halfword_cycles = self.rom_halfword_cycles(addr);
+            if let Some(cycles) = self.prefetch_buffer.take(addr, width / 2, halfword_cycles) {
+                self.cycles += cycles;
             } else {
-                println!();
+                self.cycles += self.access_cycles(addr as usize, width, access);
+                self.prefetch_buffer.restart(addr.wrapping_add(width));
             }
+        } else {
+            let cycles = self.access_cycles(addr as usize, width, access);
+            self.add_bus_cycles(addr as usize, cycles);
         }
 
-        self.set_register(
-            15,
-            self.get_register(15).wrapping_add(self.mode_instr_width()),
-        );
+        let thumb = self.cpsr.get_t();
+        let addr = addr & !(width - 1);
+        let location = self.code_location(addr as usize);
+        let cached = location
+            .and_then(|location| self.decode_cache.get(location))
+            .filter(|decoded| decoded.thumb == thumb);
+        let (opcode, decoded) = match cached {
+            Some(decoded) => (decoded.opcode, Some(decoded)),
+            None => {
+                self.fetching = true;
+                let opcode = if thumb {
+                    self.read_u16(addr as usize) as u32
+                } else {
+                    self.read_u32(addr as usize)
+                };
+                self.fetching = false;
+                let decoded = location.map(|location| {
+                    let decoded = DecodedInstruction::decode(opcode, thumb);
+                    self.decode_cache.insert(location, decoded);
+                    decoded
+                });
+                (opcode, decoded)
+            }
+        };
 
-        if self.eval_condition(condition) {
-            match instr_type {
-                InstructionType::Multiply | InstructionType::MultiplyLong => {
-                    self.multiply_instr(encoding)
+        self.last_fetched = opcode;
+        if addr < 0x4000 {
+            self.bios_latch = opcode;
+        }
+        (opcode, decoded)
+    }
+
+    // The location in the decode cache of the instruction at the given address, if it's in memory
+    // that can only change through writes the CPU sees
+    fn code_location(&self, addr: usize) -> Option<usize> {
+        match addr {
+            // Code fetched from the BIOS is being executed, so can always see it
+            0x00000000..=0x00003FFF => Some(DecodeCache::BIOS_BASE + addr),
+            0x03000000..=0x03FFFFFF => Some(DecodeCache::IWRAM_BASE + (addr - 0x03000000) % 0x8000),
+            0x08000000..=0x0DFFFFFF => {
+                let offset = self.cartridge.as_ref()?.rom_offset(addr)?;
+                Some(DecodeCache::ROM_BASE + offset)
+            }
+            _ => None,
+        }
+    }
+
+    // The value on the bus when reading unmapped memory, which is the last opcode fetched. In
+    // Thumb mode, the fetched halfword appears in both halves of the word.
+    fn open_bus(&self, addr: usize) -> u8 {
+        let word = if self.cpsr.get_t() {
+            (self.last_fetched << 16) | (self.last_fetched & 0xFFFF)
+        } else {
+            self.last_fetched
+        };
+        (word >> ((addr & 0b11) * 8)) as u8
+    }
+
+    // The number of cycles taken by a `width`-byte access to the given address
+    pub fn access_cycles(&self, addr: usize, width: u32, access: AccessType) -> u32 {
+        match addr {
+            // External work RAM has a 16-bit bus with 2 wait states
+            0x02000000..=0x02FFFFFF => {
+                if width == 4 {
+                    6
+                } else {
+                    3
                 }
-                InstructionType::BranchExchange => self.branch_exchange(encoding),
-                InstructionType::SingleSwap => self.single_swap_instr(encoding),
-                InstructionType::HalfwordTransferReg | InstructionType::HalfwordTransferImm => {
-                    self.halfword_transfer_instr(encoding)
+            }
+            // Palette RAM and VRAM have 16-bit buses
+            0x05000000..=0x06FFFFFF => {
+                if width == 4 {
+                    2
+                } else {
+                    1
+                }
+            }
+            // Cartridge ROM has a 16-bit bus, so a 32-bit access is followed by a sequential one
+            0x08000000..=0x0DFFFFFF => {
+                let wait_state = ((addr - 0x08000000) >> 25) as u32;
+                let first = match access {
+                    AccessType::NonSequential => {
+                        self.wait_control_reg.first_access_cycles(wait_state)
+                    }
+                    AccessType::Sequential => {
+                        self.wait_control_reg.second_access_cycles(wait_state)
+                    }
+                };
+                if width == 4 {
+                    first + self.wait_control_reg.second_access_cycles(wait_state)
+                } else {
+                    first
                 }
-                InstructionType::SingleTransfer => self.single_transfer_instr(encoding),
-                InstructionType::DataProcessing => self.data_proc_instr(encoding),
-                InstructionType::Undefined => self.undefined_interrupt(),
-                InstructionType::BlockTransfer => self.block_transfer(encoding),
-                InstructionType::Branch => self.branch_instr(encoding),
-                InstructionType::CoprocDataTransfer => {}
-                InstructionType::CoprocOperation => {}
-                InstructionType::CoprocRegOperation => {}
-                InstructionType::SoftwareInterrupt => self.software_interrupt(),
-
-                InstructionType::ThumbBranchPrefix => self.thumb_branch_prefix(encoding as u16),
-                InstructionType::ThumbBranchSuffix => self.thumb_branch_suffix(encoding as u16),
             }
+            0x0E000000..=0x0FFFFFFF => self.wait_control_reg.sram_access_cycles(),
+            _ => 1,
+        }
+    }
+
+    // Counts the cycles of a data access made by the current instruction. Code fetches
+    // following a data access are non-sequential.
+    fn add_data_cycles(&mut self, addr: usize, width: u32, access: AccessType) {
+        let cycles = self.access_cycles(addr, width, access);
+        self.add_bus_cycles(addr, cycles);
+        self.sequential_fetch = false;
+    }
+
+    fn add_internal_cycles(&mut self, n: u32) {
+        self.cycles += n;
+        self.advance_prefetch(n);
+    }
+
+    // Counts the cycles of an access that isn't served by the prefetch buffer. Accesses to
+    // cartridge ROM interrupt the prefetcher, while other accesses leave the cartridge bus free.
+    fn add_bus_cycles(&mut self, addr: usize, cycles: u32) {
+        self.cycles += cycles;
+        if Self::is_cart_rom(addr) {
+            self.prefetch_buffer.stop();
+        } else {
+            self.advance_prefetch(cycles);
+        }
+    }
+
+    fn advance_prefetch(&mut self, cycles: u32) {
+        if self.wait_control_reg.prefetch() {
+            let halfword_cycles = self.rom_halfword_cycles(self.prefetch_buffer.head());
+            self.prefetch_buffer.advance(cycles, halfword_cycles);
+        }
+    }
+
+    // The number of cycles the prefetcher takes to read a halfword of cartridge ROM
+    fn rom_halfword_cycles(&self, addr: u32) -> u32 {
+        let wait_state = (addr.wrapping_sub(0x08000000) >> 25) % 3;
+        self.wait_control_reg.second_access_cycles(wait_state)
+    }
+
+    fn is_cart_rom(addr: usize) -> bool {
+        (0x08000000..=0x0DFFFFFF).contains(&addr)
+    }
+
+    // The address of the next instruction to be executed
+    pub fn pc(&self) -> u32 {
+        let width = self.mode_instr_width();
+        if self.pipeline_flushed {
+            // r15 was just written, and holds the branch target
+            self.registers[15] & !(width - 1)
+        } else {
+            self.registers[15].wrapping_sub(2 * width)
+        }
+    }
+
+    // The value of a register in the current mode, where r15 is the address of the next
+    // instruction rather than the fetch address
+    pub fn register(&self, n: usize) -> u32 {
+        if n == 15 {
+            self.pc()
+        } else {
+            self.get_register(n)
+        }
+    }
+
+    pub fn cpsr(&self) -> u32 {
+        self.cpsr.raw
+    }
+
+    // Writes a register in the current mode. Writing r15 sets the address of the next
+    // instruction, refilling the pipeline from there.
+    pub fn write_register(&mut self, n: usize, value: u32) {
+        if n == 15 {
+            self.restart_at(value);
+        } else {
+            self.set_register(n, value);
+        }
+    }
+
+    // Replaces the CPSR, ignoring values with an invalid mode. Changing between ARM and Thumb
+    // restarts execution at the same address in the new state.
+    pub fn write_cpsr(&mut self, value: u32) {
+        if OperatingMode::from_u32(value & 0b11111).is_some() {
+            let pc = self.pc();
+            self.cpsr.raw = value;
+            self.restart_at(pc);
+        }
+    }
+
+    // The SPSR of the given mode, or None for user and system mode, which don't have one
+    pub fn spsr(&self, mode: OperatingMode) -> Option<u32> {
+        match mode {
+            OperatingMode::FastInterrupt => Some(self.fiq_spsr.raw),
+            OperatingMode::Supervisor => Some(self.svc_spsr.raw),
+            OperatingMode::Abort => Some(self.abt_spsr.raw),
+            OperatingMode::Interrupt => Some(self.irq_spsr.raw),
+            OperatingMode::Undefined => Some(self.und_spsr.raw),
+            OperatingMode::User | OperatingMode::System => None,
+        }
+    }
+
+    pub fn set_spsr(&mut self, mode: OperatingMode, value: u32) {
+        if let Some(spsr) = self.banked_spsr(mode) {
+            spsr.raw = value;
+        }
+    }
+
+    // Passes the state before the instruction at the given address executes to the tracer, if the
+    // instruction passes its filter
+    fn trace(&mut self, pc: u32) {
+        let mode = self.cpsr.get_mode();
+        match &self.tracer {
+            Some(tracer) if tracer.filter().matches(pc, mode) => {}
+            _ => return,
+        }
+
+        let mut registers = [0; 16];
+        for (n, register) in registers.iter_mut().enumerate() {
+            *register = self.get_register(n);
+        }
+        let entry = TraceEntry {
+            registers,
+            cpsr: self.cpsr.raw,
+            opcode: self.pipeline[0],
+            next: self.pipeline[1] as u16,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(entry);
+        }
+    }
+
+    // Flushes the pipeline so that execution continues from the given address
+    fn restart_at(&mut self, pc: u32) {
+        self.registers[15] = pc & !(self.mode_instr_width() - 1);
+        self.pipeline_flushed = true;
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.watchpoints = watchpoints;
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    // Returns the first access to trigger a watchpoint since the last call, if any
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    fn check_watchpoints(&mut self, addr: usize, write: bool, value: u8) {
+        if self.fetching || self.watchpoint_hit.is_some() {
+            return;
+        }
+
+        let addr = addr as u32;
+        if self.watchpoints.iter().any(|w| w.matches(addr, write)) {
+            self.watchpoint_hit = Some(WatchpointHit { addr, write, value });
         }
     }
 
     pub fn flash_bios(&mut self, data: Vec<u8>) {
         self.bios_rom = vec![0; 0x4000];
         self.bios_rom[..data.len()].clone_from_slice(&data);
+        self.hle_bios = false;
+        self.decode_cache.clear();
+    }
+
+    // Puts the CPU in the state the BIOS leaves it in after booting, about to run the cartridge
+    pub fn skip_bios(&mut self) {
+        self.registers = [0; 16];
+        self.registers[13] = 0x03007F00;
+        self.fiq_register_bank = [0; 7];
+        self.svc_register_bank = [0x03007FE0, 0];
+        self.irq_register_bank = [0x03007FA0, 0];
+        self.abt_register_bank = [0; 2];
+        self.und_register_bank = [0; 2];
+        self.cpsr.raw = OperatingMode::System as u32;
+        self.set_register(15, 0x08000000);
+        self.prefetch_buffer.stop();
+        // The last opcode the BIOS fetches before jumping to the cartridge
+        self.bios_latch = 0xE129F000;
+        self.executing = 0x08000000;
+
+        // The IO registers the BIOS changes from their power-on values
+        self.write_u16(0x04000088, 0x200); // SOUNDBIAS
+        self.write(0x04000300, 1); // POSTFLG
+    }
+
+    // The low-power mode the CPU is in, if it's been halted by a write to HALTCNT
+    pub fn halt_mode(&self) -> Option<HaltMode> {
+        self.halt_mode
+    }
+
+    // Resumes execution after a halt, once an interrupt that ends the low-power mode is requested
+    pub fn wake(&mut self) {
+        self.halt_mode = None;
+    }
+
+    pub fn set_idle_loop_detection(&mut self, enabled: bool) {
+        self.idle_loop.set_enabled(enabled);
     }
 
-    pub fn flash_cart(&mut self, data: Vec<u8>) {
-        // self.cart_rom = vec![0; data.len()];
-        self.cart_rom = vec![0; 0x800000 * 0xC];
-        self.cart_rom[..data.len()].clone_from_slice(&data);
+    // Returns the start address of the idle loop closed by the last instruction, if any. Until an
+    // event changes the memory the loop reads, executing it further has no effect.
+    pub fn take_idle_loop(&mut self) -> Option<u32> {
+        self.idle_loop_detected.take()
+    }
+
+    // Called when an event is handled. Memory the current iteration of a loop has already read
+    // may have changed, so the iteration doesn't count towards detecting an idle loop.
+    pub fn on_event(&mut self) {
+        self.idle_loop.on_side_effect();
+    }
+
+    // Replaces the BIOS with the built-in high-level emulation, which is the default
+    pub fn use_hle_bios(&mut self) {
+        self.bios_rom = hle_bios::image();
+        self.hle_bios = true;
+        self.decode_cache.clear();
+    }
+
+    // Inserts a cartridge, returning the previously inserted one (if any)
+    pub fn insert_cartridge(&mut self, cartridge: Cartridge) -> Option<Cartridge> {
+        self.prefetch_buffer.stop();
+        self.decode_cache.clear();
+        self.cartridge.replace(cartridge)
+    }
+
+    pub fn eject_cartridge(&mut self) -> Option<Cartridge> {
+        self.prefetch_buffer.stop();
+        self.decode_cache.clear();
+        self.cartridge.take()
+    }
+
+    // Replaces the halfwords read from the cartridge ROM at the given addresses, or undoes the
+    // patches if empty
+    pub fn set_rom_patches(&mut self, patches: &BTreeMap<u32, u16>) {
+        if let Some(cartridge) = &mut self.cartridge {
+            let patches = patches
+                .iter()
+                .map(|(&addr, &value)| {
+                    let offset = (addr as usize).wrapping_sub(0x08000000) % Cartridge::MAX_ROM_SIZE;
+                    (offset, value)
+                })
+                .collect();
+            cartridge.set_rom_patches(patches);
+            self.decode_cache.clear();
+        }
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    pub fn on_dma3_transfer(&mut self, dest: usize, n_units: u32) {
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.on_dma3_transfer(dest, n_units);
+        }
     }
 
     fn eval_condition(&self, condition: Condition) -> bool {
@@ -193,7 +707,15 @@ impl CPU {
     }
 
     fn get_register(&self, n: usize) -> u32 {
-        let mode = self.cpsr.get_mode();
+        self.banked_register(self.cpsr.get_mode(), n)
+    }
+
+    fn set_register(&mut self, n: usize, val: u32) {
+        self.set_banked_register(self.cpsr.get_mode(), n, val)
+    }
+
+    // A register as seen from the given mode, which may not be the current one
+    pub fn banked_register(&self, mode: OperatingMode, n: usize) -> u32 {
         if n == 13 || n == 14 {
             match mode {
                 OperatingMode::User | OperatingMode::System => self.registers[n],
@@ -203,15 +725,17 @@ impl CPU {
                 OperatingMode::Abort => self.abt_register_bank[n - 13],
                 OperatingMode::Undefined => self.und_register_bank[n - 13],
             }
-        } else if mode == OperatingMode::FastInterrupt && n >= 8 && n <= 14 {
+        } else if mode == OperatingMode::FastInterrupt && (8..=14).contains(&n) {
             self.fiq_register_bank[n - 8]
         } else {
             self.registers[n]
         }
     }
 
-    fn set_register(&mut self, n: usize, val: u32) {
-        let mode = self.cpsr.get_mode();
+    pub fn set_banked_register(&mut self, mode: OperatingMode, n: usize, val: u32) {
+        if n == 15 {
+            self.pipeline_flushed = true;
+        }
         if n == 13 || n == 14 {
             match mode {
                 OperatingMode::User | OperatingMode::System => self.registers[n] = val,
@@ -221,7 +745,7 @@ impl CPU {
                 OperatingMode::Abort => self.abt_register_bank[n - 13] = val,
                 OperatingMode::Undefined => self.und_register_bank[n - 13] = val,
             }
-        } else if mode == OperatingMode::FastInterrupt && n >= 8 && n <= 14 {
+        } else if mode == OperatingMode::FastInterrupt && (8..=14).contains(&n) {
             self.fiq_register_bank[n - 8] = val
         } else {
             self.registers[n] = val
@@ -229,7 +753,11 @@ impl CPU {
     }
 
     fn get_mode_spsr(&mut self) -> Option<&mut StatusRegister> {
-        match self.cpsr.get_mode() {
+        self.banked_spsr(self.cpsr.get_mode())
+    }
+
+    fn banked_spsr(&mut self, mode: OperatingMode) -> Option<&mut StatusRegister> {
+        match mode {
             OperatingMode::FastInterrupt => Some(&mut self.fiq_spsr),
             OperatingMode::Supervisor => Some(&mut self.svc_spsr),
             OperatingMode::Abort => Some(&mut self.abt_spsr),
@@ -239,28 +767,63 @@ impl CPU {
         }
     }
 
-    fn multiply_instr(&mut self, encoding: u32) {
-        let long_flag = (encoding >> 23) & 1 == 1; // Output to two registers, allowing 64 bits
-        let signed_flag = (encoding >> 22) & 1 == 1; // Only used for long multiplies
-        let accumulate_flag = (encoding >> 21) & 1 == 1; // Allows a value to be added to the product
-        let set_cond_flag = (encoding >> 20) & 1 == 1; // Updates zero and negative CPSR flags
+    fn multiply_instr(&mut self, instruction: Instruction) {
+        // Long multiplies output to two registers, allowing 64 bits, and may be signed. Short
+        // multiplies write to the "hi" register and accumulate from the "lo" register.
+        let (
+            long_flag,
+            signed_flag,
+            accumulate_flag,
+            set_cond_flag,
+            other_reg_hi_n,
+            other_reg_lo_n,
+            rs,
+            rm,
+        ) = match instruction {
+            Instruction::Multiply {
+                accumulate,
+                set_flags,
+                rd,
+                rn,
+                rs,
+                rm,
+            } => (false, false, accumulate, set_flags, rd, rn, rs, rm),
+            Instruction::MultiplyLong {
+                signed,
+                accumulate,
+                set_flags,
+                rd_hi,
+                rd_lo,
+                rs,
+                rm,
+            } => (true, signed, accumulate, set_flags, rd_hi, rd_lo, rs, rm),
+            _ => unreachable!("multiply_instr called with {:?}", instruction),
+        };
+
+        let op1 = self.get_register(rs);
+        let op2 = self.get_register(rm);
+
+        // The multiplier array takes 1-4 cycles depending on how many of the top bytes of the
+        // multiplier are all zeroes (or, for signed multiplies, all ones)
+        let multiplier_cycles = (1..4)
+            .find(|n| {
+                let top_bits = op1 >> (8 * n);
+                top_bits == 0 || ((!long_flag || signed_flag) && top_bits == (!0 >> (8 * n)))
+            })
+            .unwrap_or(4);
+        self.add_internal_cycles(multiplier_cycles + long_flag as u32 + accumulate_flag as u32);
 
-        let op1 = self.get_register(((encoding >> 8) & 0b1111) as usize);
-        let op2 = self.get_register((encoding & 0b1111) as usize);
         let product = if long_flag && signed_flag {
             ((op1 as i32) as i64 * (op2 as i32) as i64) as u64
         } else {
             op1 as u64 * op2 as u64
         };
 
-        let other_reg_hi_n = ((encoding >> 16) & 0b1111) as usize;
-        let other_reg_lo_n = ((encoding >> 12) & 0b1111) as usize;
-
         let addend = if long_flag {
             ((self.get_register(other_reg_hi_n) as u64) << 32)
                 | self.get_register(other_reg_lo_n) as u64
         } else {
-            self.get_register(other_reg_lo_n as usize) as u64
+            self.get_register(other_reg_lo_n) as u64
         };
 
         // Write results and optionally set condition flags
@@ -279,13 +842,11 @@ impl CPU {
         }
     }
 
-    fn branch_exchange(&mut self, encoding: u32) {
+    fn branch_exchange(&mut self, reg_n: usize) {
         let val = {
-            let reg_n = (encoding & 0b1111) as usize;
             let mut addr = self.get_register(reg_n);
             if reg_n == 15 {
                 addr &= if self.cpsr.get_t() { !0b1 } else { !0b11 };
-                addr = addr.wrapping_add(self.mode_instr_width());
             }
             addr
         };
@@ -293,16 +854,21 @@ impl CPU {
         self.cpsr.set_t(val & 1 == 1); // Set Thumb bit based on LSB
     }
 
-    fn single_swap_instr(&mut self, encoding: u32) {
-        let byte_flag = (encoding >> 22) & 1 == 1;
-
-        let base_reg_n = ((encoding >> 16) & 0b1111) as usize;
+    fn single_swap_instr(
+        &mut self,
+        byte_flag: bool,
+        base_reg_n: usize,
+        dest_reg_n: usize,
+        source_reg_n: usize,
+    ) {
         let swap_addr = self.get_register(base_reg_n) as usize;
-
-        let dest_reg_n = ((encoding >> 12) & 0b1111) as usize;
-        let source_reg_n = (encoding & 0b1111) as usize;
         let source_reg = self.get_register(source_reg_n);
 
+        let width = if byte_flag { 1 } else { 4 };
+        self.add_data_cycles(swap_addr, width, AccessType::NonSequential);
+        self.add_data_cycles(swap_addr, width, AccessType::NonSequential);
+        self.add_internal_cycles(1);
+
         if byte_flag {
             let old_data = self.read(swap_addr) as u32;
             self.write(swap_addr, (source_reg & 0xFF) as u8);
@@ -317,28 +883,29 @@ impl CPU {
         };
     }
 
-    fn halfword_transfer_instr(&mut self, encoding: u32) {
-        let pre_index_flag = (encoding >> 24) & 1 == 1;
-        let up_flag = (encoding >> 23) & 1 == 1;
-        let write_back_flag = (encoding >> 21) & 1 == 1;
-        let load_flag = (encoding >> 20) & 1 == 1;
+    fn halfword_transfer_instr(
+        &mut self,
+        load_flag: bool,
+        kind: HalfwordKind,
+        base_reg_n: usize,
+        source_dest_reg_n: usize,
+        indexing: Indexing,
+        offset: TransferOffset,
+    ) {
+        let Indexing {
+            pre_index: pre_index_flag,
+            up: up_flag,
+            write_back: write_back_flag,
+        } = indexing;
 
-        let base_reg_n = ((encoding >> 16) & 0b1111) as usize;
         let base_reg = {
             let mut val = self.get_register(base_reg_n);
             if base_reg_n == 15 {
-                val = val.wrapping_add(self.mode_instr_width());
                 val &= !0b11;
             }
             val
         };
-        let source_dest_reg_n = ((encoding >> 12) & 0b1111) as usize;
-
-        let offset = if (encoding >> 22) & 1 == 1 {
-            ((encoding & 0xF00) >> 4) | (encoding & 0xF)
-        } else {
-            self.get_register((encoding & 0b1111) as usize)
-        };
+        let offset = self.transfer_offset(offset);
 
         let offset_addr = if up_flag {
             base_reg.wrapping_add(offset)
@@ -352,41 +919,44 @@ impl CPU {
             base_reg
         } as usize;
 
+        let width = if kind == HalfwordKind::SignedByte {
+            1
+        } else {
+            2
+        };
+        self.add_data_cycles(transfer_addr, width, AccessType::NonSequential);
+
         // TODO: Handle endianness
         if load_flag {
-            let data = match (encoding >> 5) & 0b11 {
-                0b01 => {
+            self.add_internal_cycles(1);
+            let data = match kind {
+                HalfwordKind::UnsignedHalfword => {
                     let mut val = self.read_u16(transfer_addr & !0b1) as u32; // Unsigned halfword
                     if transfer_addr & 0b1 != 0b0 {
                         val = val.rotate_right(8);
                     }
                     val
                 }
-                0b10 => ((self.read(transfer_addr) as i8) as i32) as u32, // Signed byte
-                0b11 => {
+                HalfwordKind::SignedByte => ((self.read(transfer_addr) as i8) as i32) as u32,
+                HalfwordKind::SignedHalfword => {
                     let mut val = (self.read_u16(transfer_addr & !0b1) as i16) as i32; // Signed halfword
                     if transfer_addr & 0b1 != 0b0 {
                         val = val >> 8;
                     }
                     val as u32
                 }
-                0b00 | _ => panic!("SWP format encountered in halfword transfer instruction"),
             };
             self.set_register(source_dest_reg_n, data);
         } else {
             let data = {
                 let mut val = self.get_register(source_dest_reg_n);
                 if source_dest_reg_n == 15 {
-                    val = val.wrapping_add(self.mode_instr_width());
                     val &= !0b11;
                 }
                 val
             };
-            match (encoding >> 5) & 0b11 {
-                0b01 => self.write_u16(transfer_addr & !0b1, (data & 0xFFFF) as u16), // Unsigned halfword
-                0b10 | 0b11 => panic!("signed transfers used with store instructions"),
-                0b00 | _ => panic!("SWP format encountered in halfword transfer instruction"),
-            }
+            // Signed stores decode as undefined instructions, so this is always STRH
+            self.write_u16(transfer_addr & !0b1, (data & 0xFFFF) as u16);
         }
 
         // Post-indexing always writes back
@@ -398,30 +968,29 @@ impl CPU {
         }
     }
 
-    fn single_transfer_instr(&mut self, encoding: u32) {
-        let reg_offset_flag = (encoding >> 25) & 1 == 1;
-        let pre_index_flag = (encoding >> 24) & 1 == 1;
-        let up_flag = (encoding >> 23) & 1 == 1;
-        let byte_flag = (encoding >> 22) & 1 == 1;
-        let write_back_flag = (encoding >> 21) & 1 == 1;
-        let load_flag = (encoding >> 20) & 1 == 1;
+    fn single_transfer_instr(
+        &mut self,
+        load_flag: bool,
+        byte_flag: bool,
+        base_reg_n: usize,
+        source_dest_reg_n: usize,
+        indexing: Indexing,
+        offset: TransferOffset,
+    ) {
+        let Indexing {
+            pre_index: pre_index_flag,
+            up: up_flag,
+            write_back: write_back_flag,
+        } = indexing;
 
-        let base_reg_n = ((encoding >> 16) & 0b1111) as usize;
         let base_reg = {
             let mut val = self.get_register(base_reg_n);
             if base_reg_n == 15 {
-                val = val.wrapping_add(self.mode_instr_width());
                 val &= !0b11;
             }
             val
         };
-        let source_dest_reg_n = ((encoding >> 12) & 0b1111) as usize;
-
-        let offset = if reg_offset_flag {
-            self.shifted_reg_operand(encoding & 0xFFF, false).0
-        } else {
-            encoding & 0xFFF
-        };
+        let offset = self.transfer_offset(offset);
 
         let offset_addr = if up_flag {
             base_reg.wrapping_add(offset)
@@ -435,8 +1004,12 @@ impl CPU {
             base_reg
         } as usize;
 
+        let width = if byte_flag { 1 } else { 4 };
+        self.add_data_cycles(transfer_addr, width, AccessType::NonSequential);
+
         // TODO: Handle endianness
         if load_flag {
+            self.add_internal_cycles(1);
             let data = if byte_flag {
                 self.read(transfer_addr) as u32
             } else {
@@ -451,8 +1024,7 @@ impl CPU {
             let data = {
                 let mut val = self.get_register(source_dest_reg_n);
                 if source_dest_reg_n == 15 {
-                    val = val.wrapping_add(2 * self.mode_instr_width());
-                    // val = val.wrapping_add(self.mode_instr_width());
+                    val = val.wrapping_add(self.mode_instr_width());
                     val &= !0b11;
                     // val &= if self.cpsr.get_t() { !0b1 } else { !0b11 };
                 }
@@ -474,33 +1046,29 @@ impl CPU {
         }
     }
 
-    fn data_proc_instr(&mut self, encoding: u32) {
-        let imm_flag = (encoding >> 25) & 1 == 1;
-        let set_cond_flag = (encoding >> 20) & 1 == 1;
-        let opcode = (encoding >> 21) & 0b1111;
-
-        let dest_reg_n = ((encoding >> 12) & 0b1111) as usize;
-
-        // PSR instructions are special cases of this encoding
-        if !set_cond_flag {
-            let use_spsr_flag = (encoding >> 22) & 1 == 1;
-            if (opcode | 0b0010) == 0b1010 && !imm_flag {
-                self.move_psr_into_reg(use_spsr_flag, dest_reg_n);
-                return;
-            } else if (opcode | 0b0010) == 0b1011 {
-                self.move_into_psr(use_spsr_flag, imm_flag, encoding);
-                return;
+    fn data_proc_instr(
+        &mut self,
+        opcode: AluOpcode,
+        set_cond_flag: bool,
+        op1_reg_n: usize,
+        dest_reg_n: usize,
+        operand: ShifterOperand,
+    ) {
+        let shift_by_reg = matches!(
+            operand,
+            ShifterOperand::Register {
+                amount: ShiftAmount::Register(_),
+                ..
             }
-        };
+        );
 
-        let op1_reg_n = ((encoding >> 16) & 0b1111) as usize;
         let op1_reg = {
             let mut val = self.get_register(op1_reg_n);
             if op1_reg_n == 15 {
-                let shift_reg = (encoding >> 4) & 1 == 1;
-                val = val.wrapping_add(
-                    self.mode_instr_width() * if !imm_flag && shift_reg { 2 } else { 1 },
-                );
+                // Shifting by a register takes an extra cycle, during which r15 advances
+                if shift_by_reg {
+                    val = val.wrapping_add(self.mode_instr_width());
+                }
                 val &= !0b11;
             }
             val
@@ -508,12 +1076,16 @@ impl CPU {
 
         // http://vision.gel.ulaval.ca/~jflalonde/cours/1001/h17/docs/arm-instructionset.pdf pages 4-12 through 4-15
         // TODO: PC is supposed to produce lots of special cases
-        let (op2, mut shifter_carry) = if imm_flag {
-            self.rotated_imm_operand(encoding & 0xFFF)
-        } else {
+        if shift_by_reg {
+            // Shifting by a register takes an internal cycle
+            self.add_internal_cycles(1);
+        }
+        let (op2, mut shifter_carry) = match operand {
+            ShifterOperand::Immediate { imm, rotate } => self.rotated_imm_operand(imm, rotate),
             // TODO: Investigate pulling from R15 when in Thumb mode (maybe should be +2?)
-            let (a, b) = self.shifted_reg_operand(encoding & 0xFFF, true);
-            (a, b)
+            ShifterOperand::Register { rm, shift, amount } => {
+                self.shifted_reg_operand(rm, shift, amount)
+            }
         };
 
         // TODO: Refactor all of this overflow and carry code. It can be done simply for all
@@ -542,56 +1114,64 @@ impl CPU {
 
         let carry = self.cpsr.get_c() as u32;
         let (result, overflow, write_result) = match opcode {
-            0b0000 => (op1_reg & op2, None, true), // AND
-            0b0001 => (op1_reg ^ op2, None, true), // EOR
-            0b0010 => check_overflow_sub(op1_reg.wrapping_sub(op2), true, false), // SUB
-            0b0011 => check_overflow_sub(op2.wrapping_sub(op1_reg), true, true), // RSB
-            0b0100 => check_overflow(op1_reg.wrapping_add(op2), true), // ADD
-            0b0101 => check_overflow(op1_reg.wrapping_add(op2).wrapping_add(carry), true), // ADC
-            0b0110 => check_overflow_sub(
+            AluOpcode::And => (op1_reg & op2, None, true),
+            AluOpcode::Eor => (op1_reg ^ op2, None, true),
+            AluOpcode::Sub => check_overflow_sub(op1_reg.wrapping_sub(op2), true, false),
+            AluOpcode::Rsb => check_overflow_sub(op2.wrapping_sub(op1_reg), true, true),
+            AluOpcode::Add => check_overflow(op1_reg.wrapping_add(op2), true),
+            AluOpcode::Adc => check_overflow(op1_reg.wrapping_add(op2).wrapping_add(carry), true),
+            AluOpcode::Sbc => check_overflow_sub(
                 op1_reg
                     .wrapping_sub(op2)
                     .wrapping_add(carry)
                     .wrapping_sub(1),
                 true,
                 false,
-            ), // SBC
-            0b0111 => check_overflow_sub(
+            ),
+            AluOpcode::Rsc => check_overflow_sub(
                 op2.wrapping_sub(op1_reg)
                     .wrapping_add(carry)
                     .wrapping_sub(1),
                 true,
                 true,
-            ), // RSC
-            0b1000 => (op1_reg & op2, None, false), // TST
-            0b1001 => (op1_reg ^ op2, None, false), // TEQ
-            0b1010 => check_overflow_sub(op1_reg.wrapping_sub(op2), false, false), // CMP
-            0b1011 => check_overflow(op1_reg.wrapping_add(op2), false), // CMN
-            0b1100 => (op1_reg | op2, None, true), // OOR
-            0b1101 => (op2, None, true),           // MOV
-            0b1110 => (op1_reg & !op2, None, true), // BIC
-            0b1111 | _ => (!op2, None, true),      // MVN
+            ),
+            AluOpcode::Tst => (op1_reg & op2, None, false),
+            AluOpcode::Teq => (op1_reg ^ op2, None, false),
+            AluOpcode::Cmp => check_overflow_sub(op1_reg.wrapping_sub(op2), false, false),
+            AluOpcode::Cmn => check_overflow(op1_reg.wrapping_add(op2), false),
+            AluOpcode::Orr => (op1_reg | op2, None, true),
+            AluOpcode::Mov => (op2, None, true),
+            AluOpcode::Bic => (op1_reg & !op2, None, true),
+            AluOpcode::Mvn => (!op2, None, true),
         };
 
         // Check for carry for arithmetic instructions
         // TODO: Could this be wrapped into check_overflow?
-        if opcode == 0b1010 || opcode == 0b0010 {
-            shifter_carry = !op1_reg.checked_sub(op2).is_none();
-        } else if opcode == 0b01011 || opcode == 0b0100 {
-            shifter_carry = op1_reg.checked_add(op2).is_none();
-        } else if opcode == 0b0011 {
-            shifter_carry = !op2.checked_sub(op1_reg).is_none();
-        } else if opcode == 0b0101 {
-            shifter_carry = op1_reg.checked_add(op2).is_none()
-                || op1_reg
-                    .checked_add(op2)
-                    .unwrap()
-                    .checked_add(carry)
-                    .is_none();
-        } else if opcode == 0b0110 {
-            shifter_carry = ((op1_reg as u64) + ((!op2) as u64) + carry as u64) > 0xFFFFFFFF;
-        } else if opcode == 0b0111 {
-            shifter_carry = ((op2 as u64) + ((!op1_reg) as u64) + carry as u64) > 0xFFFFFFFF;
+        match opcode {
+            AluOpcode::Cmp | AluOpcode::Sub => {
+                shifter_carry = !op1_reg.checked_sub(op2).is_none();
+            }
+            AluOpcode::Cmn | AluOpcode::Add => {
+                shifter_carry = op1_reg.checked_add(op2).is_none();
+            }
+            AluOpcode::Rsb => {
+                shifter_carry = !op2.checked_sub(op1_reg).is_none();
+            }
+            AluOpcode::Adc => {
+                shifter_carry = op1_reg.checked_add(op2).is_none()
+                    || op1_reg
+                        .checked_add(op2)
+                        .unwrap()
+                        .checked_add(carry)
+                        .is_none();
+            }
+            AluOpcode::Sbc => {
+                shifter_carry = ((op1_reg as u64) + ((!op2) as u64) + carry as u64) > 0xFFFFFFFF;
+            }
+            AluOpcode::Rsc => {
+                shifter_carry = ((op2 as u64) + ((!op1_reg) as u64) + carry as u64) > 0xFFFFFFFF;
+            }
+            _ => {}
         }
 
         if write_result {
@@ -636,18 +1216,17 @@ impl CPU {
         self.set_register(dest_reg_n, val);
     }
 
-    fn move_into_psr(&mut self, use_spsr_flag: bool, imm_flag: bool, encoding: u32) {
-        let val = if imm_flag {
-            self.rotated_imm_operand(encoding & 0xFFF).0
-        } else {
-            self.get_register((encoding & 0b1111) as usize)
+    fn move_into_psr(&mut self, use_spsr_flag: bool, field_mask: u32, operand: PsrOperand) {
+        let val = match operand {
+            PsrOperand::Immediate(imm) => imm,
+            PsrOperand::Register(reg_n) => self.get_register(reg_n),
         };
 
         // Sets whether certain parts of the PSR will be modified
-        let control_mask = (encoding >> 16) & 1 == 1; // PSR[7:0]
-        let extension_mask = (encoding >> 17) & 1 == 1; // PSR[15:8]
-        let status_mask = (encoding >> 18) & 1 == 1; // PSR[23:16]
-        let flags_mask = (encoding >> 19) & 1 == 1; // PSR[31:24]
+        let control_mask = field_mask & 1 == 1; // PSR[7:0]
+        let extension_mask = (field_mask >> 1) & 1 == 1; // PSR[15:8]
+        let status_mask = (field_mask >> 2) & 1 == 1; // PSR[23:16]
+        let flags_mask = (field_mask >> 3) & 1 == 1; // PSR[31:24]
         let mask = if control_mask { 0xFF } else { 0 }
             | if extension_mask { 0xFF << 8 } else { 0 }
             | if status_mask { 0xFF << 16 } else { 0 }
@@ -660,8 +1239,8 @@ impl CPU {
             // (*spsr).raw &= !mask;
             // (*spsr).raw |= val & mask;
             if let Some(spsr) = self.get_mode_spsr() {
-                (*spsr).raw &= !mask;
-                (*spsr).raw |= val & mask;
+                spsr.raw &= !mask;
+                spsr.raw |= val & mask;
             }
         } else {
             self.cpsr.raw &= !mask;
@@ -669,16 +1248,23 @@ impl CPU {
         }
     }
 
-    fn block_transfer(&mut self, encoding: u32) {
-        let pre_index_flag = (encoding >> 24) & 1 == 1;
-        let up_flag = (encoding >> 23) & 1 == 1;
-        let psr_force_user_flag = (encoding >> 22) & 1 == 1;
-        let write_back_flag = (encoding >> 21) & 1 == 1;
-        let load_flag = (encoding >> 20) & 1 == 1;
+    fn block_transfer(
+        &mut self,
+        load_flag: bool,
+        psr_force_user_flag: bool,
+        base_reg_n: usize,
+        register_list: u16,
+        indexing: Indexing,
+    ) {
+        let Indexing {
+            pre_index: pre_index_flag,
+            up: up_flag,
+            write_back: write_back_flag,
+        } = indexing;
 
         let (reg_n_list, empty_list) = {
             let list = (0..16)
-                .filter(|i| (encoding >> i) & 1 == 1)
+                .filter(|i| (register_list >> i) & 1 == 1)
                 .collect::<Vec<usize>>();
             if list.is_empty() {
                 (vec![15], true)
@@ -686,9 +1272,8 @@ impl CPU {
                 (list, false)
             }
         };
-        let pc_in_list = (encoding >> 15) & 1 == 1;
+        let pc_in_list = (register_list >> 15) & 1 == 1;
 
-        let base_reg_n = ((encoding >> 16) & 0b1111) as usize;
         let base_reg = self.get_register(base_reg_n);
         let mut transfer_addr = if up_flag {
             base_reg.wrapping_add(if pre_index_flag { 4 } else { 0 })
@@ -704,7 +1289,17 @@ impl CPU {
             base_reg.wrapping_sub(4 * reg_n_list.len() as u32)
         };
 
-        for reg_n in &reg_n_list {
+        if load_flag {
+            self.add_internal_cycles(1);
+        }
+        for (i, reg_n) in reg_n_list.iter().enumerate() {
+            let access = if i == 0 {
+                AccessType::NonSequential
+            } else {
+                AccessType::Sequential
+            };
+            self.add_data_cycles(transfer_addr as usize, 4, access);
+
             if load_flag {
                 let data = self.read_u32(transfer_addr as usize);
                 // If S flag is set and r15 is not in the list, the user bank is used
@@ -746,7 +1341,7 @@ impl CPU {
                         }
                     };
                     if *reg_n == 15 {
-                        reg += 2 * self.mode_instr_width();
+                        reg += self.mode_instr_width();
                     }
                     reg
                 };
@@ -759,7 +1354,7 @@ impl CPU {
         if empty_list {
             self.set_register(base_reg_n, base_reg + 0x40);
         } else {
-            let base_reg_in_reg_list = (encoding >> base_reg_n) & 1 == 1;
+            let base_reg_in_reg_list = (register_list >> base_reg_n) & 1 == 1;
             if write_back_flag
                 && (!base_reg_in_reg_list
                     || (!load_flag
@@ -770,51 +1365,41 @@ impl CPU {
         }
     }
 
-    fn branch_instr(&mut self, encoding: u32) {
-        let link_flag = (encoding >> 24) & 1 == 1;
+    fn branch_instr(&mut self, link_flag: bool, offset: i32) {
         if link_flag {
-            self.set_register(14, self.get_register(15));
+            self.set_register(
+                14,
+                self.get_register(15).wrapping_sub(self.mode_instr_width()),
+            );
         }
 
-        // TODO: Branch must use a different shift in thumb mode
-        let mut offset = (encoding & 0xFFFFFF) << if self.cpsr.get_t() { 1 } else { 2 }; // 24 bits, shifted left
-        if (offset >> 23) & 1 == 1 {
-            offset |= 0xFF_000000; // Sign extend
-        }
-        self.set_register(
-            15,
-            self.get_register(15)
-                .wrapping_add(offset)
-                .wrapping_add(if self.cpsr.get_t() { 2 } else { 4 }),
-        );
+        self.set_register(15, self.get_register(15).wrapping_add(offset as u32));
     }
 
-    fn thumb_branch_prefix(&mut self, encoding: u16) {
-        let offset = {
-            let offset_11 = (encoding & 0b11111111111) as u32;
-            let shifted = offset_11 << 12;
-            let sign_ext = if (shifted >> 22) & 1 == 1 {
-                0b111111111
-            } else {
-                0
-            };
-            (sign_ext << 23) | shifted
-        };
-        self.set_register(
-            14,
-            self.get_register(15).wrapping_add(offset).wrapping_add(2),
-        )
+    fn thumb_branch_prefix(&mut self, offset: i32) {
+        self.set_register(14, self.get_register(15).wrapping_add(offset as u32))
     }
 
-    fn thumb_branch_suffix(&mut self, encoding: u16) {
-        let offset = (encoding & 0b11111111111) as u32;
-        let pc_next_instr = self.get_register(15);
-        self.set_register(15, self.get_register(14).wrapping_add(offset << 1));
+    fn thumb_branch_suffix(&mut self, offset: u32) {
+        let pc_next_instr = self.get_register(15).wrapping_sub(2);
+        self.set_register(15, self.get_register(14).wrapping_add(offset));
         self.set_register(14, pc_next_instr | 1);
     }
 
-    fn software_interrupt(&mut self) {
-        self.svc_register_bank[1] = self.get_register(15) & !0b1;
+    fn software_interrupt(&mut self, comment: u32) {
+        if self.hle_bios {
+            // The function number is in the comment field: the low byte in Thumb mode, and the
+            // byte above the low halfword in ARM mode
+            let function = if self.cpsr.get_t() {
+                comment as u8
+            } else {
+                (comment >> 16) as u8
+            };
+            self.hle_software_interrupt(function);
+            return;
+        }
+
+        self.svc_register_bank[1] = self.get_register(15).wrapping_sub(self.mode_instr_width());
         self.svc_spsr.raw = self.cpsr.raw;
         self.cpsr.set_mode(OperatingMode::Supervisor);
         self.cpsr.set_t(false);
@@ -823,7 +1408,7 @@ impl CPU {
     }
 
     fn undefined_interrupt(&mut self) {
-        self.und_register_bank[1] = self.get_register(15) & !0b1;
+        self.und_register_bank[1] = self.get_register(15).wrapping_sub(self.mode_instr_width());
         self.und_spsr.raw = self.cpsr.raw;
         self.cpsr.set_mode(OperatingMode::Undefined);
         self.cpsr.set_t(false);
@@ -831,34 +1416,55 @@ impl CPU {
         self.set_register(15, UND_VEC);
     }
 
-    pub fn irq(&mut self) {
+    // Takes an IRQ exception, unless IRQs are disabled in the CPSR. Returns whether it was taken.
+    pub fn irq(&mut self) -> bool {
         if self.cpsr.get_i() {
-            return;
+            return false;
         }
 
-        self.irq_register_bank[1] = (self.get_register(15) & !0b1) + 4;
+        // The return address is that of the next instruction to execute, plus 4
+        self.irq_register_bank[1] = self.pc().wrapping_add(4);
         self.irq_spsr.raw = self.cpsr.raw;
         self.cpsr.set_mode(OperatingMode::Interrupt);
         self.cpsr.set_t(false);
         self.cpsr.set_i(true);
         self.set_register(15, IRQ_VEC);
+        self.flush_pipeline();
+        self.idle_loop.reset();
+        true
+    }
+
+    // Evaluates the offset of a load or store relative to its base register
+    fn transfer_offset(&self, offset: TransferOffset) -> u32 {
+        match offset {
+            TransferOffset::Immediate(imm) => imm,
+            TransferOffset::Register { rm, shift, amount } => {
+                self.shifted_reg_operand(rm, shift, ShiftAmount::Immediate(amount))
+                    .0
+            }
+        }
     }
 
-    // Decodes a 12-bit operand to a register shifted by an immediate- or register-defined value
+    // Evaluates a register shifted by an immediate- or register-defined value
     // Returns (shifted result, barrel shifter carry out)
-    fn shifted_reg_operand(&self, operand: u32, allow_shift_by_reg: bool) -> (u32, bool) {
-        let shift_by_reg = (operand >> 4) & 1 == 1;
-        let shift_amount = if allow_shift_by_reg && shift_by_reg {
-            self.get_register(((operand >> 8) & 0b1111) as usize) & 0xFF
-        } else {
-            (operand >> 7) & 0b11111
+    fn shifted_reg_operand(
+        &self,
+        op2_reg_n: usize,
+        shift: ShiftType,
+        amount: ShiftAmount,
+    ) -> (u32, bool) {
+        let shift_by_reg = matches!(amount, ShiftAmount::Register(_));
+        let shift_amount = match amount {
+            ShiftAmount::Register(reg_n) => self.get_register(reg_n) & 0xFF,
+            ShiftAmount::Immediate(imm) => imm,
         };
 
-        let op2_reg_n = (operand & 0b1111) as usize;
         let op2_reg = {
             let mut val = self.get_register(op2_reg_n);
             if op2_reg_n == 15 {
-                val = val.wrapping_add(self.mode_instr_width() * if shift_by_reg { 2 } else { 1 });
+                if shift_by_reg {
+                    val = val.wrapping_add(self.mode_instr_width());
+                }
                 val &= if self.cpsr.get_t() { !0b1 } else { !0b11 };
             }
             val
@@ -867,9 +1473,8 @@ impl CPU {
         if shift_by_reg && shift_amount == 0 {
             (op2_reg, self.cpsr.get_c())
         } else {
-            match (operand >> 5) & 0b11 {
-                0b00 => {
-                    // LSL
+            match shift {
+                ShiftType::Lsl => {
                     if shift_amount == 32 {
                         (0, op2_reg & 1 == 1)
                     } else if shift_amount > 32 {
@@ -883,8 +1488,7 @@ impl CPU {
                         (op2_reg << shift_amount, shifter_carry)
                     }
                 }
-                0b01 => {
-                    // LSR
+                ShiftType::Lsr => {
                     if shift_amount == 32 || shift_amount == 0 {
                         (0, (op2_reg >> 31) & 1 == 1)
                     } else if shift_amount > 32 {
@@ -894,8 +1498,7 @@ impl CPU {
                         (op2_reg >> shift_amount, shifter_carry)
                     }
                 }
-                0b10 => {
-                    // ASR
+                ShiftType::Asr => {
                     if shift_amount >= 32 || shift_amount == 0 {
                         if (op2_reg >> 31) & 1 == 1 {
                             (0xFFFFFFFF, true)
@@ -907,8 +1510,7 @@ impl CPU {
                         (((op2_reg as i32) >> shift_amount) as u32, shifter_carry)
                     }
                 }
-                0b11 | _ => {
-                    // ROR
+                ShiftType::Ror => {
                     if shift_amount == 32 {
                         (op2_reg, (op2_reg >> 31) & 1 == 1)
                     } else {
@@ -928,11 +1530,9 @@ impl CPU {
         }
     }
 
-    // Decodes a 12-bit operand to an immediate rotated by a 4-bit unsigned immediate
+    // Evaluates an immediate rotated right by an even number of bits
     // Returns (shifted result, barrel shifter carry out)
-    fn rotated_imm_operand(&self, operand: u32) -> (u32, bool) {
-        let rotate = ((operand >> 8) & 0b1111) * 2;
-        let imm = operand & 0xFF;
+    fn rotated_imm_operand(&self, imm: u32, rotate: u32) -> (u32, bool) {
         let shifter_operand = imm.rotate_right(rotate);
         let shifter_carry = if rotate == 0 {
             self.cpsr.get_c()
@@ -961,29 +1561,59 @@ impl CPU {
 }
 
 impl Memory for CPU {
+    fn read(&mut self, addr: usize) -> u8 {
+        // Sound and timer registers change as time passes, and EEPROM reads advance its output, so
+        // loops reading them aren't idle
+        if let 0x04000060..=0x040000A8 | 0x04000100..=0x0400010F | 0x0D000000..=0x0DFFFFFF = addr {
+            self.idle_loop.on_side_effect();
+        }
+
+        let data = match (addr, &mut self.cartridge) {
+            // Reads from the cartridge can have side effects, e.g. on an EEPROM
+            (0x08000000..=0x0FFFFFFF, Some(cartridge)) => cartridge.read(addr),
+            // The memory map can observe reads of the IO registers the CPU doesn't handle itself
+            (0x04000000..=0x04FFFFFF, _)
+                if !matches!(addr, 0x04000204 | 0x04000205 | 0x04000300 | 0x04000301)
+                    && self.memory.borrow().is_mapped(addr) =>
+            {
+                self.memory.borrow_mut().read(addr)
+            }
+            _ => self.peek(addr),
+        };
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, false, data);
+        }
+        data
+    }
+
     fn peek(&self, addr: usize) -> u8 {
         if addr >> 8 == 0x03FFFF {
             return self.peek(0x03007F00 | (addr & 0xFF));
         }
 
         match addr {
-            0x00000000..=0x00003FFF => self.bios_rom[addr],
+            // The BIOS can only be read while executing it
+            0x00000000..=0x00003FFF if self.fetching || self.executing < 0x4000 => {
+                self.bios_rom[addr]
+            }
+            0x00000000..=0x00003FFF => (self.bios_latch >> ((addr & 0b11) * 8)) as u8,
+            0x04000204 => self.wait_control_reg.lo_byte(),
+            0x04000205 => self.wait_control_reg.hi_byte(),
+            0x04000300 => self.post_flag,
+            // HALTCNT is write-only
+            0x04000301 => 0,
             // 0x02000000..=0x0203FFFF => self.ewram[addr - 0x02000000],
             0x02000000..=0x02FFFFFF => self.ewram[(addr - 0x02000000) % 0x40000],
             // 0x03000000..=0x0307FFFF => self.iwram[addr - 0x03000000],
             0x03000000..=0x03FFFFFF => self.iwram[(addr - 0x03000000) % 0x8000],
             // 0x03FFFF00..=0x03FFFFFF => self.iwram[addr - 0x3FF8000],
-            // TODO: Different wait states
-            0x09FFFF00..=0x09FFFFFF => 1,
-            0x08000000..=0x09FFFFFF => self.cart_rom[addr - 0x08000000],
-            0x0A000000..=0x0BFFFFFF => self.cart_rom[addr - 0x0A000000],
-            // 0x0D000000..=0x0DFFFFFF => 1,
-            0x0D000000..=0x0DFFFFFF => 1,
-            0x0C000000..=0x0DFFFFFF => self.cart_rom[addr - 0x0C000000],
-            // Flash stub
-            0x0E000000 => 0xC2,
-            0x0E000001 => 0x09,
-            0x0E000000..=0x0EFFFFFF => self.cart_sram[(addr - 0x0E000000) % 0x10000],
+            0x08000000..=0x0FFFFFFF => match &self.cartridge {
+                Some(cartridge) => cartridge.peek(addr),
+                // With no cartridge inserted, nothing drives the bus
+                None => Cartridge::open_bus(addr),
+            },
+            0x00004000..=0x01FFFFFF | 0x10000000..=0xFFFFFFFF => self.open_bus(addr),
+            _ if !self.memory.borrow().is_mapped(addr) => self.open_bus(addr),
             _ => self.memory.borrow().peek(addr),
         }
     }
@@ -994,19 +1624,162 @@ impl Memory for CPU {
             return;
         }
 
+        self.idle_loop.on_side_effect();
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, true, data);
+        }
+
         match addr {
+            0x04000204 => {
+                self.wait_control_reg.set_lo_byte(data);
+                self.prefetch_buffer.reset_progress();
+            }
+            // The cartridge type flag is read-only
+            0x04000205 => {
+                self.wait_control_reg.set_hi_byte(data & 0x7F);
+                self.prefetch_buffer.reset_progress();
+            }
+            0x04000300 => self.post_flag = data & 1,
+            0x04000301 => {
+                self.halt_mode = Some(if (data >> 7) & 1 == 1 {
+                    HaltMode::Stop
+                } else {
+                    HaltMode::Halt
+                })
+            }
             // 0x02000000..=0x0203FFFF => self.ewram[addr - 0x02000000] = data,
             0x02000000..=0x02FFFFFF => self.ewram[(addr - 0x02000000) % 0x40000] = data,
             // 0x03000000..=0x0307FFFF => self.iwram[addr - 0x03000000] = data,
-            0x03000000..=0x03FFFFFF => self.iwram[(addr - 0x03000000) % 0x8000] = data,
+            0x03000000..=0x03FFFFFF => {
+                let offset = (addr - 0x03000000) % 0x8000;
+                self.iwram[offset] = data;
+                self.decode_cache
+                    .invalidate(DecodeCache::IWRAM_BASE + offset);
+            }
             // 0x03FFFF00..=0x03FFFFFF => self.iwram[addr - 0x3FF8000] = data,
-            // 0x0D000000..=0x0DFFFFFF => println!("{:08X} {:02X}", addr, data),
-            0x08000000..=0x0DFFFFFF => {}
-            // 0x08000000..=0x09FFFFFF => self.cart_rom[addr - 0x08000000] = data,
-            // 0x0A000000..=0x0BFFFFFF => self.cart_rom[addr - 0x0A000000] = data,
-            // 0x0C000000..=0x0DFFFFFF => self.cart_rom[addr - 0x0C000000] = data,
-            0x0E000000..=0x0EFFFFFF => self.cart_sram[(addr - 0x0E000000) % 0x10000] = data,
+            0x08000000..=0x0FFFFFFF => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.write(addr, data);
+                }
+            }
             _ => self.memory.borrow_mut().write(addr, data),
         }
     }
 }
+
+impl SaveState for CPU {
+    fn save_state(&self, state: &mut StateWriter) {
+        for status_reg in [
+            &self.cpsr,
+            &self.fiq_spsr,
+            &self.svc_spsr,
+            &self.abt_spsr,
+            &self.irq_spsr,
+            &self.und_spsr,
+        ] {
+            state.write_u32(status_reg.raw);
+        }
+
+        let banks = [
+            &self.registers[..],
+            &self.fiq_register_bank[..],
+            &self.svc_register_bank[..],
+            &self.abt_register_bank[..],
+            &self.irq_register_bank[..],
+            &self.und_register_bank[..],
+        ];
+        for &val in banks.iter().flat_map(|bank| bank.iter()) {
+            state.write_u32(val);
+        }
+
+        // The BIOS and cartridge ROM aren't saved, since they can't change while running
+        state.write_bytes(&self.ewram);
+        state.write_bytes(&self.iwram);
+        state.write_bool(self.cartridge.is_some());
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.save_state(state);
+        }
+
+        state.write_u32(self.pipeline[0]);
+        state.write_u32(self.pipeline[1]);
+        state.write_bool(self.pipeline_flushed);
+
+        state.write_u16(self.wait_control_reg.bit_range(15, 0));
+        self.prefetch_buffer.save_state(state);
+        state.write_bool(self.sequential_fetch);
+        state.write_bool(self.hle_intr_waiting);
+        state.write_u32(self.bios_latch);
+        state.write_u32(self.last_fetched);
+        state.write_u32(self.executing);
+        state.write_u8(match self.halt_mode {
+            None => 0,
+            Some(HaltMode::Halt) => 1,
+            Some(HaltMode::Stop) => 2,
+        });
+        state.write_u8(self.post_flag);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for status_reg in [
+            &mut self.cpsr,
+            &mut self.fiq_spsr,
+            &mut self.svc_spsr,
+            &mut self.abt_spsr,
+            &mut self.irq_spsr,
+            &mut self.und_spsr,
+        ] {
+            status_reg.raw = state.read_u32()?;
+        }
+        if OperatingMode::from_u32(self.cpsr.raw & 0b11111).is_none() {
+            return Err(StateError::Corrupt);
+        }
+
+        let banks = [
+            &mut self.registers[..],
+            &mut self.fiq_register_bank[..],
+            &mut self.svc_register_bank[..],
+            &mut self.abt_register_bank[..],
+            &mut self.irq_register_bank[..],
+            &mut self.und_register_bank[..],
+        ];
+        for bank in banks {
+            for val in bank.iter_mut() {
+                *val = state.read_u32()?;
+            }
+        }
+
+        state.read_bytes_into(&mut self.ewram)?;
+        state.read_bytes_into(&mut self.iwram)?;
+        match (state.read_bool()?, &mut self.cartridge) {
+            (true, Some(cartridge)) => cartridge.load_state(state)?,
+            (false, None) => {}
+            _ => return Err(StateError::RomMismatch),
+        }
+
+        self.pipeline = [state.read_u32()?, state.read_u32()?];
+        self.pipeline_decoded = [None; 2];
+        self.pipeline_flushed = state.read_bool()?;
+        self.decode_cache.clear();
+
+        // The cartridge type flag is read-only
+        let wait_control = state.read_u16()? & 0x7FFF;
+        self.wait_control_reg.set_bit_range(15, 0, wait_control);
+        self.prefetch_buffer.load_state(state)?;
+        self.sequential_fetch = state.read_bool()?;
+        self.hle_intr_waiting = state.read_bool()?;
+        self.bios_latch = state.read_u32()?;
+        self.last_fetched = state.read_u32()?;
+        self.executing = state.read_u32()?;
+        self.halt_mode = match state.read_u8()? {
+            0 => None,
+            1 => Some(HaltMode::Halt),
+            2 => Some(HaltMode::Stop),
+            _ => return Err(StateError::Corrupt),
+        };
+        self.post_flag = state.read_u8()?;
+        self.idle_loop.reset();
+        self.idle_loop_detected = None;
+        self.cycles = 0;
+        Ok(())
+    }
+}
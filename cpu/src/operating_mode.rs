@@ -1,4 +1,4 @@
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum OperatingMode {
     User = 0b10000,
     FastInterrupt = 0b10001,
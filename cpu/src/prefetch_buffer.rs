@@ -0,0 +1,112 @@
+use memory::{SaveState, StateError, StateReader, StateWriter};
+
+// The GamePak prefetch buffer, which reads up to 8 sequential halfwords of cartridge ROM ahead of
+// the CPU while the cartridge bus is otherwise idle
+pub struct PrefetchBuffer {
+    // The address of the oldest buffered halfword
+    head: u32,
+    // The number of halfwords buffered
+    len: u32,
+    // Cycles spent so far reading the next halfword
+    progress: u32,
+    active: bool,
+}
+
+impl PrefetchBuffer {
+    const CAPACITY: u32 = 8;
+    // The slowest a halfword can be read, from WS2 with 8 wait states
+    const MAX_HALFWORD_CYCLES: u32 = 9;
+
+    pub fn new() -> Self {
+        Self {
+            head: 0,
+            len: 0,
+            progress: 0,
+            active: false,
+        }
+    }
+
+    // The address of the next halfword the buffer will provide
+    pub fn head(&self) -> u32 {
+        self.head
+    }
+
+    // Starts buffering sequentially from the given address, discarding the contents
+    pub fn restart(&mut self, addr: u32) {
+        self.head = addr;
+        self.len = 0;
+        self.progress = 0;
+        self.active = true;
+    }
+
+    // Stops buffering, e.g. when the CPU makes a data access to cartridge ROM
+    pub fn stop(&mut self) {
+        self.len = 0;
+        self.progress = 0;
+        self.active = false;
+    }
+
+    // Starts reading the next halfword again, for when the wait states change partway through it
+    pub fn reset_progress(&mut self) {
+        self.progress = 0;
+    }
+
+    // Advances the buffer by some idle bus cycles, where each halfword takes `halfword_cycles`
+    pub fn advance(&mut self, cycles: u32, halfword_cycles: u32) {
+        if !self.active || self.len == Self::CAPACITY {
+            return;
+        }
+
+        self.progress += cycles;
+        while self.len < Self::CAPACITY && self.progress >= halfword_cycles {
+            self.progress -= halfword_cycles;
+            self.len += 1;
+        }
+        if self.len == Self::CAPACITY {
+            self.progress = 0;
+        }
+    }
+
+    // Attempts to serve a code fetch of `halfwords` halfwords from the buffer. Returns the number
+    // of cycles the fetch took, or None if the buffer isn't reading from that address.
+    pub fn take(&mut self, addr: u32, halfwords: u32, halfword_cycles: u32) -> Option<u32> {
+        if !self.active || addr != self.head {
+            return None;
+        }
+
+        let cycles = if self.len >= halfwords {
+            self.len -= halfwords;
+            1
+        } else {
+            // The CPU waits for the halfwords that are still being read. The progress can exceed a
+            // halfword's cycles if the head has just moved into a faster wait state region.
+            let remaining =
+                ((halfwords - self.len) * halfword_cycles).saturating_sub(self.progress);
+            self.len = 0;
+            self.progress = 0;
+            std::cmp::max(remaining, 1)
+        };
+        self.head = self.head.wrapping_add(2 * halfwords);
+        Some(cycles)
+    }
+}
+
+impl SaveState for PrefetchBuffer {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.head);
+        state.write_u32(self.len);
+        state.write_u32(self.progress);
+        state.write_bool(self.active);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.head = state.read_u32()?;
+        self.len = state.read_u32()?;
+        self.progress = state.read_u32()?;
+        self.active = state.read_bool()?;
+        if self.len > Self::CAPACITY || self.progress >= Self::MAX_HALFWORD_CYCLES {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+}
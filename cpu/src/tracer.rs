@@ -0,0 +1,162 @@
+use crate::{disassemble_arm, disassemble_thumb, OperatingMode};
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+// The state of the CPU just before an instruction executes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceEntry {
+    // r15 is the value the instruction reads, i.e. its address plus two instruction widths
+    pub registers: [u32; 16],
+    pub cpsr: u32,
+    pub opcode: u32,
+    // The halfword after a Thumb opcode, so that both halves of a BL disassemble as one
+    pub next: u16,
+}
+
+impl TraceEntry {
+    pub fn thumb(&self) -> bool {
+        self.cpsr & (1 << 5) != 0
+    }
+
+    // The address of the instruction
+    pub fn addr(&self) -> u32 {
+        let width = if self.thumb() { 2 } else { 4 };
+        self.registers[15].wrapping_sub(2 * width)
+    }
+
+    // Writes the entry as a line of the registers, CPSR and opcode, followed by the disassembly
+    // if asked for, e.g.
+    // `00000000 ... 08000008 cpsr: 0000001F | E3A00001: mov r0, #0x1`
+    // Thumb opcodes are padded to the width of ARM ones, so the columns line up. This format is
+    // stable, so traces can be diffed between versions, and against other emulators' logs with
+    // the disassembly left out.
+    pub fn write_line(&self, out: &mut dyn Write, disassemble: bool) -> io::Result<()> {
+        for register in self.registers.iter() {
+            write!(out, "{:08X} ", register)?;
+        }
+        write!(out, "cpsr: {:08X} | ", self.cpsr)?;
+        if self.thumb() {
+            write!(out, "    {:04X}", self.opcode)?;
+        } else {
+            write!(out, "{:08X}", self.opcode)?;
+        }
+        if disassemble {
+            let disassembly = if self.thumb() {
+                disassemble_thumb(self.opcode as u16, self.next, self.addr())
+            } else {
+                disassemble_arm(self.opcode, self.addr())
+            };
+            write!(out, ": {}", disassembly)?;
+        }
+        writeln!(out)
+    }
+}
+
+// Which instructions are traced. An empty list traces everything, rather than nothing.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    // Instruction addresses to trace
+    pub ranges: Vec<RangeInclusive<u32>>,
+    // Operating modes to trace in
+    pub modes: Vec<OperatingMode>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, addr: u32, mode: OperatingMode) -> bool {
+        (self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&addr)))
+            && (self.modes.is_empty() || self.modes.contains(&mode))
+    }
+}
+
+// Records the instructions the CPU executes that pass a filter. Each one can be written out as
+// it's traced, and the most recent are kept in a ring buffer, which is dumped to stderr if the
+// emulator panics.
+pub struct Tracer {
+    filter: TraceFilter,
+    output: Option<Box<dyn Write>>,
+    disassemble: bool,
+    // The most recent entries, oldest first
+    recent: VecDeque<TraceEntry>,
+    recent_len: usize,
+}
+
+impl Tracer {
+    pub fn new(filter: TraceFilter) -> Self {
+        Self {
+            filter,
+            output: None,
+            disassemble: true,
+            recent: VecDeque::new(),
+            recent_len: 0,
+        }
+    }
+
+    // Writes every entry traced from now on
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = Some(output);
+    }
+
+    // Whether lines include the disassembly, which is on by default. Other emulators disassemble
+    // differently, so it's best left out of traces to be diffed against theirs.
+    pub fn set_disassemble(&mut self, disassemble: bool) {
+        self.disassemble = disassemble;
+    }
+
+    // Sets how many of the most recent entries are kept
+    pub fn set_recent_len(&mut self, len: usize) {
+        self.recent_len = len;
+        while self.recent.len() > len {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    // The most recent entries, oldest first
+    pub fn recent(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.recent.iter()
+    }
+
+    // Writes the most recent entries, oldest first
+    pub fn dump(&self, out: &mut dyn Write) -> io::Result<()> {
+        for entry in self.recent.iter() {
+            entry.write_line(out, self.disassemble)?;
+        }
+        Ok(())
+    }
+
+    pub fn trace(&mut self, entry: TraceEntry) {
+        if self.recent_len > 0 {
+            if self.recent.len() == self.recent_len {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(entry);
+        }
+        if let Some(output) = &mut self.output {
+            // A trace that can't be written isn't worth stopping emulation for
+            if entry.write_line(output, self.disassemble).is_err() {
+                self.output = None;
+            }
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if let Some(output) = &mut self.output {
+            output.flush().ok();
+        }
+        // The emulator is dropped while unwinding from a panic, which is when the last
+        // instructions are most useful
+        if std::thread::panicking() && !self.recent.is_empty() {
+            let stderr = io::stderr();
+            let mut stderr = stderr.lock();
+            writeln!(stderr, "last {} instructions:", self.recent.len()).ok();
+            self.dump(&mut stderr).ok();
+        }
+    }
+}
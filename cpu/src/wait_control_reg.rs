@@ -0,0 +1,54 @@
+bitfield! {
+  /// 4000204h - WAITCNT
+  /// Configures the wait states of cartridge ROM and SRAM accesses
+  pub struct WaitControlReg(u16);
+  impl Debug;
+  pub sram_wait, _: 1, 0;
+  pub ws0_first_wait, _: 3, 2;
+  pub ws0_second_wait, _: 4;
+  pub ws1_first_wait, _: 6, 5;
+  pub ws1_second_wait, _: 7;
+  pub ws2_first_wait, _: 9, 8;
+  pub ws2_second_wait, _: 10;
+  pub phi_terminal_output, _: 12, 11;
+  pub prefetch, _: 14;
+  pub cgb_cart, _: 15;
+
+  pub u8, lo_byte, set_lo_byte: 7, 0;
+  pub u8, hi_byte, set_hi_byte: 15, 8;
+}
+
+impl WaitControlReg {
+    // The number of cycles taken by a non-sequential 16-bit access to wait state region 0-2
+    pub fn first_access_cycles(&self, wait_state: u32) -> u32 {
+        let setting = match wait_state {
+            0 => self.ws0_first_wait(),
+            1 => self.ws1_first_wait(),
+            _ => self.ws2_first_wait(),
+        };
+        1 + Self::first_wait(setting)
+    }
+
+    // The number of cycles taken by a sequential 16-bit access to wait state region 0-2
+    pub fn second_access_cycles(&self, wait_state: u32) -> u32 {
+        1 + match wait_state {
+            0 => [2, 1][self.ws0_second_wait() as usize],
+            1 => [4, 1][self.ws1_second_wait() as usize],
+            _ => [8, 1][self.ws2_second_wait() as usize],
+        }
+    }
+
+    // The number of cycles taken by an 8-bit SRAM access
+    pub fn sram_access_cycles(&self) -> u32 {
+        1 + Self::first_wait(self.sram_wait())
+    }
+
+    fn first_wait(setting: u16) -> u32 {
+        match setting {
+            0 => 4,
+            1 => 3,
+            2 => 2,
+            _ => 8,
+        }
+    }
+}
@@ -0,0 +1,36 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+// Watches a range of the address space for data accesses, by either the CPU or DMA. Code fetches
+// aren't data accesses, so they don't trigger watchpoints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub addr: u32,
+    pub len: u32,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, addr: u32, write: bool) -> bool {
+        let kind_matches = match self.kind {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::ReadWrite => true,
+        };
+        kind_matches && addr.wrapping_sub(self.addr) < self.len
+    }
+}
+
+// The first access to trigger a watchpoint. Accesses are seen a byte at a time, so this is the
+// first watched byte of the access.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WatchpointHit {
+    pub addr: u32,
+    pub write: bool,
+    // The byte read or written
+    pub value: u8,
+}
@@ -0,0 +1,216 @@
+mod common;
+
+use cpu::{BackupType, Cartridge};
+use memory::Memory;
+
+// A ROM carrying the ID string of the given save library, which decides its backup type
+fn cartridge(id: &[u8]) -> Cartridge {
+    let mut rom = vec![0; 0x200];
+    rom[0x100..0x100 + id.len()].copy_from_slice(id);
+    Cartridge::new(rom).unwrap()
+}
+
+#[test]
+fn sram() {
+    let mut cart = cartridge(b"SRAM_V113");
+    assert_eq!(cart.backup_type(), BackupType::Sram);
+    assert_eq!(cart.backup().len(), 0x8000);
+
+    // The 32 KiB are mirrored through the region
+    cart.write(0x0E000010, 0x12);
+    cart.write(0x0E00FFFF, 0x34);
+    assert_eq!(cart.read(0x0E008010), 0x12);
+    assert_eq!(cart.read(0x0E007FFF), 0x34);
+    assert_eq!(cart.read(0x0EFF8010), 0x12);
+    assert!(cart.take_backup_dirty());
+    assert!(!cart.take_backup_dirty());
+
+    // Larger saves are cut down to size
+    let mut save = vec![0xAA; 0x10000];
+    save[0] = 0x56;
+    cart.load_backup(&save);
+    assert_eq!(cart.backup().len(), 0x8000);
+    assert_eq!(cart.read(0x0E008000), 0x56);
+    assert_eq!(cart.read(0x0E000010), 0xAA);
+
+    // The CPU sees the region mirrored again from 0x0F000000
+    let mut cpu = common::cpu();
+    cpu.insert_cartridge(cart);
+    cpu.write(0x0F000020, 0x78);
+    assert_eq!(cpu.read(0x0E000020), 0x78);
+    assert_eq!(cpu.read(0x0FFF8000), 0x56);
+}
+
+// Sends a flash command, preceded by the unlock sequence
+fn flash_command(cart: &mut Cartridge, addr: u32, command: u8) {
+    cart.write(0x0E005555, 0xAA);
+    cart.write(0x0E002AAA, 0x55);
+    cart.write(0x0E000000 | addr as usize, command);
+}
+
+fn program_byte(cart: &mut Cartridge, addr: usize, data: u8) {
+    flash_command(cart, 0x5555, 0xA0);
+    cart.write(addr, data);
+}
+
+#[test]
+fn flash_id_mode() {
+    let mut cart = cartridge(b"FLASH1M_V103");
+    assert_eq!(cart.backup_type(), BackupType::Flash128K);
+    assert_eq!(cart.read(0x0E000000), 0xFF);
+    flash_command(&mut cart, 0x5555, 0x90);
+    assert_eq!((cart.read(0x0E000000), cart.read(0x0E000001)), (0xC2, 0x09));
+    assert_eq!(cart.read(0x0E000002), 0xFF);
+    flash_command(&mut cart, 0x5555, 0xF0);
+    assert_eq!((cart.read(0x0E000000), cart.read(0x0E000001)), (0xFF, 0xFF));
+
+    let mut cart = cartridge(b"FLASH512_V131");
+    assert_eq!(cart.backup_type(), BackupType::Flash64K);
+    flash_command(&mut cart, 0x5555, 0x90);
+    assert_eq!((cart.read(0x0E000000), cart.read(0x0E000001)), (0x32, 0x1B));
+    // A lone F0h also leaves ID mode
+    cart.write(0x0E005555, 0xF0);
+    assert_eq!(cart.read(0x0E000000), 0xFF);
+}
+
+#[test]
+fn flash_program() {
+    let mut cart = cartridge(b"FLASH_V126");
+    program_byte(&mut cart, 0x0E001234, 0x42);
+    assert_eq!(cart.read(0x0E001234), 0x42);
+    assert_eq!(cart.backup()[0x1234], 0x42);
+
+    // Writes without the command sequence are ignored, as is a broken unlock sequence
+    cart.write(0x0E001235, 0x43);
+    cart.write(0x0E005555, 0xAA);
+    cart.write(0x0E002AAA, 0x56);
+    cart.write(0x0E005555, 0xA0);
+    cart.write(0x0E001235, 0x43);
+    assert_eq!(cart.read(0x0E001235), 0xFF);
+}
+
+#[test]
+fn flash_erase() {
+    let mut cart = cartridge(b"FLASH_V126");
+    for addr in [0x0E000010, 0x0E001010, 0x0E002010] {
+        program_byte(&mut cart, addr, 0x42);
+    }
+
+    // Sector erase clears the 4K sector containing the address it's given
+    flash_command(&mut cart, 0x5555, 0x80);
+    flash_command(&mut cart, 0x1ABC, 0x30);
+    assert_eq!(cart.read(0x0E000010), 0x42);
+    assert_eq!(cart.read(0x0E001010), 0xFF);
+    assert_eq!(cart.read(0x0E002010), 0x42);
+
+    // Erasing has to immediately follow command 80h
+    flash_command(&mut cart, 0x5555, 0x80);
+    program_byte(&mut cart, 0x0E003000, 0x12);
+    flash_command(&mut cart, 0x5555, 0x10);
+    flash_command(&mut cart, 0x2000, 0x30);
+    assert_eq!(cart.read(0x0E000010), 0x42);
+    assert_eq!(cart.read(0x0E002010), 0x42);
+    assert_eq!(cart.read(0x0E003000), 0x12);
+
+    flash_command(&mut cart, 0x5555, 0x80);
+    flash_command(&mut cart, 0x5555, 0x10);
+    assert!(cart.backup().iter().all(|&byte| byte == 0xFF));
+}
+
+#[test]
+fn flash_bank_switch() {
+    let mut cart = cartridge(b"FLASH1M_V103");
+    program_byte(&mut cart, 0x0E000100, 0x11);
+    flash_command(&mut cart, 0x5555, 0xB0);
+    cart.write(0x0E000000, 1);
+    assert_eq!(cart.read(0x0E000100), 0xFF);
+    program_byte(&mut cart, 0x0E000100, 0x22);
+    assert_eq!(cart.read(0x0E000100), 0x22);
+    assert_eq!((cart.backup()[0x100], cart.backup()[0x10100]), (0x11, 0x22));
+
+    flash_command(&mut cart, 0x5555, 0xB0);
+    cart.write(0x0E000000, 0);
+    assert_eq!(cart.read(0x0E000100), 0x11);
+
+    // 64K chips only have one bank
+    let mut cart = cartridge(b"FLASH_V126");
+    flash_command(&mut cart, 0x5555, 0xB0);
+    cart.write(0x0E000000, 1);
+    program_byte(&mut cart, 0x0E000100, 0x33);
+    assert_eq!(cart.backup()[0x100], 0x33);
+}
+
+// Sends the bits of a request to the EEPROM, MSB-first, as DMA3 would
+fn eeprom_request(cart: &mut Cartridge, bits: &[(u64, u32)]) {
+    let len: u32 = bits.iter().map(|&(_, width)| width).sum();
+    cart.on_dma3_transfer(0x0D000000, len);
+    for &(value, width) in bits {
+        for i in (0..width).rev() {
+            cart.write(0x0D000000, ((value >> i) & 1) as u8);
+            cart.write(0x0D000001, 0);
+        }
+    }
+}
+
+fn eeprom_read_bits(cart: &mut Cartridge, n: u32) -> Vec<u8> {
+    (0..n).map(|_| cart.read(0x0D000000)).collect()
+}
+
+fn bits(value: u64) -> Vec<u8> {
+    (0..64).rev().map(|i| ((value >> i) & 1) as u8).collect()
+}
+
+#[test]
+fn eeprom_8k() {
+    let mut cart = cartridge(b"EEPROM_V124");
+    eeprom_request(
+        &mut cart,
+        &[(0b10, 2), (0x0123, 14), (0x0123456789ABCDEF, 64), (0, 1)],
+    );
+    assert_eq!(cart.backup_type(), BackupType::Eeprom8K);
+    assert_eq!(
+        cart.backup()[0x123 * 8..0x124 * 8],
+        0x0123456789ABCDEFu64.to_be_bytes()
+    );
+    // Writes complete instantly, so the ready bit is set straight away
+    assert_eq!(cart.read(0x0D000000), 1);
+
+    eeprom_request(&mut cart, &[(0b11, 2), (0x0123, 14), (0, 1)]);
+    // The 64 data bits are preceded by 4 dummy bits
+    assert_eq!(eeprom_read_bits(&mut cart, 4), vec![0; 4]);
+    assert_eq!(eeprom_read_bits(&mut cart, 64), bits(0x0123456789ABCDEF));
+    assert_eq!(cart.read(0x0D000000), 1);
+
+    // Only bit 0 of each halfword is connected
+    assert_eq!(cart.read(0x0D000001), 0);
+    // Requests not starting with a 1 bit are ignored
+    eeprom_request(&mut cart, &[(0b01, 2), (0x0123, 14), (0, 1)]);
+    assert_eq!(cart.read(0x0D000000), 1);
+}
+
+#[test]
+fn eeprom_512() {
+    let mut cart = cartridge(b"EEPROM_V124");
+    // The first request's length shows it has a 6-bit address
+    eeprom_request(
+        &mut cart,
+        &[(0b10, 2), (0x3F, 6), (0xFEDCBA9876543210, 64), (0, 1)],
+    );
+    assert_eq!(cart.backup_type(), BackupType::Eeprom512);
+    assert_eq!(cart.backup().len(), 0x200);
+    assert_eq!(cart.backup()[0x1F8..], 0xFEDCBA9876543210u64.to_be_bytes());
+
+    // Then the size is fixed, even if a later transfer has the length of an 8 KiB request
+    cart.on_dma3_transfer(0x0D000000, 17);
+    assert_eq!(cart.backup_type(), BackupType::Eeprom512);
+    eeprom_request(&mut cart, &[(0b11, 2), (0x3F, 6), (0, 1)]);
+    eeprom_read_bits(&mut cart, 4);
+    assert_eq!(eeprom_read_bits(&mut cart, 64), bits(0xFEDCBA9876543210));
+
+    // A loaded save also fixes the size
+    let mut cart = cartridge(b"EEPROM_V124");
+    cart.load_backup(&[0x55; 0x200]);
+    cart.on_dma3_transfer(0x0D000000, 81);
+    assert_eq!(cart.backup_type(), BackupType::Eeprom512);
+    assert_eq!(cart.backup(), [0x55; 0x200]);
+}
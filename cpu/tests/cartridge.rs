@@ -0,0 +1,107 @@
+use cpu::{Cartridge, CartridgeError, CartridgeHeader, Peripherals};
+use memory::Memory;
+
+// A ROM whose header has the given game code, with a valid complement check
+fn rom_with_header(game_code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x200];
+    rom[0xA0..0xAC].copy_from_slice(b"POKEMON RUBY");
+    rom[0xAC..0xB0].copy_from_slice(game_code);
+    rom[0xB0..0xB2].copy_from_slice(b"01");
+    rom[0xB2] = 0x96;
+    rom[0xBC] = 1;
+    rom[0xBD] = 0x40;
+    rom
+}
+
+#[test]
+fn header_parsing() {
+    let header = CartridgeHeader::parse(&rom_with_header(b"AXVE"));
+    assert_eq!(header.title, "POKEMON RUBY");
+    assert_eq!(header.game_code, "AXVE");
+    assert_eq!(header.maker_code, "01");
+    assert_eq!(header.version, 1);
+    assert_eq!(header.complement_check, 0x40);
+    assert!(header.complement_check_valid);
+
+    // Text fields are padded with zeros
+    let mut rom = rom_with_header(b"AXVE");
+    rom[0xA7..0xAC].fill(0);
+    assert_eq!(CartridgeHeader::parse(&rom).title, "POKEMON");
+
+    // ROMs too small for a header read as if they were padded with zeros
+    let header = CartridgeHeader::parse(&[0; 0x10]);
+    assert_eq!((header.title.as_str(), header.version), ("", 0));
+}
+
+#[test]
+fn complement_check() {
+    // Changing any byte of the header after the check was computed invalidates it
+    let mut rom = rom_with_header(b"AXVE");
+    rom[0xBC] = 2;
+    let header = CartridgeHeader::parse(&rom);
+    assert_eq!(header.complement_check, 0x40);
+    assert!(!header.complement_check_valid);
+
+    // The check doesn't cover the rest of the ROM
+    let mut rom = rom_with_header(b"AXVE");
+    rom[0x1FF] = 0xFF;
+    assert!(CartridgeHeader::parse(&rom).complement_check_valid);
+}
+
+#[test]
+fn peripherals() {
+    let rtc = Cartridge::new(rom_with_header(b"BPEE"))
+        .unwrap()
+        .peripherals();
+    assert_eq!(rtc.names(), vec!["real-time clock"]);
+    let boktai = Cartridge::new(rom_with_header(b"U3IE"))
+        .unwrap()
+        .peripherals();
+    assert!(boktai.rtc && boktai.solar_sensor);
+    let twisted = Cartridge::new(rom_with_header(b"RZWE"))
+        .unwrap()
+        .peripherals();
+    assert_eq!(twisted.names(), vec!["gyro sensor", "rumble"]);
+
+    // Other games with a clock are found by the RTC library's ID string
+    let mut rom = rom_with_header(b"ABCE");
+    assert_eq!(
+        Cartridge::new(rom.clone()).unwrap().peripherals(),
+        Peripherals::default()
+    );
+    rom[0x1F0..0x1F8].copy_from_slice(b"SIIRTC_V");
+    assert!(Cartridge::new(rom).unwrap().peripherals().rtc);
+}
+
+#[test]
+fn too_large() {
+    assert_eq!(
+        Cartridge::new(vec![0; 0x2000001]).err(),
+        Some(CartridgeError::TooLarge(0x2000001))
+    );
+}
+
+#[test]
+fn rom_mirrors_and_open_bus() {
+    let rom: Vec<u8> = (0..0x300).map(|i| i as u8).collect();
+    let cartridge = Cartridge::new(rom).unwrap();
+
+    // The ROM appears in each wait state region
+    for base in [0x08000000, 0x0A000000, 0x0C000000] {
+        assert_eq!(cartridge.peek_u32(base + 0x1FC), 0xFFFEFDFC);
+    }
+    // Past its end, each halfword reads as half its address
+    assert_eq!(cartridge.peek_u16(0x08000300), 0x0180);
+    assert_eq!(cartridge.peek_u32(0x08123454), 0x1A2B1A2A);
+    assert_eq!(cartridge.peek_u16(0x0A000302), 0x0181);
+    // Including the end of each region, which is the same whichever mirror it's read through
+    assert_eq!(cartridge.peek_u16(0x09FFFF00), 0xFF80);
+    assert_eq!(cartridge.peek_u16(0x0BFFFF00), 0xFF80);
+
+    // A 32 MiB ROM fills the regions right up to the end
+    let rom: Vec<u8> = (0..0x2000000).map(|i| (i >> 8) as u8).collect();
+    let cartridge = Cartridge::new(rom).unwrap();
+    assert_eq!(cartridge.peek_u16(0x09FFFF00), 0xFFFF);
+    assert_eq!(cartridge.peek_u16(0x0BFFFF00), 0xFFFF);
+    assert_eq!(cartridge.peek_u16(0x09FFFE00), 0xFEFE);
+}
@@ -0,0 +1,37 @@
+// Each test file only uses some of these
+#![allow(dead_code)]
+
+use cpu::{Cartridge, CPU};
+use memory::Memory;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Stands in for the IO registers, palette RAM, VRAM and OAM
+#[derive(Default)]
+pub struct Bus(HashMap<usize, u8>);
+
+impl Memory for Bus {
+    fn peek(&self, addr: usize) -> u8 {
+        self.0.get(&addr).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, addr: usize, data: u8) {
+        self.0.insert(addr, data);
+    }
+}
+
+// A CPU with nothing but the stand-in bus attached
+pub fn cpu() -> CPU {
+    CPU::new(Rc::new(RefCell::new(Bus::default())))
+}
+
+// A CPU booted straight into a cartridge holding the given ARM program
+pub fn boot_rom(program: &[u32]) -> CPU {
+    let rom = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let mut cpu = cpu();
+    cpu.insert_cartridge(Cartridge::new(rom).unwrap());
+    cpu.skip_bios();
+    cpu
+}
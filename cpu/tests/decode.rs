@@ -0,0 +1,1285 @@
+// Test vectors for the ARM and Thumb decoders. The reference tables below are written from the
+// instruction format tables in the ARM7TDMI data sheet, independently of the decoder's own logic.
+use cpu::{
+    AluOpcode, Condition, HalfwordKind, Indexing, Instruction, PsrOperand, ShiftAmount, ShiftType,
+    ShifterOperand, TransferOffset,
+};
+
+fn bits(encoding: u32, lo: u32, len: u32) -> u32 {
+    (encoding >> lo) & ((1 << len) - 1)
+}
+
+fn bit(encoding: u32, n: u32) -> bool {
+    (encoding >> n) & 1 == 1
+}
+
+fn reg(encoding: u32, lo: u32) -> usize {
+    bits(encoding, lo, 4) as usize
+}
+
+fn indexing(encoding: u32) -> Indexing {
+    Indexing {
+        pre_index: bit(encoding, 24),
+        up: bit(encoding, 23),
+        write_back: bit(encoding, 21),
+    }
+}
+
+fn sign_extend(val: u32, width: u32) -> i32 {
+    ((val << (32 - width)) as i32) >> (32 - width)
+}
+
+fn halfword_kind(encoding: u32) -> HalfwordKind {
+    match bits(encoding, 5, 2) {
+        0b01 => HalfwordKind::UnsignedHalfword,
+        0b10 => HalfwordKind::SignedByte,
+        _ => HalfwordKind::SignedHalfword,
+    }
+}
+
+fn data_proc(encoding: u32, operand: ShifterOperand) -> Instruction {
+    Instruction::DataProcessing {
+        opcode: AluOpcode::from_u32(bits(encoding, 21, 4)),
+        set_flags: bit(encoding, 20),
+        rn: reg(encoding, 16),
+        rd: reg(encoding, 12),
+        operand,
+    }
+}
+
+fn halfword_transfer(encoding: u32, offset: TransferOffset) -> Instruction {
+    Instruction::HalfwordTransfer {
+        load: bit(encoding, 20),
+        kind: halfword_kind(encoding),
+        rn: reg(encoding, 16),
+        rd: reg(encoding, 12),
+        indexing: indexing(encoding),
+        offset,
+    }
+}
+
+struct ArmFormat {
+    name: &'static str,
+    mask: u32,
+    value: u32,
+    expected: fn(u32) -> Instruction,
+}
+
+// Ordered so that the first matching row wins, as in the data sheet's decoding table
+const ARM_FORMATS: &[ArmFormat] = &[
+    ArmFormat {
+        name: "BX",
+        mask: 0x0FFFFFF0,
+        value: 0x012FFF10,
+        expected: |e| Instruction::BranchExchange { rm: reg(e, 0) },
+    },
+    ArmFormat {
+        name: "MUL/MLA",
+        mask: 0x0FC000F0,
+        value: 0x00000090,
+        expected: |e| Instruction::Multiply {
+            accumulate: bit(e, 21),
+            set_flags: bit(e, 20),
+            rd: reg(e, 16),
+            rn: reg(e, 12),
+            rs: reg(e, 8),
+            rm: reg(e, 0),
+        },
+    },
+    ArmFormat {
+        name: "UMULL/UMLAL/SMULL/SMLAL",
+        mask: 0x0F8000F0,
+        value: 0x00800090,
+        expected: |e| Instruction::MultiplyLong {
+            signed: bit(e, 22),
+            accumulate: bit(e, 21),
+            set_flags: bit(e, 20),
+            rd_hi: reg(e, 16),
+            rd_lo: reg(e, 12),
+            rs: reg(e, 8),
+            rm: reg(e, 0),
+        },
+    },
+    ArmFormat {
+        name: "SWP/SWPB",
+        mask: 0x0FB000F0,
+        value: 0x01000090,
+        expected: |e| Instruction::SingleSwap {
+            byte: bit(e, 22),
+            rn: reg(e, 16),
+            rd: reg(e, 12),
+            rm: reg(e, 0),
+        },
+    },
+    ArmFormat {
+        name: "LDRH/STRH register offset",
+        mask: 0x0E4000F0,
+        value: 0x000000B0,
+        expected: |e| {
+            let offset = TransferOffset::Register {
+                rm: reg(e, 0),
+                shift: ShiftType::Lsl,
+                amount: 0,
+            };
+            halfword_transfer(e, offset)
+        },
+    },
+    ArmFormat {
+        name: "LDRSB/LDRSH register offset",
+        mask: 0x0E5000D0,
+        value: 0x001000D0,
+        expected: |e| {
+            let offset = TransferOffset::Register {
+                rm: reg(e, 0),
+                shift: ShiftType::Lsl,
+                amount: 0,
+            };
+            halfword_transfer(e, offset)
+        },
+    },
+    ArmFormat {
+        name: "LDRH/STRH immediate offset",
+        mask: 0x0E4000F0,
+        value: 0x004000B0,
+        expected: |e| {
+            let offset = TransferOffset::Immediate((bits(e, 8, 4) << 4) | bits(e, 0, 4));
+            halfword_transfer(e, offset)
+        },
+    },
+    ArmFormat {
+        name: "LDRSB/LDRSH immediate offset",
+        mask: 0x0E5000D0,
+        value: 0x005000D0,
+        expected: |e| {
+            let offset = TransferOffset::Immediate((bits(e, 8, 4) << 4) | bits(e, 0, 4));
+            halfword_transfer(e, offset)
+        },
+    },
+    ArmFormat {
+        name: "unallocated multiply/swap/halfword encodings",
+        mask: 0x0E000090,
+        value: 0x00000090,
+        expected: |_| Instruction::Undefined,
+    },
+    ArmFormat {
+        name: "MRS",
+        mask: 0x0FB000F0,
+        value: 0x01000000,
+        expected: |e| Instruction::Mrs {
+            use_spsr: bit(e, 22),
+            rd: reg(e, 12),
+        },
+    },
+    ArmFormat {
+        name: "MSR register",
+        mask: 0x0FB000F0,
+        value: 0x01200000,
+        expected: |e| Instruction::Msr {
+            use_spsr: bit(e, 22),
+            field_mask: bits(e, 16, 4),
+            operand: PsrOperand::Register(reg(e, 0)),
+        },
+    },
+    ArmFormat {
+        name: "MSR immediate",
+        mask: 0x0FB00000,
+        value: 0x03200000,
+        expected: |e| Instruction::Msr {
+            use_spsr: bit(e, 22),
+            field_mask: bits(e, 16, 4),
+            operand: PsrOperand::Immediate(bits(e, 0, 8).rotate_right(bits(e, 8, 4) * 2)),
+        },
+    },
+    ArmFormat {
+        name: "unallocated PSR transfer encodings",
+        mask: 0x0D900000,
+        value: 0x01000000,
+        expected: |_| Instruction::Undefined,
+    },
+    ArmFormat {
+        name: "data processing register operand",
+        mask: 0x0E000000,
+        value: 0x00000000,
+        expected: |e| {
+            let amount = if bit(e, 4) {
+                ShiftAmount::Register(reg(e, 8))
+            } else {
+                ShiftAmount::Immediate(bits(e, 7, 5))
+            };
+            let operand = ShifterOperand::Register {
+                rm: reg(e, 0),
+                shift: ShiftType::from_u32(bits(e, 5, 2)),
+                amount,
+            };
+            data_proc(e, operand)
+        },
+    },
+    ArmFormat {
+        name: "data processing immediate operand",
+        mask: 0x0E000000,
+        value: 0x02000000,
+        expected: |e| {
+            let operand = ShifterOperand::Immediate {
+                imm: bits(e, 0, 8),
+                rotate: bits(e, 8, 4) * 2,
+            };
+            data_proc(e, operand)
+        },
+    },
+    ArmFormat {
+        name: "undefined",
+        mask: 0x0E000010,
+        value: 0x06000010,
+        expected: |_| Instruction::Undefined,
+    },
+    ArmFormat {
+        name: "LDR/STR",
+        mask: 0x0C000000,
+        value: 0x04000000,
+        expected: |e| Instruction::SingleTransfer {
+            load: bit(e, 20),
+            byte: bit(e, 22),
+            rn: reg(e, 16),
+            rd: reg(e, 12),
+            indexing: indexing(e),
+            offset: if bit(e, 25) {
+                TransferOffset::Register {
+                    rm: reg(e, 0),
+                    shift: ShiftType::from_u32(bits(e, 5, 2)),
+                    amount: bits(e, 7, 5),
+                }
+            } else {
+                TransferOffset::Immediate(bits(e, 0, 12))
+            },
+        },
+    },
+    ArmFormat {
+        name: "LDM/STM",
+        mask: 0x0E000000,
+        value: 0x08000000,
+        expected: |e| Instruction::BlockTransfer {
+            load: bit(e, 20),
+            psr_force_user: bit(e, 22),
+            rn: reg(e, 16),
+            register_list: bits(e, 0, 16) as u16,
+            indexing: indexing(e),
+        },
+    },
+    ArmFormat {
+        name: "B/BL",
+        mask: 0x0E000000,
+        value: 0x0A000000,
+        expected: |e| Instruction::Branch {
+            link: bit(e, 24),
+            offset: sign_extend(bits(e, 0, 24), 24) * 4,
+        },
+    },
+    ArmFormat {
+        name: "LDC/STC",
+        mask: 0x0E000000,
+        value: 0x0C000000,
+        expected: |e| Instruction::CoprocDataTransfer {
+            load: bit(e, 20),
+            long: bit(e, 22),
+            cp_num: bits(e, 8, 4),
+            crd: bits(e, 12, 4),
+            rn: reg(e, 16),
+            indexing: indexing(e),
+            offset: bits(e, 0, 8) * 4,
+        },
+    },
+    ArmFormat {
+        name: "CDP",
+        mask: 0x0F000010,
+        value: 0x0E000000,
+        expected: |e| Instruction::CoprocOperation {
+            cp_opcode: bits(e, 20, 4),
+            cp_num: bits(e, 8, 4),
+            crd: bits(e, 12, 4),
+            crn: bits(e, 16, 4),
+            crm: bits(e, 0, 4),
+            cp_info: bits(e, 5, 3),
+        },
+    },
+    ArmFormat {
+        name: "MRC/MCR",
+        mask: 0x0F000010,
+        value: 0x0E000010,
+        expected: |e| Instruction::CoprocRegTransfer {
+            load: bit(e, 20),
+            cp_opcode: bits(e, 21, 3),
+            cp_num: bits(e, 8, 4),
+            rd: reg(e, 12),
+            crn: bits(e, 16, 4),
+            crm: bits(e, 0, 4),
+            cp_info: bits(e, 5, 3),
+        },
+    },
+    ArmFormat {
+        name: "SWI",
+        mask: 0x0F000000,
+        value: 0x0F000000,
+        expected: |e| Instruction::SoftwareInterrupt {
+            comment: bits(e, 0, 24),
+        },
+    },
+];
+
+// A small xorshift generator, so the ARM sample set is the same on every run
+struct SampleGenerator(u32);
+
+impl SampleGenerator {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+#[test]
+fn arm_sample_set_matches_format_table() {
+    let mut generator = SampleGenerator(0x2545F491);
+    let mut hits = vec![0; ARM_FORMATS.len()];
+
+    // Every combination of bits 27-20 and 7-4, which together select the format, with the other
+    // bits filled in randomly
+    for selector in 0..(1 << 12) {
+        for sample in 0..16 {
+            let mut encoding =
+                (generator.next() & 0xF00FFF0F) | ((selector >> 4) << 20) | ((selector & 0xF) << 4);
+            // Some formats, like BX, are only picked out by fixed values in the other bits
+            if sample % 4 == 0 {
+                encoding |= 0x000FFF00;
+            }
+
+            let (i, format) = ARM_FORMATS
+                .iter()
+                .enumerate()
+                .find(|(_, f)| encoding & f.mask == f.value)
+                .unwrap_or_else(|| panic!("{:08X} matched no format", encoding));
+            hits[i] += 1;
+
+            let expected = (
+                Condition::from_u8((encoding >> 28) as u8),
+                (format.expected)(encoding),
+            );
+            assert_eq!(
+                Instruction::decode_arm(encoding),
+                expected,
+                "{:08X} ({})",
+                encoding,
+                format.name
+            );
+        }
+    }
+
+    for (format, hits) in ARM_FORMATS.iter().zip(hits) {
+        assert!(hits > 0, "no samples for {}", format.name);
+    }
+}
+
+// Encodings assembled with an ARMv4T assembler
+#[test]
+fn arm_assembled_vectors() {
+    let reg_operand = |rm, shift, amount| ShifterOperand::Register { rm, shift, amount };
+    let pre_index = Indexing {
+        pre_index: true,
+        up: true,
+        write_back: false,
+    };
+    let vectors = [
+        (
+            0xE3A00001, // mov r0, #1
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Mov,
+                set_flags: false,
+                rn: 0,
+                rd: 0,
+                operand: ShifterOperand::Immediate { imm: 1, rotate: 0 },
+            },
+        ),
+        (
+            0xE0921203, // adds r1, r2, r3, lsl #4
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Add,
+                set_flags: true,
+                rn: 2,
+                rd: 1,
+                operand: reg_operand(3, ShiftType::Lsl, ShiftAmount::Immediate(4)),
+            },
+        ),
+        (
+            0xE0454756, // sub r4, r5, r6, asr r7
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Sub,
+                set_flags: false,
+                rn: 5,
+                rd: 4,
+                operand: reg_operand(6, ShiftType::Asr, ShiftAmount::Register(7)),
+            },
+        ),
+        (
+            0xE1100001, // tst r0, r1
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Tst,
+                set_flags: true,
+                rn: 0,
+                rd: 0,
+                operand: reg_operand(1, ShiftType::Lsl, ShiftAmount::Immediate(0)),
+            },
+        ),
+        (
+            0xE10F0000, // mrs r0, cpsr
+            Instruction::Mrs {
+                use_spsr: false,
+                rd: 0,
+            },
+        ),
+        (
+            0xE14F1000, // mrs r1, spsr
+            Instruction::Mrs {
+                use_spsr: true,
+                rd: 1,
+            },
+        ),
+        (
+            0xE129F000, // msr cpsr_fc, r0
+            Instruction::Msr {
+                use_spsr: false,
+                field_mask: 0b1001,
+                operand: PsrOperand::Register(0),
+            },
+        ),
+        (
+            0xE368F20F, // msr spsr_f, #0xF0000000
+            Instruction::Msr {
+                use_spsr: true,
+                field_mask: 0b1000,
+                operand: PsrOperand::Immediate(0xF0000000),
+            },
+        ),
+        (
+            0xE0000291, // mul r0, r1, r2
+            Instruction::Multiply {
+                accumulate: false,
+                set_flags: false,
+                rd: 0,
+                rn: 0,
+                rs: 2,
+                rm: 1,
+            },
+        ),
+        (
+            0xE0336594, // mlas r3, r4, r5, r6
+            Instruction::Multiply {
+                accumulate: true,
+                set_flags: true,
+                rd: 3,
+                rn: 6,
+                rs: 5,
+                rm: 4,
+            },
+        ),
+        (
+            0xE0810392, // umull r0, r1, r2, r3
+            Instruction::MultiplyLong {
+                signed: false,
+                accumulate: false,
+                set_flags: false,
+                rd_hi: 1,
+                rd_lo: 0,
+                rs: 3,
+                rm: 2,
+            },
+        ),
+        (
+            0xE0F54796, // smlals r4, r5, r6, r7
+            Instruction::MultiplyLong {
+                signed: true,
+                accumulate: true,
+                set_flags: true,
+                rd_hi: 5,
+                rd_lo: 4,
+                rs: 7,
+                rm: 6,
+            },
+        ),
+        (
+            0xE1453094, // swpb r3, r4, [r5]
+            Instruction::SingleSwap {
+                byte: true,
+                rn: 5,
+                rd: 3,
+                rm: 4,
+            },
+        ),
+        (0xE12FFF1E, Instruction::BranchExchange { rm: 14 }), // bx lr
+        (
+            0xE17100B6, // ldrh r0, [r1, #-6]!
+            Instruction::HalfwordTransfer {
+                load: true,
+                kind: HalfwordKind::UnsignedHalfword,
+                rn: 1,
+                rd: 0,
+                indexing: Indexing {
+                    pre_index: true,
+                    up: false,
+                    write_back: true,
+                },
+                offset: TransferOffset::Immediate(6),
+            },
+        ),
+        (
+            0xE08320B4, // strh r2, [r3], r4
+            Instruction::HalfwordTransfer {
+                load: false,
+                kind: HalfwordKind::UnsignedHalfword,
+                rn: 3,
+                rd: 2,
+                indexing: Indexing {
+                    pre_index: false,
+                    up: true,
+                    write_back: false,
+                },
+                offset: TransferOffset::Register {
+                    rm: 4,
+                    shift: ShiftType::Lsl,
+                    amount: 0,
+                },
+            },
+        ),
+        (
+            0xE11870F9, // ldrsh r7, [r8, -r9]
+            Instruction::HalfwordTransfer {
+                load: true,
+                kind: HalfwordKind::SignedHalfword,
+                rn: 8,
+                rd: 7,
+                indexing: Indexing {
+                    pre_index: true,
+                    up: false,
+                    write_back: false,
+                },
+                offset: TransferOffset::Register {
+                    rm: 9,
+                    shift: ShiftType::Lsl,
+                    amount: 0,
+                },
+            },
+        ),
+        (
+            0xE5910004, // ldr r0, [r1, #4]
+            Instruction::SingleTransfer {
+                load: true,
+                byte: false,
+                rn: 1,
+                rd: 0,
+                indexing: pre_index,
+                offset: TransferOffset::Immediate(4),
+            },
+        ),
+        (
+            0xE7B54106, // ldr r4, [r5, r6, lsl #2]!
+            Instruction::SingleTransfer {
+                load: true,
+                byte: false,
+                rn: 5,
+                rd: 4,
+                indexing: Indexing {
+                    pre_index: true,
+                    up: true,
+                    write_back: true,
+                },
+                offset: TransferOffset::Register {
+                    rm: 6,
+                    shift: ShiftType::Lsl,
+                    amount: 2,
+                },
+            },
+        ),
+        (
+            0xE8BD80F0, // pop {r4-r7, pc}
+            Instruction::BlockTransfer {
+                load: true,
+                psr_force_user: false,
+                rn: 13,
+                register_list: 0x80F0,
+                indexing: Indexing {
+                    pre_index: false,
+                    up: true,
+                    write_back: true,
+                },
+            },
+        ),
+        (
+            0xE92D4001, // push {r0, lr}
+            Instruction::BlockTransfer {
+                load: false,
+                psr_force_user: false,
+                rn: 13,
+                register_list: 0x4001,
+                indexing: Indexing {
+                    pre_index: true,
+                    up: false,
+                    write_back: true,
+                },
+            },
+        ),
+        (
+            0xEA000000, // b .+8
+            Instruction::Branch {
+                link: false,
+                offset: 0,
+            },
+        ),
+        (
+            0xEBFFFFFD, // bl .-4
+            Instruction::Branch {
+                link: true,
+                offset: -12,
+            },
+        ),
+        (
+            0xEA3FFFFF, // b .+0x1000004, beyond the range of a 24-bit byte offset
+            Instruction::Branch {
+                link: false,
+                offset: 0xFFFFFC,
+            },
+        ),
+        (
+            0xEF060000,
+            Instruction::SoftwareInterrupt { comment: 0x60000 },
+        ), // swi #0x60000
+        (
+            0xEE2431C5, // cdp p1, 2, c3, c4, c5, 6
+            Instruction::CoprocOperation {
+                cp_opcode: 2,
+                cp_num: 1,
+                crd: 3,
+                crn: 4,
+                crm: 5,
+                cp_info: 6,
+            },
+        ),
+        (
+            0xEDB43204, // ldc p2, c3, [r4, #16]!
+            Instruction::CoprocDataTransfer {
+                load: true,
+                long: false,
+                cp_num: 2,
+                crd: 3,
+                rn: 4,
+                indexing: Indexing {
+                    pre_index: true,
+                    up: true,
+                    write_back: true,
+                },
+                offset: 16,
+            },
+        ),
+        (
+            0xEE010F10, // mcr p15, 0, r0, c1, c0, 0
+            Instruction::CoprocRegTransfer {
+                load: false,
+                cp_opcode: 0,
+                cp_num: 15,
+                rd: 0,
+                crn: 1,
+                crm: 0,
+                cp_info: 0,
+            },
+        ),
+        // ARMv5 and later additions are undefined on the ARM7TDMI
+        (0xE16F0F11, Instruction::Undefined), // clz r0, r1
+        (0xE12FFF30, Instruction::Undefined), // blx r0
+        (0xE1010052, Instruction::Undefined), // qadd r0, r2, r1
+        (0xE1C020F0, Instruction::Undefined), // strd r2, [r0]
+        (0xE0400090, Instruction::Undefined), // umaal r0, r0, r0, r0
+        (0xE3000000, Instruction::Undefined), // movw r0, #0
+        (0xE7F000F0, Instruction::Undefined), // udf #0
+    ];
+
+    for (encoding, instruction) in vectors.iter() {
+        assert_eq!(
+            Instruction::decode_arm(*encoding),
+            (Condition::AL, *instruction),
+            "{:08X}",
+            encoding
+        );
+    }
+    assert_eq!(
+        Instruction::decode_arm(0x0A000000).0,
+        Condition::EQ,
+        "beq .+8"
+    );
+}
+
+fn arm(encoding: u32) -> (Condition, Instruction) {
+    Instruction::decode_arm(encoding)
+}
+
+fn thumb_bits(encoding: u16, lo: u32, len: u32) -> u32 {
+    bits(encoding as u32, lo, len)
+}
+
+struct ThumbFormat {
+    name: &'static str,
+    mask: u16,
+    value: u16,
+    // How many of the 2^16 encodings fall into this format
+    count: usize,
+    // Most formats are checked against the equivalent ARM instruction
+    expected: fn(u16) -> (Condition, Instruction),
+}
+
+// Ordered so that the first matching row wins
+const THUMB_FORMATS: &[ThumbFormat] = &[
+    ThumbFormat {
+        name: "ADD/SUB",
+        mask: 0xF800,
+        value: 0x1800,
+        count: 2048,
+        expected: |t| {
+            let (rd, rn) = (thumb_bits(t, 0, 3), thumb_bits(t, 3, 3));
+            let base = match thumb_bits(t, 9, 2) {
+                0b00 => 0xE0900000, // ADDS <Rd>, <Rn>, <Rm>
+                0b01 => 0xE0500000, // SUBS <Rd>, <Rn>, <Rm>
+                0b10 => 0xE2900000, // ADDS <Rd>, <Rn>, #<immed_3>
+                _ => 0xE2500000,    // SUBS <Rd>, <Rn>, #<immed_3>
+            };
+            arm(base | (rn << 16) | (rd << 12) | thumb_bits(t, 6, 3))
+        },
+    },
+    ThumbFormat {
+        name: "move shifted register",
+        mask: 0xE000,
+        value: 0x0000,
+        count: 6144,
+        expected: |t| {
+            // MOVS <Rd>, <Rm>, <shift> #<immed_5>
+            let (rd, rm) = (thumb_bits(t, 0, 3), thumb_bits(t, 3, 3));
+            let shift = thumb_bits(t, 11, 2);
+            arm(0xE1B00000 | (rd << 12) | (thumb_bits(t, 6, 5) << 7) | (shift << 5) | rm)
+        },
+    },
+    ThumbFormat {
+        name: "move/compare/add/subtract immediate",
+        mask: 0xE000,
+        value: 0x2000,
+        count: 8192,
+        expected: |t| {
+            let (rd, imm) = (thumb_bits(t, 8, 3), thumb_bits(t, 0, 8));
+            arm(match thumb_bits(t, 11, 2) {
+                0b00 => 0xE3B00000 | (rd << 12) | imm,              // MOVS
+                0b01 => 0xE3500000 | (rd << 16) | imm,              // CMP
+                0b10 => 0xE2900000 | (rd << 16) | (rd << 12) | imm, // ADDS
+                _ => 0xE2500000 | (rd << 16) | (rd << 12) | imm,    // SUBS
+            })
+        },
+    },
+    ThumbFormat {
+        name: "ALU operations",
+        mask: 0xFC00,
+        value: 0x4000,
+        count: 1024,
+        expected: |t| {
+            let (rd, rs) = (thumb_bits(t, 0, 3), thumb_bits(t, 3, 3));
+            let binary = |base: u32| base | (rd << 16) | (rd << 12) | rs;
+            let shift =
+                |shift_type: u32| 0xE1B00010 | (rd << 12) | (rs << 8) | (shift_type << 5) | rd;
+            arm(match thumb_bits(t, 6, 4) {
+                0b0000 => binary(0xE0100000),                       // ANDS
+                0b0001 => binary(0xE0300000),                       // EORS
+                0b0010 => shift(0b00),                              // MOVS <Rd>, <Rd>, LSL <Rs>
+                0b0011 => shift(0b01),                              // MOVS <Rd>, <Rd>, LSR <Rs>
+                0b0100 => shift(0b10),                              // MOVS <Rd>, <Rd>, ASR <Rs>
+                0b0101 => binary(0xE0B00000),                       // ADCS
+                0b0110 => binary(0xE0D00000),                       // SBCS
+                0b0111 => shift(0b11),                              // MOVS <Rd>, <Rd>, ROR <Rs>
+                0b1000 => 0xE1100000 | (rd << 16) | rs,             // TST
+                0b1001 => 0xE2700000 | (rs << 16) | (rd << 12),     // RSBS <Rd>, <Rs>, #0
+                0b1010 => 0xE1500000 | (rd << 16) | rs,             // CMP
+                0b1011 => 0xE1700000 | (rd << 16) | rs,             // CMN
+                0b1100 => binary(0xE1900000),                       // ORRS
+                0b1101 => 0xE0100090 | (rd << 16) | (rd << 8) | rs, // MULS <Rd>, <Rs>, <Rd>
+                0b1110 => binary(0xE1D00000),                       // BICS
+                _ => 0xE1F00000 | (rd << 12) | rs,                  // MVNS
+            })
+        },
+    },
+    ThumbFormat {
+        name: "high register operations/BX",
+        mask: 0xFC00,
+        value: 0x4400,
+        count: 1024,
+        expected: |t| {
+            let rd = (thumb_bits(t, 7, 1) << 3) | thumb_bits(t, 0, 3);
+            let rm = thumb_bits(t, 3, 4);
+            arm(match thumb_bits(t, 8, 2) {
+                0b00 => 0xE0800000 | (rd << 16) | (rd << 12) | rm, // ADD
+                0b01 => 0xE1500000 | (rd << 16) | rm,              // CMP
+                0b10 => 0xE1A00000 | (rd << 12) | rm,              // MOV
+                _ => 0xE12FFF10 | rm,                              // BX
+            })
+        },
+    },
+    ThumbFormat {
+        name: "PC-relative load",
+        mask: 0xF800,
+        value: 0x4800,
+        count: 2048,
+        expected: |t| arm(0xE59F0000 | (thumb_bits(t, 8, 3) << 12) | (thumb_bits(t, 0, 8) << 2)),
+    },
+    ThumbFormat {
+        name: "load/store with register offset",
+        mask: 0xF000,
+        value: 0x5000,
+        count: 4096,
+        expected: |t| {
+            let (rd, rb, ro) = (
+                thumb_bits(t, 0, 3),
+                thumb_bits(t, 3, 3),
+                thumb_bits(t, 6, 3),
+            );
+            let base = match thumb_bits(t, 9, 3) {
+                0b000 => 0xE7800000, // STR
+                0b001 => 0xE18000B0, // STRH
+                0b010 => 0xE7C00000, // STRB
+                0b011 => 0xE19000D0, // LDRSB
+                0b100 => 0xE7900000, // LDR
+                0b101 => 0xE19000B0, // LDRH
+                0b110 => 0xE7D00000, // LDRB
+                _ => 0xE19000F0,     // LDRSH
+            };
+            arm(base | (rb << 16) | (rd << 12) | ro)
+        },
+    },
+    ThumbFormat {
+        name: "load/store with immediate offset",
+        mask: 0xE000,
+        value: 0x6000,
+        count: 8192,
+        expected: |t| {
+            let (byte, load) = (thumb_bits(t, 12, 1), thumb_bits(t, 11, 1));
+            let offset = thumb_bits(t, 6, 5) << if byte == 1 { 0 } else { 2 };
+            let (rd, rb) = (thumb_bits(t, 0, 3), thumb_bits(t, 3, 3));
+            arm(0xE5800000 | (byte << 22) | (load << 20) | (rb << 16) | (rd << 12) | offset)
+        },
+    },
+    ThumbFormat {
+        name: "load/store halfword",
+        mask: 0xF000,
+        value: 0x8000,
+        count: 4096,
+        expected: |t| {
+            let load = thumb_bits(t, 11, 1);
+            let offset = thumb_bits(t, 6, 5) << 1;
+            let (rd, rb) = (thumb_bits(t, 0, 3), thumb_bits(t, 3, 3));
+            arm(0xE1C000B0
+                | (load << 20)
+                | (rb << 16)
+                | (rd << 12)
+                | ((offset >> 4) << 8)
+                | (offset & 0xF))
+        },
+    },
+    ThumbFormat {
+        name: "SP-relative load/store",
+        mask: 0xF000,
+        value: 0x9000,
+        count: 4096,
+        expected: |t| {
+            let (load, rd) = (thumb_bits(t, 11, 1), thumb_bits(t, 8, 3));
+            arm(0xE58D0000 | (load << 20) | (rd << 12) | (thumb_bits(t, 0, 8) << 2))
+        },
+    },
+    ThumbFormat {
+        name: "load address",
+        mask: 0xF000,
+        value: 0xA000,
+        count: 4096,
+        expected: |t| {
+            // ADD <Rd>, PC|SP, #<immed_8> * 4, encoded as an immediate rotated right by 30
+            let rn = if thumb_bits(t, 11, 1) == 1 { 13 } else { 15 };
+            arm(0xE2800F00 | (rn << 16) | (thumb_bits(t, 8, 3) << 12) | thumb_bits(t, 0, 8))
+        },
+    },
+    ThumbFormat {
+        name: "add offset to stack pointer",
+        mask: 0xFF00,
+        value: 0xB000,
+        count: 256,
+        expected: |t| {
+            let base = if thumb_bits(t, 7, 1) == 1 {
+                0xE24DDF00 // SUB SP, SP, #<immed_7> * 4
+            } else {
+                0xE28DDF00 // ADD SP, SP, #<immed_7> * 4
+            };
+            arm(base | thumb_bits(t, 0, 7))
+        },
+    },
+    ThumbFormat {
+        name: "push/pop registers",
+        mask: 0xF600,
+        value: 0xB400,
+        count: 1024,
+        expected: |t| {
+            let (list, r) = (thumb_bits(t, 0, 8), thumb_bits(t, 8, 1));
+            arm(if thumb_bits(t, 11, 1) == 1 {
+                0xE8BD0000 | list | (r << 15) // LDMIA SP!, {<list>, PC}
+            } else {
+                0xE92D0000 | list | (r << 14) // STMDB SP!, {<list>, LR}
+            })
+        },
+    },
+    ThumbFormat {
+        name: "multiple load/store",
+        mask: 0xF000,
+        value: 0xC000,
+        count: 4096,
+        expected: |t| {
+            let (load, rb) = (thumb_bits(t, 11, 1), thumb_bits(t, 8, 3));
+            arm(0xE8A00000 | (load << 20) | (rb << 16) | thumb_bits(t, 0, 8))
+        },
+    },
+    ThumbFormat {
+        name: "undefined conditional branch",
+        mask: 0xFF00,
+        value: 0xDE00,
+        count: 256,
+        expected: |_| (Condition::AL, Instruction::Undefined),
+    },
+    ThumbFormat {
+        name: "software interrupt",
+        mask: 0xFF00,
+        value: 0xDF00,
+        count: 256,
+        expected: |t| {
+            let comment = thumb_bits(t, 0, 8);
+            (Condition::AL, Instruction::SoftwareInterrupt { comment })
+        },
+    },
+    ThumbFormat {
+        name: "conditional branch",
+        mask: 0xF000,
+        value: 0xD000,
+        count: 3584,
+        expected: |t| {
+            let condition = Condition::from_u8(thumb_bits(t, 8, 4) as u8);
+            let offset = sign_extend(thumb_bits(t, 0, 8), 8) * 2;
+            let link = false;
+            (condition, Instruction::Branch { link, offset })
+        },
+    },
+    ThumbFormat {
+        name: "unconditional branch",
+        mask: 0xF800,
+        value: 0xE000,
+        count: 2048,
+        expected: |t| {
+            let offset = sign_extend(thumb_bits(t, 0, 11), 11) * 2;
+            let link = false;
+            (Condition::AL, Instruction::Branch { link, offset })
+        },
+    },
+    ThumbFormat {
+        name: "long branch with link, first half",
+        mask: 0xF800,
+        value: 0xF000,
+        count: 2048,
+        expected: |t| {
+            let offset = sign_extend(thumb_bits(t, 0, 11), 11) << 12;
+            (Condition::AL, Instruction::ThumbBranchPrefix { offset })
+        },
+    },
+    ThumbFormat {
+        name: "long branch with link, second half",
+        mask: 0xF800,
+        value: 0xF800,
+        count: 2048,
+        expected: |t| {
+            let offset = thumb_bits(t, 0, 11) << 1;
+            (Condition::AL, Instruction::ThumbBranchSuffix { offset })
+        },
+    },
+    ThumbFormat {
+        // The BLX suffix (0xE800) and the miscellaneous encodings added after ARMv4T
+        name: "undefined",
+        mask: 0x0000,
+        value: 0x0000,
+        count: 4864,
+        expected: |_| (Condition::AL, Instruction::Undefined),
+    },
+];
+
+#[test]
+fn all_thumb_encodings_match_format_table() {
+    let mut counts = vec![0; THUMB_FORMATS.len()];
+
+    for encoding in 0..=0xFFFF {
+        let (i, format) = THUMB_FORMATS
+            .iter()
+            .enumerate()
+            .find(|(_, f)| encoding & f.mask == f.value)
+            .unwrap();
+        counts[i] += 1;
+
+        assert_eq!(
+            Instruction::decode_thumb(encoding),
+            (format.expected)(encoding),
+            "{:04X} ({})",
+            encoding,
+            format.name
+        );
+    }
+
+    for (format, count) in THUMB_FORMATS.iter().zip(counts) {
+        assert_eq!(format.count, count, "{}", format.name);
+    }
+}
+
+// Encodings assembled with an ARMv4T assembler, checked directly rather than through ARM
+#[test]
+fn thumb_assembled_vectors() {
+    let reg_operand = |rm| ShifterOperand::Register {
+        rm,
+        shift: ShiftType::Lsl,
+        amount: ShiftAmount::Immediate(0),
+    };
+    let offset_indexing = Indexing {
+        pre_index: true,
+        up: true,
+        write_back: false,
+    };
+    let vectors = [
+        (
+            0x0148, // lsls r0, r1, #5
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Mov,
+                set_flags: true,
+                rn: 0,
+                rd: 0,
+                operand: ShifterOperand::Register {
+                    rm: 1,
+                    shift: ShiftType::Lsl,
+                    amount: ShiftAmount::Immediate(5),
+                },
+            },
+        ),
+        (
+            0x081A, // lsrs r2, r3, #32
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Mov,
+                set_flags: true,
+                rn: 0,
+                rd: 2,
+                operand: ShifterOperand::Register {
+                    rm: 3,
+                    shift: ShiftType::Lsr,
+                    amount: ShiftAmount::Immediate(0),
+                },
+            },
+        ),
+        (
+            0x1FE3, // subs r3, r4, #7
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Sub,
+                set_flags: true,
+                rn: 4,
+                rd: 3,
+                operand: ShifterOperand::Immediate { imm: 7, rotate: 0 },
+            },
+        ),
+        (
+            0x2E01, // cmp r6, #1
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Cmp,
+                set_flags: true,
+                rn: 6,
+                rd: 0,
+                operand: ShifterOperand::Immediate { imm: 1, rotate: 0 },
+            },
+        ),
+        (
+            0x425A, // rsbs r2, r3, #0
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Rsb,
+                set_flags: true,
+                rn: 3,
+                rd: 2,
+                operand: ShifterOperand::Immediate { imm: 0, rotate: 0 },
+            },
+        ),
+        (
+            0x436C, // muls r4, r5, r4
+            Instruction::Multiply {
+                accumulate: false,
+                set_flags: true,
+                rd: 4,
+                rn: 0,
+                rs: 4,
+                rm: 5,
+            },
+        ),
+        (
+            0x4488, // add r8, r1
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Add,
+                set_flags: false,
+                rn: 8,
+                rd: 8,
+                operand: reg_operand(1),
+            },
+        ),
+        (
+            0x46F7, // mov pc, lr
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Mov,
+                set_flags: false,
+                rn: 0,
+                rd: 15,
+                operand: reg_operand(14),
+            },
+        ),
+        (0x4770, Instruction::BranchExchange { rm: 14 }), // bx lr
+        (
+            0x4804, // ldr r0, [pc, #16]
+            Instruction::SingleTransfer {
+                load: true,
+                byte: false,
+                rn: 15,
+                rd: 0,
+                indexing: offset_indexing,
+                offset: TransferOffset::Immediate(16),
+            },
+        ),
+        (
+            0x57AC, // ldrsb r4, [r5, r6]
+            Instruction::HalfwordTransfer {
+                load: true,
+                kind: HalfwordKind::SignedByte,
+                rn: 5,
+                rd: 4,
+                indexing: offset_indexing,
+                offset: TransferOffset::Register {
+                    rm: 6,
+                    shift: ShiftType::Lsl,
+                    amount: 0,
+                },
+            },
+        ),
+        (
+            0x7FC8, // ldrb r0, [r1, #31]
+            Instruction::SingleTransfer {
+                load: true,
+                byte: true,
+                rn: 1,
+                rd: 0,
+                indexing: offset_indexing,
+                offset: TransferOffset::Immediate(31),
+            },
+        ),
+        (
+            0x8FDA, // ldrh r2, [r3, #62]
+            Instruction::HalfwordTransfer {
+                load: true,
+                kind: HalfwordKind::UnsignedHalfword,
+                rn: 3,
+                rd: 2,
+                indexing: offset_indexing,
+                offset: TransferOffset::Immediate(62),
+            },
+        ),
+        (
+            0x94FF, // str r4, [sp, #1020]
+            Instruction::SingleTransfer {
+                load: false,
+                byte: false,
+                rn: 13,
+                rd: 4,
+                indexing: offset_indexing,
+                offset: TransferOffset::Immediate(1020),
+            },
+        ),
+        (
+            0xAD02, // add r5, sp, #8
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Add,
+                set_flags: false,
+                rn: 13,
+                rd: 5,
+                operand: ShifterOperand::Immediate { imm: 2, rotate: 30 },
+            },
+        ),
+        (
+            0xB083, // sub sp, #12
+            Instruction::DataProcessing {
+                opcode: AluOpcode::Sub,
+                set_flags: false,
+                rn: 13,
+                rd: 13,
+                operand: ShifterOperand::Immediate { imm: 3, rotate: 30 },
+            },
+        ),
+        (
+            0xB530, // push {r4, r5, lr}
+            Instruction::BlockTransfer {
+                load: false,
+                psr_force_user: false,
+                rn: 13,
+                register_list: 0x4030,
+                indexing: Indexing {
+                    pre_index: true,
+                    up: false,
+                    write_back: true,
+                },
+            },
+        ),
+        (
+            0xBD01, // pop {r0, pc}
+            Instruction::BlockTransfer {
+                load: true,
+                psr_force_user: false,
+                rn: 13,
+                register_list: 0x8001,
+                indexing: Indexing {
+                    pre_index: false,
+                    up: true,
+                    write_back: true,
+                },
+            },
+        ),
+        (0xDF05, Instruction::SoftwareInterrupt { comment: 5 }), // swi #5
+        (
+            0xE7FE, // b .
+            Instruction::Branch {
+                link: false,
+                offset: -4,
+            },
+        ),
+        // bl .+0x1000, as a pair of halves
+        (0xF000, Instruction::ThumbBranchPrefix { offset: 0 }),
+        (0xFFFE, Instruction::ThumbBranchSuffix { offset: 0xFFC }),
+        // bl .-0x400000, the furthest back a BL can reach
+        (0xF400, Instruction::ThumbBranchPrefix { offset: -0x400000 }),
+        (0xB100, Instruction::Undefined), // cbz r0, . (ARMv6T2)
+        (0xBE00, Instruction::Undefined), // bkpt #0 (ARMv5)
+        (0xE800, Instruction::Undefined), // blx suffix (ARMv5)
+    ];
+
+    for (encoding, instruction) in vectors.iter() {
+        assert_eq!(
+            Instruction::decode_thumb(*encoding),
+            (Condition::AL, *instruction),
+            "{:04X}",
+            encoding
+        );
+    }
+
+    let beq = Instruction::Branch {
+        link: false,
+        offset: -8,
+    };
+    assert_eq!(Instruction::decode_thumb(0xD0FC), (Condition::EQ, beq));
+}
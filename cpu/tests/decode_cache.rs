@@ -0,0 +1,91 @@
+// Checks that instructions are decoded again after the memory holding them changes
+mod common;
+
+use cpu::CPU;
+use memory::Memory;
+
+use std::collections::BTreeMap;
+
+// Runs from the given address until reaching another
+fn run(cpu: &mut CPU, from: u32, until: u32) {
+    cpu.write_register(15, from);
+    for _ in 0..100 {
+        if cpu.pc() == until {
+            return;
+        }
+        cpu.tick();
+    }
+    panic!("didn't reach {:08X}", until);
+}
+
+#[test]
+fn iwram_stores() {
+    let program = [
+        0xE3A04001, // 03000000: mov r4, #0x1
+        0xE59F1008, // 03000004: ldr r1, [pc, #0x8]
+        0xE50F1010, // 03000008: str r1, [pc, #-0x10]
+        0xEAFFFFFE, // 0300000C: b .
+        0xE1A00000, // 03000010: mov r0, r0
+        0xE3A04002, // 03000014: mov r4, #0x2
+    ];
+    let mut cpu = common::cpu();
+    cpu.skip_bios();
+    for (i, &word) in program.iter().enumerate() {
+        cpu.write_u32(0x03000000 + i * 4, word);
+    }
+    run(&mut cpu, 0x03000000, 0x0300000C);
+    assert_eq!(cpu.register(4), 1);
+
+    // The program replaced its first instruction, which runs the next time round
+    run(&mut cpu, 0x03000000, 0x03000004);
+    assert_eq!(cpu.register(4), 2);
+}
+
+#[test]
+fn rom_patches_and_bios_swaps() {
+    let program = [
+        0xE3A04001, // 08000000: mov r4, #0x1
+        0xEAFFFFFE, // 08000004: b .
+    ];
+    let mut cpu = common::boot_rom(&program);
+    run(&mut cpu, 0x08000000, 0x08000004);
+    assert_eq!(cpu.register(4), 1);
+
+    // Patching the low halfword gives `mov r4, #0x2`, until the patch is undone
+    cpu.set_rom_patches(&BTreeMap::from([(0x08000000, 0x4002)]));
+    run(&mut cpu, 0x08000000, 0x08000004);
+    assert_eq!(cpu.register(4), 2);
+    cpu.set_rom_patches(&BTreeMap::new());
+    run(&mut cpu, 0x08000000, 0x08000004);
+    assert_eq!(cpu.register(4), 1);
+
+    // `mov r4, #0x3`, then the same program, run from the BIOS
+    cpu.flash_bios(vec![0x03, 0x40, 0xA0, 0xE3, 0xFE, 0xFF, 0xFF, 0xEA]);
+    run(&mut cpu, 0x00000000, 0x00000004);
+    assert_eq!(cpu.register(4), 3);
+    cpu.flash_bios(program.iter().flat_map(|word| word.to_le_bytes()).collect());
+    run(&mut cpu, 0x00000000, 0x00000004);
+    assert_eq!(cpu.register(4), 1);
+}
+
+#[test]
+fn arm_and_thumb_at_the_same_address() {
+    let mut cpu = common::cpu();
+    cpu.skip_bios();
+    // `mov r2, #0x2000000` in ARM, and `movs r4, #0x2` in Thumb
+    cpu.write_u32(0x03000000, 0xE3A02402);
+    let cpsr = cpu.cpsr();
+
+    run(&mut cpu, 0x03000000, 0x03000004);
+    assert_eq!((cpu.register(2), cpu.register(4)), (0x2000000, 0));
+
+    cpu.write_register(2, 0);
+    cpu.write_cpsr(cpsr | 0x20);
+    run(&mut cpu, 0x03000000, 0x03000002);
+    assert_eq!((cpu.register(2), cpu.register(4)), (0, 2));
+
+    cpu.write_register(4, 0);
+    cpu.write_cpsr(cpsr);
+    run(&mut cpu, 0x03000000, 0x03000004);
+    assert_eq!((cpu.register(2), cpu.register(4)), (0x2000000, 0));
+}
@@ -0,0 +1,115 @@
+use cpu::{disassemble_arm, disassemble_thumb};
+
+#[test]
+fn arm_disassembly() {
+    let vectors = [
+        (0xE3A00001, "mov r0, #0x1"),
+        (0xE0921203, "adds r1, r2, r3, lsl #4"),
+        (0xE0454756, "sub r4, r5, r6, asr r7"),
+        (0xE1B00062, "movs r0, r2, rrx"),
+        (0xE1A00020, "mov r0, r0, lsr #32"),
+        (0xE1100001, "tst r0, r1"),
+        (0x11A0F00E, "movne pc, lr"),
+        (0xE10F0000, "mrs r0, cpsr"),
+        (0xE14F1000, "mrs r1, spsr"),
+        (0xE129F000, "msr cpsr_fc, r0"),
+        (0xE368F20F, "msr spsr_f, #0xF0000000"),
+        (0xE0000291, "mul r0, r1, r2"),
+        (0xE0336594, "mlas r3, r4, r5, r6"),
+        (0xE0810392, "umull r0, r1, r2, r3"),
+        (0xE0F54796, "smlals r4, r5, r6, r7"),
+        (0xE1453094, "swpb r3, r4, [r5]"),
+        (0xE12FFF1E, "bx lr"),
+        (0xE17100B6, "ldrh r0, [r1, #-0x6]!"),
+        (0xE08320B4, "strh r2, [r3], r4"),
+        (0xE1D650D1, "ldrsb r5, [r6, #0x1]"),
+        (0xE11870F9, "ldrsh r7, [r8, -r9]"),
+        (0xE5910004, "ldr r0, [r1, #0x4]"),
+        (0xE4432001, "strb r2, [r3], #-0x1"),
+        (0xE7B54106, "ldr r4, [r5, r6, lsl #2]!"),
+        (0xE4B10000, "ldrt r0, [r1], #0x0"),
+        (0xE8B0001E, "ldmia r0!, {r1-r4}"),
+        (0xE8BD80F0, "ldmia sp!, {r4-r7, pc}"),
+        (0xE92D4001, "stmdb sp!, {r0, lr}"),
+        (0xE8D08000, "ldmia r0, {pc}^"),
+        (0x0A000000, "beq 0x08000008"),
+        (0xEBFFFFFD, "bl 0x07FFFFFC"),
+        (0xEF060000, "swi #0x60000"),
+        (0xEE2431C5, "cdp p1, 2, c3, c4, c5, 6"),
+        (0xEDB43204, "ldc p2, c3, [r4, #0x10]!"),
+        (0xEE010F10, "mcr p15, 0, r0, c1, c0, 0"),
+        (0xE7F000F0, ".word 0xE7F000F0"),
+    ];
+
+    for (encoding, expected) in vectors.iter() {
+        assert_eq!(
+            disassemble_arm(*encoding, 0x08000000),
+            *expected,
+            "{:08X}",
+            encoding
+        );
+    }
+}
+
+#[test]
+fn thumb_disassembly() {
+    let vectors = [
+        (0x0148, "lsl r0, r1, #5"),
+        (0x081A, "lsr r2, r3, #32"),
+        (0x1888, "add r0, r1, r2"),
+        (0x1FE3, "sub r3, r4, #0x7"),
+        (0x25C8, "mov r5, #0xC8"),
+        (0x2E01, "cmp r6, #0x1"),
+        (0x4008, "and r0, r1"),
+        (0x425A, "neg r2, r3"),
+        (0x436C, "mul r4, r5"),
+        (0x4488, "add r8, r1"),
+        (0x46F7, "mov pc, lr"),
+        (0x4770, "bx lr"),
+        (0x4804, "ldr r0, [pc, #0x10]"),
+        (0x52D1, "strh r1, [r2, r3]"),
+        (0x57AC, "ldrsb r4, [r5, r6]"),
+        (0x7FC8, "ldrb r0, [r1, #0x1F]"),
+        (0x8FDA, "ldrh r2, [r3, #0x3E]"),
+        (0x94FF, "str r4, [sp, #0x3FC]"),
+        (0xA004, "add r0, pc, #0x10"),
+        (0xAD02, "add r5, sp, #0x8"),
+        (0xB083, "sub sp, #0xC"),
+        (0xB530, "push {r4, r5, lr}"),
+        (0xBD01, "pop {r0, pc}"),
+        (0xC81E, "ldmia r0!, {r1-r4}"),
+        (0xDF05, "swi #0x5"),
+        (0xD0FC, "beq 0x07FFFFFC"),
+        (0xE7FE, "b 0x08000000"),
+        (0xDE00, ".hword 0xDE00"),
+        (0xE800, ".hword 0xE800"),
+    ];
+
+    for (encoding, expected) in vectors.iter() {
+        assert_eq!(
+            disassemble_thumb(*encoding, 0, 0x08000000),
+            *expected,
+            "{:04X}",
+            encoding
+        );
+    }
+}
+
+#[test]
+fn thumb_long_branch_with_link() {
+    assert_eq!(
+        disassemble_thumb(0xF000, 0xF91A, 0x08000000),
+        "bl 0x08000238"
+    );
+    assert_eq!(
+        disassemble_thumb(0xF7FF, 0xFFFE, 0x08001234),
+        "bl 0x08001234"
+    );
+
+    // Halves that aren't part of a pair are shown by what they do on their own
+    assert_eq!(
+        disassemble_thumb(0xF7FF, 0x0000, 0x08000000),
+        "add lr, pc, #-0x1000"
+    );
+    assert_eq!(disassemble_thumb(0xF91A, 0, 0x08000000), "bl lr+0x234");
+}
@@ -0,0 +1,335 @@
+// Test vectors for the high-level emulation of the BIOS functions, with the expected results
+// worked out by hand from the BIOS's documented behaviour
+mod common;
+
+use cpu::CPU;
+use memory::Memory;
+
+// Calls a BIOS function with the given arguments in r0-r3, after writing the given data to memory
+fn call(function: u8, args: &[u32], inputs: &[(u32, &[u8])]) -> CPU {
+    let program = [
+        0xEF000000 | (function as u32) << 16, // 08000000: swi function
+        0xEAFFFFFE,                           // 08000004: b .
+    ];
+    let mut cpu = common::boot_rom(&program);
+    for &(addr, data) in inputs {
+        for (i, &byte) in data.iter().enumerate() {
+            cpu.write(addr as usize + i, byte);
+        }
+    }
+    for (n, &arg) in args.iter().enumerate() {
+        cpu.write_register(n, arg);
+    }
+
+    for _ in 0..10 {
+        if cpu.pc() == 0x08000004 {
+            return cpu;
+        }
+        cpu.tick();
+    }
+    panic!("SWI {:02X} didn't return", function);
+}
+
+fn peek(cpu: &CPU, addr: u32, len: usize) -> Vec<u8> {
+    (0..len).map(|i| cpu.peek(addr as usize + i)).collect()
+}
+
+fn halfwords(values: &[u16]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+#[test]
+fn div() {
+    // (numerator, denominator, quotient, remainder), with r3 set to the absolute quotient
+    let cases: [(i32, i32, i32, i32); 5] = [
+        (7, 2, 3, 1),
+        (-7, 2, -3, -1),
+        (7, -2, -3, 1),
+        (-7, -2, 3, -1),
+        (0x40000000, 3, 0x15555555, 1),
+    ];
+    for &(numerator, denominator, quotient, remainder) in cases.iter() {
+        let args = [numerator as u32, denominator as u32];
+        let cpu = call(0x06, &args, &[]);
+        let result = (cpu.register(0), cpu.register(1), cpu.register(3));
+        let expected = (quotient as u32, remainder as u32, quotient.unsigned_abs());
+        assert_eq!(result, expected, "Div({}, {})", numerator, denominator);
+
+        // DivArm takes the arguments the other way around
+        let cpu = call(0x07, &[args[1], args[0]], &[]);
+        let result = (cpu.register(0), cpu.register(1), cpu.register(3));
+        assert_eq!(result, expected, "DivArm({}, {})", denominator, numerator);
+    }
+}
+
+#[test]
+fn sqrt() {
+    let cases = [
+        (0, 0),
+        (1, 1),
+        (2, 1),
+        (99, 9),
+        (100, 10),
+        (0x10000, 0x100),
+        (0xFFFFFFFF, 0xFFFF),
+    ];
+    for &(n, root) in cases.iter() {
+        assert_eq!(call(0x08, &[n], &[]).register(0), root, "Sqrt({:#X})", n);
+    }
+}
+
+#[test]
+fn arctan() {
+    // (tan, r0, r1, r3)
+    let cases = [
+        (0x0000, 0x0000, 0x00000000, 0xA2F9),
+        (0x2000, 0x12E4, 0xFFFFF000, 0x9720),
+        (0x4000, 0x2000, 0xFFFFC000, 0x8000),
+        (0xFFFFC000, 0xFFFFE000, 0xFFFFC000, 0x8000),
+    ];
+    for &(tan, angle, r1, r3) in cases.iter() {
+        let cpu = call(0x09, &[tan], &[]);
+        let result = (cpu.register(0), cpu.register(1), cpu.register(3));
+        assert_eq!(result, (angle, r1, r3), "ArcTan({:#X})", tan);
+    }
+}
+
+#[test]
+fn arctan2() {
+    // The axes and diagonals, then a point in each octant
+    let cases: [(i32, i32, u32); 16] = [
+        (0x100, 0, 0x0000),
+        (0, 0x100, 0x4000),
+        (-0x100, 0, 0x8000),
+        (0, -0x100, 0xC000),
+        (0x100, 0x100, 0x2000),
+        (-0x100, 0x100, 0x6000),
+        (-0x100, -0x100, 0xA000),
+        (0x100, -0x100, 0xE000),
+        (0x300, 0x100, 0x0D1B),
+        (0x100, 0x300, 0x32E5),
+        (-0x100, 0x300, 0x4D1C),
+        (-0x300, 0x100, 0x72E4),
+        (-0x300, -0x100, 0x8D1B),
+        (-0x100, -0x300, 0xB2E5),
+        (0x100, -0x300, 0xCD1C),
+        (0x300, -0x100, 0xF2E4),
+    ];
+    for &(x, y, angle) in cases.iter() {
+        let cpu = call(0x0A, &[x as u32, y as u32], &[]);
+        assert_eq!(cpu.register(0), angle, "ArcTan2({}, {})", x, y);
+    }
+}
+
+#[test]
+fn get_bios_checksum() {
+    assert_eq!(call(0x0D, &[], &[]).register(0), 0xBAAE187F);
+}
+
+#[test]
+fn cpu_set() {
+    let src: Vec<u8> = (1..=32).collect();
+    let input = [(0x02000000, &src[..])];
+    // (control, bytes written)
+    let cases: [(u32, Vec<u8>); 4] = [
+        // Copies 3 halfwords
+        (3, src[..6].to_vec()),
+        // Copies 3 words
+        (1 << 26 | 3, src[..12].to_vec()),
+        // Fills 3 halfwords
+        (1 << 24 | 3, [1, 2].repeat(3)),
+        // Fills 2 words
+        (1 << 26 | 1 << 24 | 2, [1, 2, 3, 4].repeat(2)),
+    ];
+    for (control, expected) in cases.iter() {
+        let cpu = call(0x0B, &[0x02000000, 0x02000100, *control], &input);
+        let written = peek(&cpu, 0x02000100, expected.len() + 4);
+        assert_eq!(
+            written[..expected.len()],
+            expected[..],
+            "CpuSet({:#X})",
+            control
+        );
+        assert_eq!(written[expected.len()..], [0; 4], "CpuSet({:#X})", control);
+    }
+
+    // Nothing can be copied out of the BIOS
+    let cpu = call(0x0B, &[0x00000000, 0x02000100, 1 << 26 | 4], &[]);
+    assert_eq!(peek(&cpu, 0x02000100, 16), [0; 16]);
+}
+
+#[test]
+fn cpu_fast_set() {
+    let src: Vec<u8> = (1..=64).collect();
+    let input = [(0x02000000, &src[..])];
+    // Words are always copied in blocks of 8
+    let cpu = call(0x0C, &[0x02000000, 0x02000100, 3], &input);
+    assert_eq!(peek(&cpu, 0x02000100, 36), [&src[..32], &[0; 4]].concat());
+    let cpu = call(0x0C, &[0x02000000, 0x02000100, 9], &input);
+    assert_eq!(peek(&cpu, 0x02000100, 64), src);
+    let cpu = call(0x0C, &[0x02000000, 0x02000100, 1 << 24 | 8], &input);
+    assert_eq!(
+        peek(&cpu, 0x02000100, 36),
+        [&[1, 2, 3, 4].repeat(8)[..], &[0; 4]].concat()
+    );
+}
+
+#[test]
+fn bg_affine_set() {
+    let src = [
+        // Origin (16.0, 32.0), center (8, 4), scale (1.0, 2.0), rotated by 90 degrees
+        words(&[0x1000, 0x2000]),
+        halfwords(&[8, 4, 0x100, 0x200, 0x4000, 0]),
+        // Origin (0, 0), center (0, 0), scale (64.0, 64.0), rotated by 2/256 of a turn
+        words(&[0, 0]),
+        halfwords(&[0, 0, 0x4000, 0x4000, 0x0200, 0]),
+    ]
+    .concat();
+    let cpu = call(0x0E, &[0x03000000, 0x03000100, 2], &[(0x03000000, &src)]);
+    let expected = [
+        halfwords(&[0, 0xFF00, 0x200, 0]),
+        // The center is displayed at the origin
+        words(&[0x1400, 0x1000]),
+        // The BIOS's sine table gives sin = 0x0323 here, where rounding would give 0x0324
+        halfwords(&[0x3FEC, 0xFCDD, 0x0323, 0x3FEC]),
+        words(&[0, 0]),
+    ]
+    .concat();
+    assert_eq!(peek(&cpu, 0x03000100, 32), expected);
+}
+
+#[test]
+fn obj_affine_set() {
+    let src = [
+        halfwords(&[0x100, 0x200, 0x4000, 0]),
+        halfwords(&[0x4000, 0x4000, 0x0200, 0]),
+    ]
+    .concat();
+    let input = [(0x03000000, &src[..])];
+
+    // Packed parameters
+    let cpu = call(0x0F, &[0x03000000, 0x03000100, 2, 2], &input);
+    let expected = halfwords(&[0, 0xFF00, 0x200, 0, 0x3FEC, 0xFCDD, 0x0323, 0x3FEC]);
+    assert_eq!(peek(&cpu, 0x03000100, 16), expected);
+
+    // Parameters interleaved with OAM attributes
+    let cpu = call(0x0F, &[0x03000000, 0x07000006, 2, 8], &input);
+    for (i, param) in expected.chunks(2).enumerate() {
+        assert_eq!(
+            peek(&cpu, 0x07000006 + i as u32 * 8, 2),
+            param,
+            "parameter {}",
+            i
+        );
+    }
+}
+
+#[test]
+fn bit_unpack() {
+    // (source bytes, source width, destination width, data offset, unpacked words)
+    type Case = (&'static [u8], u8, u8, u32, &'static [u32]);
+    let cases: [Case; 4] = [
+        (&[0xB1, 0x0F], 1, 4, 0, &[0x10110001, 0x00001111]),
+        // The offset is only added to non-zero units, unless bit 31 is set
+        (&[0xB1, 0x0F], 1, 4, 2, &[0x30330003, 0x00003333]),
+        (&[0xB1, 0x0F], 1, 4, 0x80000002, &[0x32332223, 0x22223333]),
+        (
+            &[0x21, 0x43, 0x65, 0x87],
+            4,
+            8,
+            0,
+            &[0x04030201, 0x08070605],
+        ),
+    ];
+    for &(src, src_width, dst_width, offset, expected) in cases.iter() {
+        let info = [
+            halfwords(&[src.len() as u16]),
+            vec![src_width, dst_width],
+            words(&[offset]),
+        ]
+        .concat();
+        let inputs = [(0x03000000, src), (0x03000100, &info[..])];
+        let cpu = call(0x10, &[0x03000000, 0x03000200, 0x03000100], &inputs);
+        assert_eq!(
+            peek(&cpu, 0x03000200, expected.len() * 4),
+            words(expected),
+            "BitUnPack({:X?}, {}, {}, {:#X})",
+            src,
+            src_width,
+            dst_width,
+            offset
+        );
+    }
+}
+
+// Checks a decompression function, along with its VRAM variant if it has one, which writes
+// halfwords instead of bytes
+fn check_decompression(functions: &[u8], compressed: &[u8], expected: &[u8]) {
+    for &function in functions {
+        let dst = if function == functions[0] {
+            0x02001000
+        } else {
+            0x06000000
+        };
+        let cpu = call(function, &[0x02000000, dst], &[(0x02000000, compressed)]);
+        assert_eq!(
+            peek(&cpu, dst, expected.len()),
+            expected,
+            "SWI {:02X} of {:X?}",
+            function,
+            compressed
+        );
+    }
+}
+
+#[test]
+fn lz77() {
+    // Three literals, then a reference back 3 bytes for 6 bytes, then a literal
+    let compressed = [
+        0x10, 0x0A, 0x00, 0x00, 0x10, b'A', b'B', b'C', 0x30, 0x02, b'D',
+    ];
+    check_decompression(&[0x11, 0x12], &compressed, b"ABCABCABCD");
+    // A reference can overlap the data it produces
+    let compressed = [0x10, 0x07, 0x00, 0x00, 0x40, b'A', 0x30, 0x00];
+    check_decompression(&[0x11, 0x12], &compressed, b"AAAAAAA");
+}
+
+#[test]
+fn huffman() {
+    // A tree whose root has two leaves, 'A' for 0 and 'B' for 1, followed by the bit stream in
+    // little-endian words, MSB first
+    let compressed = [
+        0x28, 0x08, 0x00, 0x00, 0x01, 0xC0, b'A', b'B', 0x00, 0x00, 0x00, 0x69,
+    ];
+    check_decompression(&[0x13], &compressed, b"ABBABAAB");
+    // 4-bit symbols are packed into bytes low nibble first
+    let compressed = [
+        0x24, 0x04, 0x00, 0x00, 0x01, 0xC0, 0x01, 0x02, 0x00, 0x00, 0x55, 0x55,
+    ];
+    check_decompression(&[0x13], &compressed, &[0x21; 4]);
+}
+
+#[test]
+fn run_length() {
+    // Three uncompressed bytes, then a run of 5
+    let compressed = [0x30, 0x08, 0x00, 0x00, 0x02, b'a', b'b', b'c', 0x82, b'z'];
+    check_decompression(&[0x14, 0x15], &compressed, b"abczzzzz");
+}
+
+#[test]
+fn diff_unfilter() {
+    let compressed = [0x81, 0x04, 0x00, 0x00, 0x01, 0x01, 0x01, 0xFF];
+    check_decompression(&[0x16, 0x17], &compressed, &[1, 2, 3, 2]);
+    let compressed = [0x82, 0x06, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0xFF, 0xFF];
+    check_decompression(&[0x18], &compressed, &halfwords(&[1, 3, 2]));
+}
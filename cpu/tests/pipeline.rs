@@ -0,0 +1,58 @@
+// Checks the effects of the 3-stage pipeline that programs can see
+mod common;
+
+use cpu::CPU;
+use memory::Memory;
+
+// Runs an ARM program loaded at the start of IWRAM until it reaches the given address
+fn run(program: &[u32], until: u32) -> CPU {
+    let mut cpu = common::cpu();
+    cpu.skip_bios();
+    for (i, &word) in program.iter().enumerate() {
+        cpu.write_u32(0x03000000 + i * 4, word);
+    }
+    cpu.write_register(15, 0x03000000);
+
+    for _ in 0..100 {
+        if cpu.pc() == until {
+            return cpu;
+        }
+        cpu.tick();
+    }
+    panic!("didn't reach {:08X}", until);
+}
+
+#[test]
+fn pc_reads_ahead() {
+    let program = [
+        0xE1A0000F, // 03000000: mov r0, pc
+        0xE28F2001, // 03000004: add r2, pc, #0x1
+        0xE12FFF12, // 03000008: bx r2
+        0xE7FE467B, // 0300000C: mov r3, pc; b .
+    ];
+    let cpu = run(&program, 0x0300000E);
+    // Reading r15 gives the address of the instruction two ahead, in ARM and Thumb
+    assert_eq!(cpu.register(0), 0x03000008);
+    assert_eq!(cpu.register(3), 0x03000010);
+}
+
+#[test]
+fn self_modifying_code() {
+    let program = [
+        0xE59F100C, // 03000000: ldr r1, [pc, #0xC]
+        0xE58F1000, // 03000004: str r1, [pc]
+        0xE1A00000, // 03000008: mov r0, r0
+        0xE3A04001, // 0300000C: mov r4, #0x1
+        0xEAFFFFFE, // 03000010: b .
+        0xE3A04002, // 03000014: mov r4, #0x2
+    ];
+    let mut cpu = run(&program, 0x03000010);
+    // The store replaced an instruction that had already been fetched, so the old one ran
+    assert_eq!(cpu.peek_u32(0x0300000C), 0xE3A04002);
+    assert_eq!(cpu.register(4), 1);
+
+    // Running it again fetches the new instruction
+    cpu.write_register(15, 0x0300000C);
+    cpu.tick();
+    assert_eq!(cpu.register(4), 2);
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use cpu::OperatingMode;
+use memory::Memory;
+
+#[test]
+fn state_left_by_the_bios() {
+    let mut cpu = common::cpu();
+    cpu.skip_bios();
+
+    assert_eq!(cpu.pc(), 0x08000000);
+    assert_eq!(cpu.cpsr(), OperatingMode::System as u32);
+    assert!((0..13).all(|n| cpu.register(n) == 0));
+    assert_eq!(cpu.register(13), 0x03007F00);
+    assert_eq!(
+        cpu.banked_register(OperatingMode::Supervisor, 13),
+        0x03007FE0
+    );
+    assert_eq!(
+        cpu.banked_register(OperatingMode::Interrupt, 13),
+        0x03007FA0
+    );
+
+    // The BIOS sets SOUNDBIAS and POSTFLG, and its last opcode fetched is what reads of it return
+    assert_eq!(cpu.peek_u16(0x04000088), 0x200);
+    assert_eq!(cpu.peek(0x04000300), 1);
+    assert_eq!(cpu.peek_u32(0x00000000), 0xE129F000);
+}
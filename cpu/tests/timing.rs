@@ -0,0 +1,67 @@
+// Checks the cycles instructions take, which depend on the memory they access and on WAITCNT
+mod common;
+
+use memory::Memory;
+
+#[test]
+fn wait_states() {
+    let program = [
+        0xE1A00000, // 08000000: mov r0, r0
+        0xE1A00000, // 08000004: mov r0, r0
+        0xE5921000, // 08000008: ldr r1, [r2]
+        0xE1A00000, // 0800000C: mov r0, r0
+        0xE1A00000, // 08000010: mov r0, r0
+        0xE1A00000, // 08000014: mov r0, r0
+        0xEAFFFFFE, // 08000018: b .
+    ];
+    let mut cpu = common::boot_rom(&program);
+    cpu.write_register(2, 0x02000000);
+
+    // Filling the pipeline takes a non-sequential and a sequential fetch, each of two halfwords
+    // from WS0 with 4 and 2 wait states, then the first instruction fetches sequentially
+    assert_eq!(cpu.tick(), 8 + 6 + 6);
+    assert_eq!(cpu.tick(), 6);
+    // Loading a word from EWRAM takes 6 cycles and an internal cycle, and breaks the sequence
+    assert_eq!(cpu.tick(), 6 + 6 + 1);
+    assert_eq!(cpu.tick(), 8);
+
+    // Lowering WS0's wait states to 2 and 1 speeds up fetches straight away
+    cpu.write_u16(0x04000204, 0x0018);
+    assert_eq!(cpu.peek_u16(0x04000204), 0x0018);
+    assert_eq!(cpu.tick(), 4);
+    assert_eq!(cpu.tick(), 4);
+}
+
+// The cycles taken to run 8 Thumb multiplies from ROM, with the given WAITCNT
+fn thumb_multiplies(waitcnt: u16) -> u32 {
+    let program = [
+        0xE28F0001, // 08000000: add r0, pc, #0x1
+        0xE12FFF10, // 08000004: bx r0
+        0x434A434A, // 08000008: mul r2, r1; mul r2, r1
+        0x434A434A, // 0800000C: mul r2, r1; mul r2, r1
+        0x434A434A, // 08000010: mul r2, r1; mul r2, r1
+        0x434A434A, // 08000014: mul r2, r1; mul r2, r1
+        0x0000E7FE, // 08000018: b .
+    ];
+    let mut cpu = common::boot_rom(&program);
+    cpu.write_u16(0x04000204, waitcnt);
+    cpu.write_register(1, 0x12345678);
+    while cpu.pc() != 0x08000008 {
+        cpu.tick();
+    }
+
+    let mut cycles = 0;
+    while cpu.pc() != 0x08000018 {
+        cycles += cpu.tick();
+    }
+    cycles
+}
+
+#[test]
+fn prefetch_buffer() {
+    // Each multiply takes an internal cycle, then fetches the next halfword in 3 cycles
+    assert_eq!(thumb_multiplies(0), 8 * (1 + 3));
+    // The prefetch buffer reads ahead during the internal cycle, so after the first, each fetch
+    // has a cycle less to wait
+    assert_eq!(thumb_multiplies(0x4000), (1 + 3) + 7 * (1 + 2));
+}
@@ -0,0 +1,64 @@
+use cpu::{OperatingMode, TraceEntry, TraceFilter, Tracer};
+
+fn entry(addr: u32, opcode: u32, thumb: bool) -> TraceEntry {
+    let width = if thumb { 2 } else { 4 };
+    let mut registers = [0; 16];
+    registers[0] = 0x12345678;
+    registers[15] = addr + 2 * width;
+    TraceEntry {
+        registers,
+        cpsr: 0x6000001F | (thumb as u32) << 5,
+        opcode,
+        next: 0,
+    }
+}
+
+fn line(entry: &TraceEntry, disassemble: bool) -> String {
+    let mut out = Vec::new();
+    entry.write_line(&mut out, disassemble).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn line_format() {
+    let zeros = "00000000 ".repeat(14);
+    assert_eq!(
+        line(&entry(0x08000000, 0xE3A00001, false), true),
+        format!(
+            "12345678 {}08000008 cpsr: 6000001F | E3A00001: mov r0, #0x1\n",
+            zeros
+        )
+    );
+    assert_eq!(
+        line(&entry(0x08000100, 0x4770, true), false),
+        format!("12345678 {}08000104 cpsr: 6000003F |     4770\n", zeros)
+    );
+}
+
+#[test]
+fn filters() {
+    let filter = TraceFilter {
+        ranges: vec![0x08000000..=0x080000FF, 0x03000000..=0x03007FFF],
+        modes: vec![OperatingMode::System, OperatingMode::Interrupt],
+    };
+    assert!(filter.matches(0x080000FF, OperatingMode::System));
+    assert!(filter.matches(0x03000000, OperatingMode::Interrupt));
+    assert!(!filter.matches(0x08000100, OperatingMode::System));
+    assert!(!filter.matches(0x08000000, OperatingMode::Supervisor));
+    assert!(TraceFilter::default().matches(0x00000000, OperatingMode::User));
+}
+
+#[test]
+fn recent_instructions() {
+    let mut tracer = Tracer::new(TraceFilter::default());
+    tracer.set_recent_len(3);
+    for i in 0..5 {
+        tracer.trace(entry(0x08000000 + i * 4, 0xE1A00000, false));
+    }
+    let addrs: Vec<u32> = tracer.recent().map(TraceEntry::addr).collect();
+    assert_eq!(addrs, vec![0x08000008, 0x0800000C, 0x08000010]);
+
+    let mut dump = Vec::new();
+    tracer.dump(&mut dump).unwrap();
+    assert_eq!(String::from_utf8(dump).unwrap().lines().count(), 3);
+}
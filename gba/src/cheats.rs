@@ -0,0 +1,688 @@
+use crate::GBA;
+
+use memory::Memory;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+// The keys TEA-encrypted codes are decrypted with
+const GAMESHARK_SEEDS: [u32; 4] = [0x09F4FBBD, 0x9681884A, 0x352027E9, 0xF3DEE5A7];
+const ACTION_REPLAY_MAX_SEEDS: [u32; 4] = [0x7AA9648F, 0x7FAE6994, 0xC0EFAAD5, 0x42712C57];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatFormat {
+    // GameShark v1/v2 and Action Replay v1/v2, e.g. `1A2B3C4D 5E6F7A8B`
+    GameShark,
+    // Action Replay MAX, which GameShark v3 shares, e.g. `1A2B3C4D 5E6F7A8B`
+    ActionReplayMax,
+    // CodeBreaker, which isn't encrypted, e.g. `82001234 0063`
+    CodeBreaker,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CheatError {
+    // The line isn't a code in the cheat's format
+    InvalidCode(String),
+    // The line is missing the lines that complete its code
+    Incomplete(String),
+    // The code's type isn't supported, e.g. ones needing the device's own button
+    Unsupported(String),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidCode(line) => write!(f, "invalid code: {}", line),
+            Self::Incomplete(line) => write!(f, "incomplete code: {}", line),
+            Self::Unsupported(line) => write!(f, "unsupported code type: {}", line),
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Condition {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    SignedLt,
+    SignedGt,
+    // Any of the value's bits are set
+    And,
+}
+
+// What a conditional code skips when its condition doesn't hold
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Skip {
+    // The given number of codes after it
+    Codes(usize),
+    // The codes on the given number of lines after it, which may hold fewer codes when some
+    // take up several lines
+    Lines(usize),
+    // The codes up to the matching else or end-if code
+    Block,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Modify {
+    And,
+    Or,
+    Add,
+}
+
+// A decoded code. Sizes are in bytes, and multi-line codes decode to a single one.
+#[derive(Clone, Debug, PartialEq)]
+enum Code {
+    // Writes the value `count` times, stepping the address and value after each
+    Write {
+        addr: u32,
+        size: u32,
+        value: u32,
+        count: u32,
+        addr_step: u32,
+        value_step: u32,
+    },
+    WriteBytes {
+        addr: u32,
+        bytes: Vec<u8>,
+    },
+    // Writes the same word to each address
+    WriteGroup {
+        addrs: Vec<u32>,
+        value: u32,
+    },
+    // Writes to the address held at `pointer`, plus the offset
+    WriteIndirect {
+        pointer: u32,
+        offset: u32,
+        size: u32,
+        value: u32,
+    },
+    Modify {
+        addr: u32,
+        size: u32,
+        modify: Modify,
+        value: u32,
+    },
+    RomPatch {
+        addr: u32,
+        value: u16,
+    },
+    If {
+        addr: u32,
+        size: u32,
+        condition: Condition,
+        value: u32,
+        skip: Skip,
+    },
+    // All of the given keys are held, using KEYINPUT's bits
+    IfKeys {
+        keys: u16,
+        skip: Skip,
+    },
+    Else,
+    EndIf,
+    // Stops running the cheat's codes
+    End,
+    // Identifies the game, or where the device hooks into it to run its codes. Cheats are
+    // applied once a frame instead, so these don't do anything.
+    Nop,
+}
+
+// A set of codes that are applied together, e.g. one entry of a code list
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub format: CheatFormat,
+    pub enabled: bool,
+    codes: Vec<Code>,
+    // The line each code starts on
+    starts: Vec<usize>,
+}
+
+impl Cheat {
+    // Decodes (and decrypts) one code per line. Blank lines are ignored, as is whitespace within
+    // a line.
+    pub fn parse(format: CheatFormat, text: &str) -> Result<Self, CheatError> {
+        let digits = match format {
+            CheatFormat::GameShark | CheatFormat::ActionReplayMax => 16,
+            CheatFormat::CodeBreaker => 12,
+        };
+        let mut lines = Vec::new();
+        let mut texts = Vec::new();
+        for text in text.lines().map(str::trim) {
+            let line: String = text.split_whitespace().collect();
+            if line.is_empty() {
+                continue;
+            }
+            let (op1, op2) = Some(line.as_str())
+                .filter(|line| line.len() == digits && line.is_ascii())
+                .and_then(|line| {
+                    let op1 = u32::from_str_radix(&line[..8], 16).ok()?;
+                    let op2 = u32::from_str_radix(&line[8..], 16).ok()?;
+                    Some((op1, op2))
+                })
+                .ok_or_else(|| CheatError::InvalidCode(text.to_string()))?;
+            lines.push(format.decrypt(op1, op2));
+            texts.push(text.to_string());
+        }
+
+        let mut codes = Vec::new();
+        let mut starts = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let (code, n_lines) = match format {
+                CheatFormat::GameShark => decode_gameshark(&lines[i..]),
+                CheatFormat::ActionReplayMax => decode_action_replay_max(&lines[i..]),
+                CheatFormat::CodeBreaker => decode_codebreaker(&lines[i..]),
+            }
+            .map_err(|error| error(texts[i].clone()))?;
+            codes.push(code);
+            starts.push(i);
+            i += n_lines;
+        }
+
+        Ok(Self {
+            format,
+            enabled: true,
+            codes,
+            starts,
+        })
+    }
+
+    // The ROM halfwords the cheat replaces, keyed by address
+    fn rom_patches(&self) -> impl Iterator<Item = (u32, u16)> + '_ {
+        self.codes.iter().filter_map(|code| match *code {
+            Code::RomPatch { addr, value } => Some((addr, value)),
+            _ => None,
+        })
+    }
+}
+
+impl CheatFormat {
+    // Decrypts a line of a code. CodeBreaker codes aren't encrypted, so are left as they are.
+    pub fn decrypt(self, op1: u32, op2: u32) -> (u32, u32) {
+        match self {
+            Self::GameShark => decrypt(op1, op2, &GAMESHARK_SEEDS),
+            Self::ActionReplayMax => decrypt(op1, op2, &ACTION_REPLAY_MAX_SEEDS),
+            Self::CodeBreaker => (op1, op2),
+        }
+    }
+}
+
+// Decrypts a code with the Tiny Encryption Algorithm
+fn decrypt(mut op1: u32, mut op2: u32, seeds: &[u32; 4]) -> (u32, u32) {
+    const DELTA: u32 = 0x9E3779B9;
+    let mut sum = DELTA.wrapping_mul(32);
+    for _ in 0..32 {
+        op2 = op2.wrapping_sub(
+            (op1 << 4).wrapping_add(seeds[2])
+                ^ op1.wrapping_add(sum)
+                ^ (op1 >> 5).wrapping_add(seeds[3]),
+        );
+        op1 = op1.wrapping_sub(
+            (op2 << 4).wrapping_add(seeds[0])
+                ^ op2.wrapping_add(sum)
+                ^ (op2 >> 5).wrapping_add(seeds[1]),
+        );
+        sum = sum.wrapping_sub(DELTA);
+    }
+    (op1, op2)
+}
+
+// How a code fails to decode, which is filled in with the line it starts on
+type DecodeError = fn(String) -> CheatError;
+
+// Decodes the code starting at the first line, returning it and the number of lines it took
+fn decode_gameshark(lines: &[(u32, u32)]) -> Result<(Code, usize), DecodeError> {
+    let (op1, op2) = lines[0];
+    let addr = op1 & 0x0FFFFFFF;
+    let write = |size, value| Code::Write {
+        addr,
+        size,
+        value,
+        count: 1,
+        addr_step: 0,
+        value_step: 0,
+    };
+
+    // The game ID code
+    if op2 == 0x001DC0DE {
+        return Ok((Code::Nop, 1));
+    }
+    let code = match op1 >> 28 {
+        0x0 if op2 >> 8 == 0 => write(1, op2),
+        0x1 if op2 >> 16 == 0 => write(2, op2),
+        0x2 => write(4, op2),
+        // Writes the value to each address that follows, two to a line
+        0x3 => {
+            let count = (op1 & 0xFFFF) as usize;
+            let n_lines = 1 + count.div_ceil(2);
+            let addrs = lines
+                .get(1..n_lines)
+                .ok_or(CheatError::Incomplete as DecodeError)?;
+            let addrs = addrs
+                .iter()
+                .flat_map(|&(addr1, addr2)| [addr1, addr2])
+                .take(count)
+                .collect();
+            return Ok((Code::WriteGroup { addrs, value: op2 }, n_lines));
+        }
+        0x6 if op2 >> 16 == 0 => Code::RomPatch {
+            addr: 0x08000000 | ((op1 << 1) & 0x01FFFFFE),
+            value: op2 as u16,
+        },
+        0xD if op1 != 0xDEADFACE && op2 >> 16 == 0 => Code::If {
+            addr,
+            size: 2,
+            condition: Condition::Eq,
+            value: op2,
+            skip: Skip::Codes(1),
+        },
+        0xE if op1 & 0x0F000000 == 0 => Code::If {
+            addr: op2 & 0x0FFFFFFF,
+            size: 2,
+            condition: Condition::Eq,
+            value: op1 & 0xFFFF,
+            skip: Skip::Lines(((op1 >> 16) & 0xFF) as usize),
+        },
+        // The hook code
+        0xF => Code::Nop,
+        // The button and slowdown codes need the device's button, and the encryption seeds
+        // can't be changed
+        _ => return Err(CheatError::Unsupported),
+    };
+    Ok((code, 1))
+}
+
+fn decode_action_replay_max(lines: &[(u32, u32)]) -> Result<(Code, usize), DecodeError> {
+    let (op1, op2) = lines[0];
+    let addr = ((op1 & 0x00F00000) << 4) | (op1 & 0x003FFFFF);
+    let size = 1 << ((op1 >> 25) & 0b11);
+    let mask = match size {
+        1 => 0xFF,
+        2 => 0xFFFF,
+        4 => 0xFFFFFFFF,
+        _ => return Err(CheatError::InvalidCode),
+    };
+
+    // The hook code, and the game ID code that follows it
+    if op1 & 0xFE000000 == 0xC4000000 || op2 == 0x001DC0DE {
+        return Ok((Code::Nop, 1));
+    }
+    // Codes without an address are told apart by the second word
+    if op1 == 0 {
+        let code = match op2 & 0xFE000000 {
+            0x00000000 => Code::End,
+            0x18000000 | 0x1A000000 | 0x1C000000 | 0x1E000000 => {
+                let &(value, _) = lines.get(1).ok_or(CheatError::Incomplete as DecodeError)?;
+                let patch = Code::RomPatch {
+                    addr: 0x08000000 | ((op2 & 0x00FFFFFF) << 1),
+                    value: value as u16,
+                };
+                return Ok((patch, 2));
+            }
+            0x40000000 => Code::EndIf,
+            0x60000000 => Code::Else,
+            _ => return Err(CheatError::Unsupported),
+        };
+        return Ok((code, 1));
+    }
+
+    let condition = match (op1 >> 27) & 0b111 {
+        0 => None,
+        1 => Some(Condition::Eq),
+        2 => Some(Condition::Ne),
+        3 => Some(Condition::SignedLt),
+        4 => Some(Condition::SignedGt),
+        5 => Some(Condition::Lt),
+        6 => Some(Condition::Gt),
+        _ => Some(Condition::And),
+    };
+    let code = match (condition, op1 >> 30) {
+        (Some(condition), action) => Code::If {
+            addr,
+            size,
+            condition,
+            value: op2 & mask,
+            skip: match action {
+                0 => Skip::Codes(1),
+                1 => Skip::Codes(2),
+                2 => Skip::Block,
+                // Turning off every code when the condition holds
+                _ => return Err(CheatError::Unsupported),
+            },
+        },
+        // Fills `count` bytes or halfwords, with the count in the upper bits of the value
+        (None, 0) => Code::Write {
+            addr,
+            size,
+            value: op2 & mask,
+            count: if size == 4 {
+                1
+            } else {
+                (op2 >> (size * 8)) + 1
+            },
+            addr_step: size,
+            value_step: 0,
+        },
+        // Writes to a pointer, with the offset in the upper bits of the value
+        (None, 1) => Code::WriteIndirect {
+            pointer: addr,
+            offset: if size == 4 {
+                0
+            } else {
+                (op2 >> (size * 8)) * size
+            },
+            size,
+            value: op2 & mask,
+        },
+        (None, 2) => Code::Modify {
+            addr,
+            size,
+            modify: Modify::Add,
+            value: op2 & mask,
+        },
+        _ => return Err(CheatError::Unsupported),
+    };
+    Ok((code, 1))
+}
+
+fn decode_codebreaker(lines: &[(u32, u32)]) -> Result<(Code, usize), DecodeError> {
+    let (op1, op2) = lines[0];
+    let addr = op1 & 0x0FFFFFFF;
+    let write = |size, value| Code::Write {
+        addr,
+        size,
+        value,
+        count: 1,
+        addr_step: 0,
+        value_step: 0,
+    };
+    let modify = |modify| Code::Modify {
+        addr,
+        size: 2,
+        modify,
+        value: op2,
+    };
+    let compare = |condition| Code::If {
+        addr,
+        size: 2,
+        condition,
+        value: op2,
+        skip: Skip::Codes(1),
+    };
+
+    let code = match op1 >> 28 {
+        // The game ID and hook codes
+        0x0 | 0x1 => Code::Nop,
+        0x2 => modify(Modify::Or),
+        0x3 => write(1, op2 & 0xFF),
+        // Writes `count` halfwords, with the value step, count and address step on the next line
+        0x4 => {
+            let &(step_and_count, addr_step) =
+                lines.get(1).ok_or(CheatError::Incomplete as DecodeError)?;
+            let fill = Code::Write {
+                addr,
+                size: 2,
+                value: op2,
+                count: step_and_count & 0xFFFF,
+                addr_step,
+                value_step: step_and_count >> 16,
+            };
+            return Ok((fill, 2));
+        }
+        // Writes `count` bytes, given six to a line on the lines that follow
+        0x5 => {
+            let count = op2 as usize;
+            let n_lines = 1 + count.div_ceil(6);
+            let bytes = lines
+                .get(1..n_lines)
+                .ok_or(CheatError::Incomplete as DecodeError)?
+                .iter()
+                .flat_map(|&(op1, op2)| {
+                    let [_, _, byte4, byte5] = op2.to_be_bytes();
+                    let [byte0, byte1, byte2, byte3] = op1.to_be_bytes();
+                    [byte0, byte1, byte2, byte3, byte4, byte5]
+                })
+                .take(count)
+                .collect();
+            return Ok((Code::WriteBytes { addr, bytes }, n_lines));
+        }
+        0x6 => modify(Modify::And),
+        0x7 => compare(Condition::Eq),
+        0x8 => write(2, op2),
+        0xA => compare(Condition::Ne),
+        0xB => compare(Condition::Gt),
+        0xC => compare(Condition::Lt),
+        0xD if addr == 0x20 => Code::IfKeys {
+            keys: op2 as u16,
+            skip: Skip::Codes(1),
+        },
+        0xE => modify(Modify::Add),
+        0xF => compare(Condition::And),
+        // Encrypted codes
+        _ => return Err(CheatError::Unsupported),
+    };
+    Ok((code, 1))
+}
+
+// The cheats added to a GBA, keyed by ID
+pub struct CheatEngine {
+    cheats: BTreeMap<usize, Cheat>,
+    next_id: usize,
+}
+
+impl CheatEngine {
+    pub fn new() -> Self {
+        Self {
+            cheats: BTreeMap::new(),
+            next_id: 1,
+        }
+    }
+}
+
+impl GBA {
+    // Adds a cheat, returning its ID. It's applied from the next frame on, if enabled.
+    pub fn add_cheat(&mut self, cheat: Cheat) -> usize {
+        let id = self.cheats.next_id;
+        self.cheats.next_id += 1;
+        self.cheats.cheats.insert(id, cheat);
+        self.update_rom_patches();
+        id
+    }
+
+    // Returns whether there was a cheat with the ID. Memory it wrote keeps its value, but ROM
+    // patches are undone.
+    pub fn remove_cheat(&mut self, id: usize) -> bool {
+        let removed = self.cheats.cheats.remove(&id).is_some();
+        self.update_rom_patches();
+        removed
+    }
+
+    // Returns whether there's a cheat with the ID
+    pub fn set_cheat_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        match self.cheats.cheats.get_mut(&id) {
+            Some(cheat) => cheat.enabled = enabled,
+            None => return false,
+        }
+        self.update_rom_patches();
+        true
+    }
+
+    pub fn cheats(&self) -> &BTreeMap<usize, Cheat> {
+        &self.cheats.cheats
+    }
+
+    // ROM patches are applied through the cartridge rather than each frame, since the ROM can't
+    // be written
+    fn update_rom_patches(&mut self) {
+        let patches = self
+            .cheats
+            .cheats
+            .values()
+            .filter(|cheat| cheat.enabled)
+            .flat_map(Cheat::rom_patches)
+            .collect();
+        self.cpu.borrow_mut().set_rom_patches(&patches);
+    }
+
+    // Runs the codes of the enabled cheats, which happens at the start of each VBlank
+    pub(crate) fn apply_cheats(&mut self) {
+        if self.cheats.cheats.is_empty() {
+            return;
+        }
+        let cheats = std::mem::take(&mut self.cheats.cheats);
+        for cheat in cheats.values().filter(|cheat| cheat.enabled) {
+            self.run_codes(cheat);
+        }
+        self.cheats.cheats = cheats;
+    }
+
+    fn run_codes(&mut self, cheat: &Cheat) {
+        let codes = &cheat.codes;
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                Code::Write {
+                    addr,
+                    size,
+                    value,
+                    count,
+                    addr_step,
+                    value_step,
+                } => {
+                    for n in 0..count {
+                        self.poke(
+                            addr.wrapping_add(n.wrapping_mul(addr_step)),
+                            size,
+                            value.wrapping_add(n.wrapping_mul(value_step)),
+                        );
+                    }
+                }
+                Code::WriteBytes { addr, ref bytes } => {
+                    for (n, &byte) in bytes.iter().enumerate() {
+                        self.poke(addr.wrapping_add(n as u32), 1, byte as u32);
+                    }
+                }
+                Code::WriteGroup { ref addrs, value } => {
+                    for &addr in addrs.iter() {
+                        self.poke(addr, 4, value);
+                    }
+                }
+                Code::WriteIndirect {
+                    pointer,
+                    offset,
+                    size,
+                    value,
+                } => {
+                    let addr = self.peek(pointer, 4);
+                    self.poke(addr.wrapping_add(offset), size, value);
+                }
+                Code::Modify {
+                    addr,
+                    size,
+                    modify,
+                    value,
+                } => {
+                    let old = self.peek(addr, size);
+                    let new = match modify {
+                        Modify::And => old & value,
+                        Modify::Or => old | value,
+                        Modify::Add => old.wrapping_add(value),
+                    };
+                    self.poke(addr, size, new);
+                }
+                Code::If {
+                    addr,
+                    size,
+                    condition,
+                    value,
+                    skip,
+                } => {
+                    if !condition.holds(self.peek(addr, size), value, size) {
+                        i = skipped_to(cheat, i, skip);
+                        continue;
+                    }
+                }
+                Code::IfKeys { keys, skip } => {
+                    // KEYINPUT's bits are cleared while the keys are held
+                    let held = !(self.peek(0x04000130, 2) as u16);
+                    if held & keys != keys {
+                        i = skipped_to(cheat, i, skip);
+                        continue;
+                    }
+                }
+                // Reached at the end of the block run for a condition that held
+                Code::Else => {
+                    i = skipped_to(cheat, i, Skip::Block);
+                    continue;
+                }
+                Code::End => break,
+                Code::RomPatch { .. } | Code::EndIf | Code::Nop => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn peek(&self, addr: u32, size: u32) -> u32 {
+        let cpu = self.cpu.borrow();
+        (0..size).rev().fold(0, |value, n| {
+            (value << 8) | cpu.peek(addr.wrapping_add(n) as usize) as u32
+        })
+    }
+
+    fn poke(&mut self, addr: u32, size: u32, value: u32) {
+        let mut cpu = self.cpu.borrow_mut();
+        for n in 0..size {
+            cpu.write(addr.wrapping_add(n) as usize, (value >> (n * 8)) as u8);
+        }
+    }
+}
+
+impl Condition {
+    fn holds(&self, lhs: u32, rhs: u32, size: u32) -> bool {
+        // Sign-extends values of the code's size
+        let shift = 32 - size * 8;
+        let signed = |value: u32| ((value << shift) as i32) >> shift;
+        match self {
+            Self::Eq => lhs == rhs,
+            Self::Ne => lhs != rhs,
+            Self::Lt => lhs < rhs,
+            Self::Gt => lhs > rhs,
+            Self::SignedLt => signed(lhs) < signed(rhs),
+            Self::SignedGt => signed(lhs) > signed(rhs),
+            Self::And => lhs & rhs != 0,
+        }
+    }
+}
+
+// The index of the code to run after skipping past the conditional code at `i`. A block ends
+// after its else code or at its end-if code, skipping over any nested blocks.
+fn skipped_to(cheat: &Cheat, i: usize, skip: Skip) -> usize {
+    let codes = &cheat.codes;
+    match skip {
+        Skip::Codes(n) => i + 1 + n,
+        // Skipping part of a multi-line code skips the whole of it
+        Skip::Lines(n) => match cheat.starts.get(i + 1) {
+            Some(&start) => cheat.starts.partition_point(|&line| line < start + n),
+            None => codes.len(),
+        },
+        Skip::Block => {
+            let mut depth = 0;
+            for (j, code) in codes.iter().enumerate().skip(i + 1) {
+                match code {
+                    Code::If {
+                        skip: Skip::Block, ..
+                    } => depth += 1,
+                    Code::Else if depth == 0 => return j + 1,
+                    Code::EndIf if depth == 0 => return j,
+                    Code::EndIf => depth -= 1,
+                    _ => {}
+                }
+            }
+            codes.len()
+        }
+    }
+}
@@ -0,0 +1,441 @@
+use crate::{StepPoint, GBA};
+
+use cpu::{disassemble_arm, disassemble_thumb, Instruction, WatchKind, Watchpoint, WatchpointHit};
+use memory::Memory;
+
+use std::collections::BTreeMap;
+
+// A value that breakpoint conditions and debugger commands can refer to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    Register(usize),
+    Cpsr,
+    // The value of the given width in bytes (1, 2 or 4) at an address
+    Memory { addr: u32, width: u32 },
+    Immediate(u32),
+}
+
+impl Operand {
+    // Parses a register (`r0`-`r15`, `sp`, `lr`, `pc` or `cpsr`), a memory reference (`u8[addr]`,
+    // `u16[addr]` or `[addr]` for a word), or a number, in hex if it starts with `0x`
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_ascii_lowercase();
+        let memory = |rest: &str, width| {
+            let addr = rest.strip_prefix('[')?.strip_suffix(']')?;
+            Some(Self::Memory {
+                addr: parse_number(addr.trim())?,
+                width,
+            })
+        };
+        match s.as_str() {
+            "sp" => Some(Self::Register(13)),
+            "lr" => Some(Self::Register(14)),
+            "pc" => Some(Self::Register(15)),
+            "cpsr" => Some(Self::Cpsr),
+            _ if s.starts_with("u8[") => memory(&s[2..], 1),
+            _ if s.starts_with("u16[") => memory(&s[3..], 2),
+            _ if s.starts_with("u32[") => memory(&s[3..], 4),
+            _ if s.starts_with('[') => memory(&s, 4),
+            _ if s.starts_with('r') => match s[1..].parse() {
+                Ok(n) if n < 16 => Some(Self::Register(n)),
+                _ => None,
+            },
+            _ => parse_number(&s).map(Self::Immediate),
+        }
+    }
+
+    // Reads the operand without side effects, so reading IO registers doesn't disturb them
+    pub fn value(&self, gba: &GBA) -> u32 {
+        match *self {
+            Self::Register(n) => gba.register(n),
+            Self::Cpsr => gba.cpsr(),
+            Self::Memory { addr, width } => {
+                let cpu = gba.cpu.borrow();
+                let addr = addr as usize;
+                match width {
+                    1 => cpu.peek(addr) as u32,
+                    2 => cpu.peek_u16(addr & !0b1) as u32,
+                    _ => cpu.peek_u32(addr & !0b11),
+                }
+            }
+            Self::Immediate(value) => value,
+        }
+    }
+}
+
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// A condition under which a breakpoint stops execution, e.g. `r0 == 0x10` or `u8[0x03000000] > 3`.
+// Values are compared as unsigned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BreakCondition {
+    pub lhs: Operand,
+    pub comparison: Comparison,
+    pub rhs: Operand,
+}
+
+impl BreakCondition {
+    pub fn parse(s: &str) -> Option<Self> {
+        // The two-character operators are checked first, so `<=` isn't taken for `<`
+        let operators = [
+            ("==", Comparison::Eq),
+            ("!=", Comparison::Ne),
+            ("<=", Comparison::Le),
+            (">=", Comparison::Ge),
+            ("<", Comparison::Lt),
+            (">", Comparison::Gt),
+        ];
+        let (i, operator, comparison) = operators.iter().find_map(|&(operator, comparison)| {
+            s.find(operator).map(|i| (i, operator, comparison))
+        })?;
+        Some(Self {
+            lhs: Operand::parse(&s[..i])?,
+            comparison,
+            rhs: Operand::parse(&s[i + operator.len()..])?,
+        })
+    }
+
+    pub fn holds(&self, gba: &GBA) -> bool {
+        let (lhs, rhs) = (self.lhs.value(gba), self.rhs.value(gba));
+        match self.comparison {
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+        }
+    }
+}
+
+// Stops execution before the instruction at an address is executed, if its condition holds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u32,
+    pub condition: Option<BreakCondition>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    // The instructions to step were executed, or the address to run to was reached
+    Done,
+    // Execution reached the breakpoint with the given ID
+    Breakpoint(usize),
+    // The watchpoint with the given ID was triggered by the last instruction or DMA transfer
+    Watchpoint(usize, WatchpointHit),
+    // An IRQ was taken, and the first instruction of the handler is next
+    Irq,
+    // A transfer on the given DMA channel is about to start
+    DmaStart(usize),
+    // The CPU is in stop mode, and nothing runs until a key is pressed
+    Stopped,
+}
+
+// Where a debugger run stops, besides breakpoints and the other events
+#[derive(Clone, Copy)]
+enum RunTarget {
+    Continue,
+    // The number of instructions left to execute
+    Instructions(u64),
+    // Runs until the given address is reached with the stack pointer at or above the given value.
+    // Stepping over a call uses this, so that a recursive call reaching the return address
+    // doesn't count as the call returning.
+    Address { addr: u32, min_sp: u32 },
+}
+
+pub struct Debugger {
+    // Breakpoints and watchpoints share a space of IDs, which are never reused
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_id: usize,
+
+    break_on_irq: bool,
+    break_on_dma: bool,
+    // Set once the start of a DMA transfer has been reported, so that resuming runs it
+    dma_reported: bool,
+
+    target: RunTarget,
+    // The address execution was resumed from. Its breakpoint is ignored until an instruction has
+    // executed, so that resuming from a breakpoint doesn't stop straight away.
+    resume_pc: Option<u32>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            next_id: 1,
+            break_on_irq: false,
+            break_on_dma: false,
+            dma_reported: false,
+            target: RunTarget::Continue,
+            resume_pc: None,
+        }
+    }
+
+    fn next_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+impl GBA {
+    // Adds a breakpoint, returning its ID
+    pub fn add_breakpoint(&mut self, addr: u32, condition: Option<BreakCondition>) -> usize {
+        let id = self.debugger.next_id();
+        self.debugger
+            .breakpoints
+            .insert(id, Breakpoint { addr, condition });
+        id
+    }
+
+    // Adds a watchpoint over the given number of bytes, returning its ID
+    pub fn add_watchpoint(&mut self, addr: u32, len: u32, kind: WatchKind) -> usize {
+        let id = self.debugger.next_id();
+        self.debugger
+            .watchpoints
+            .insert(id, Watchpoint { addr, len, kind });
+        self.update_watchpoints();
+        id
+    }
+
+    // Deletes the breakpoint or watchpoint with the given ID, returning whether it existed
+    pub fn delete_breakpoint(&mut self, id: usize) -> bool {
+        if self.debugger.watchpoints.remove(&id).is_some() {
+            self.update_watchpoints();
+            true
+        } else {
+            self.debugger.breakpoints.remove(&id).is_some()
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<usize, Breakpoint> {
+        &self.debugger.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &BTreeMap<usize, Watchpoint> {
+        &self.debugger.watchpoints
+    }
+
+    pub fn set_break_on_irq(&mut self, enabled: bool) {
+        self.debugger.break_on_irq = enabled;
+    }
+
+    pub fn set_break_on_dma(&mut self, enabled: bool) {
+        self.debugger.break_on_dma = enabled;
+    }
+
+    // The value of a register in the current mode, where r15 is the address of the next
+    // instruction
+    pub fn register(&self, n: usize) -> u32 {
+        self.cpu.borrow().register(n)
+    }
+
+    pub fn cpsr(&self) -> u32 {
+        self.cpu.borrow().cpsr()
+    }
+
+    // Reads memory without side effects
+    pub fn peek_memory(&self, addr: u32, len: usize) -> Vec<u8> {
+        let cpu = self.cpu.borrow();
+        (0..len)
+            .map(|i| cpu.peek(addr.wrapping_add(i as u32) as usize))
+            .collect()
+    }
+
+    // Disassembles the instruction at an address, in Thumb or ARM, returning it along with its
+    // encoding
+    pub fn disassemble(&self, addr: u32, thumb: bool) -> (u32, String) {
+        let cpu = self.cpu.borrow();
+        if thumb {
+            let addr = addr & !0b1;
+            let encoding = cpu.peek_u16(addr as usize);
+            let next = cpu.peek_u16(addr.wrapping_add(2) as usize);
+            (encoding as u32, disassemble_thumb(encoding, next, addr))
+        } else {
+            let addr = addr & !0b11;
+            let encoding = cpu.peek_u32(addr as usize);
+            (encoding, disassemble_arm(encoding, addr))
+        }
+    }
+
+    // Sets up the next debugger run to execute the given number of instructions
+    pub fn debug_step(&mut self, n: u64) {
+        self.debugger.target = RunTarget::Instructions(n);
+        self.debugger.resume_pc = Some(self.register(15));
+    }
+
+    // Sets up the next debugger run to execute one instruction, running calls (BL and SWI) until
+    // they return
+    pub fn debug_step_over(&mut self) {
+        let pc = self.register(15);
+        let thumb = self.cpsr() & (1 << 5) != 0;
+        let return_addr = {
+            let cpu = self.cpu.borrow();
+            let width = if thumb { 2 } else { 4 };
+            let instruction = if thumb {
+                Instruction::decode_thumb(cpu.peek_u16(pc as usize)).1
+            } else {
+                Instruction::decode_arm(cpu.peek_u32(pc as usize)).1
+            };
+            match instruction {
+                Instruction::Branch { link: true, .. } | Instruction::SoftwareInterrupt { .. } => {
+                    Some(pc.wrapping_add(width))
+                }
+                // The call is made by the second half of the pair
+                Instruction::ThumbBranchPrefix { .. } => {
+                    let next = cpu.peek_u16(pc.wrapping_add(2) as usize);
+                    match Instruction::decode_thumb(next).1 {
+                        Instruction::ThumbBranchSuffix { .. } => Some(pc.wrapping_add(4)),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }
+        };
+
+        self.debugger.target = match return_addr {
+            Some(addr) => RunTarget::Address {
+                addr,
+                min_sp: self.register(13),
+            },
+            None => RunTarget::Instructions(1),
+        };
+        self.debugger.resume_pc = Some(pc);
+    }
+
+    // Sets up the next debugger run to run until execution reaches an address
+    pub fn debug_run_to(&mut self, addr: u32) {
+        self.debugger.target = RunTarget::Address { addr, min_sp: 0 };
+        self.debugger.resume_pc = Some(self.register(15));
+    }
+
+    // Sets up the next debugger run to run until a breakpoint or another enabled event
+    pub fn debug_continue(&mut self) {
+        self.debugger.target = RunTarget::Continue;
+        self.debugger.resume_pc = Some(self.register(15));
+    }
+
+    // Runs towards the target set up by the last call to one of the functions above, for at most
+    // the given number of cycles. Returns why execution stopped, or None if the cycles ran out
+    // first, in which case calling this again carries on.
+    pub fn debug_run(&mut self, max_cycles: u64) -> Option<StopReason> {
+        if let RunTarget::Instructions(0) = self.debugger.target {
+            return Some(StopReason::Done);
+        }
+        // Accesses made outside the debugger aren't reported
+        self.cpu.borrow_mut().take_watchpoint_hit();
+
+        let end = self.cycles().saturating_add(max_cycles);
+        while self.cycles() < end {
+            if self.is_stopped() {
+                return Some(StopReason::Stopped);
+            }
+            if let Some(reason) = self.debug_step_once() {
+                return Some(reason);
+            }
+        }
+        None
+    }
+
+    // Like `step`, but stops for breakpoints and the other events the debugger watches for
+    fn debug_step_once(&mut self) -> Option<StopReason> {
+        let executed = match self.step_with(Self::debug_check) {
+            Ok(executed) => executed,
+            Err(reason) => return Some(reason),
+        };
+        self.handle_due_events();
+
+        // The step counts even if a watchpoint stops execution first
+        let done = executed && {
+            let (pc, sp) = (self.register(15), self.register(13));
+            match &mut self.debugger.target {
+                RunTarget::Continue => false,
+                RunTarget::Instructions(n) => {
+                    *n -= 1;
+                    *n == 0
+                }
+                RunTarget::Address { addr, min_sp } => pc == *addr && sp >= *min_sp,
+            }
+        };
+
+        let watchpoint_hit = self.cpu.borrow_mut().take_watchpoint_hit();
+        if let Some(hit) = watchpoint_hit {
+            let id = self
+                .debugger
+                .watchpoints
+                .iter()
+                .find(|(_, watchpoint)| watchpoint.matches(hit.addr, hit.write))
+                .map_or(0, |(&id, _)| id);
+            Some(StopReason::Watchpoint(id, hit))
+        } else if done {
+            Some(StopReason::Done)
+        } else {
+            None
+        }
+    }
+
+    // Whether the debugger stops at a point in a step, for DMA, an IRQ or a breakpoint
+    fn debug_check(&mut self, point: StepPoint) -> Option<StopReason> {
+        match point {
+            StepPoint::Dma(channel) => {
+                if self.debugger.break_on_dma && !self.debugger.dma_reported {
+                    self.debugger.dma_reported = true;
+                    return Some(StopReason::DmaStart(channel));
+                }
+                self.debugger.dma_reported = false;
+            }
+            StepPoint::Irq => {
+                if self.debugger.break_on_irq {
+                    return Some(StopReason::Irq);
+                }
+            }
+            StepPoint::Instruction(pc) => {
+                if self.debugger.resume_pc != Some(pc) {
+                    if let Some(id) = self.breakpoint_hit(pc) {
+                        self.debugger.resume_pc = Some(pc);
+                        return Some(StopReason::Breakpoint(id));
+                    }
+                }
+                self.debugger.resume_pc = None;
+            }
+        }
+        None
+    }
+
+    // The ID of the first breakpoint at the address whose condition holds
+    fn breakpoint_hit(&self, pc: u32) -> Option<usize> {
+        self.debugger
+            .breakpoints
+            .iter()
+            .find(|(_, breakpoint)| {
+                breakpoint.addr == pc
+                    && breakpoint
+                        .condition
+                        .is_none_or(|condition| condition.holds(self))
+            })
+            .map(|(&id, _)| id)
+    }
+
+    fn update_watchpoints(&mut self) {
+        let watchpoints = self.debugger.watchpoints.values().copied().collect();
+        self.cpu.borrow_mut().set_watchpoints(watchpoints);
+    }
+}
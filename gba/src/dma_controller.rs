@@ -1,6 +1,7 @@
 use crate::interrupt_controller::{self, InterruptController};
 
-use memory::Memory;
+use cpu::{AccessType, CPU};
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -14,6 +15,8 @@ pub struct DmaController {
     transfers_active: [bool; 4],
     // Internal control registers and source/dest addresses of active channels
     transfers: [(DmaControlReg, usize, usize); 4],
+    // The last value read by a transfer. Halfwords are duplicated into both halves.
+    latch: u32,
 }
 
 impl DmaController {
@@ -46,6 +49,7 @@ impl DmaController {
                 (DmaControlReg(0), 0, 0),
                 (DmaControlReg(0), 0, 0),
             ],
+            latch: 0,
         }
     }
 
@@ -53,15 +57,35 @@ impl DmaController {
         self.transfers_active.iter().any(|&active| active)
     }
 
+    // The channel whose transfer runs next, which is the active one with the highest priority
+    pub fn active_channel(&self) -> Option<usize> {
+        self.transfers_active.iter().position(|&active| active)
+    }
+
+    // The size in bytes of each unit a channel's transfer copies. Sound FIFO transfers always
+    // copy words.
+    pub fn unit_size(&self, channel: usize) -> u32 {
+        let control = &self.transfers[channel].0;
+        let fifo = (channel == 1 || channel == 2) && control.start_timing() == 0b11;
+        if fifo || control.unit_size() {
+            4
+        } else {
+            2
+        }
+    }
+
+    // Runs the highest-priority active transfer, returning the number of cycles it took
     pub fn tick(
         &mut self,
-        memory_rc: Rc<RefCell<dyn Memory>>,
+        cpu: Rc<RefCell<CPU>>,
         interrupt_controller: Rc<RefCell<InterruptController>>,
-    ) {
-        // TODO: Implement accurate transfer timing
-        let mut memory = memory_rc.borrow_mut();
+    ) -> u32 {
+        let mut memory = cpu.borrow_mut();
+        // Starting a transfer takes 2 internal cycles
+        let mut cycles = 2;
         for channel in 0..4 {
             if self.transfers_active[channel] {
+                let unit_size = self.unit_size(channel) as usize;
                 let active_transfer = &mut self.transfers[channel];
                 let mut n_units = match active_transfer.0.n_units() {
                     0 => {
@@ -81,22 +105,37 @@ impl DmaController {
                 if (channel == 1 || channel == 2) && (active_transfer.0.start_timing() == 0b11) {
                     n_units = 4;
                 }
-                for _ in 0..n_units {
-                    let mut unit_size = if active_transfer.0.unit_size() { 4 } else { 2 };
-                    if (channel == 1 || channel == 2) && (active_transfer.0.start_timing() == 0b11)
-                    {
-                        unit_size = 4;
-                    }
+                if channel == 3 {
+                    memory.on_dma3_transfer(active_transfer.2, n_units);
+                }
+                for unit_i in 0..n_units {
+                    // The first read and write are non-sequential, and the rest are sequential
+                    let access = if unit_i == 0 {
+                        AccessType::NonSequential
+                    } else {
+                        AccessType::Sequential
+                    };
+                    cycles += memory.access_cycles(active_transfer.1, unit_size as u32, access);
+                    cycles += memory.access_cycles(active_transfer.2, unit_size as u32, access);
 
                     if !((0x040000B0..=0x040000E1).contains(&active_transfer.1))
                         && !((0x040000B0..=0x040000E1).contains(&active_transfer.2))
                     {
+                        // DMA can't read the BIOS or the unused memory after it, and gets the
+                        // last value it read instead
+                        let readable = active_transfer.1 >= 0x02000000;
                         if unit_size == 4 {
-                            let data = memory.read_u32(active_transfer.1 & !0b11);
-                            memory.write_u32(active_transfer.2 & !0b11, data);
+                            if readable {
+                                self.latch = memory.read_u32(active_transfer.1 & !0b11);
+                            }
+                            memory.write_u32(active_transfer.2 & !0b11, self.latch);
                         } else {
-                            let data = memory.read_u16(active_transfer.1 & !0b1);
-                            memory.write_u16(active_transfer.2 & !0b1, data);
+                            if readable {
+                                let data = memory.read_u16(active_transfer.1 & !0b1) as u32;
+                                self.latch = (data << 16) | data;
+                            }
+                            let data = self.latch >> ((active_transfer.1 & 0b10) * 8);
+                            memory.write_u16(active_transfer.2 & !0b1, data as u16);
                         }
                     }
 
@@ -139,6 +178,8 @@ impl DmaController {
                 break;
             }
         }
+
+        cycles
     }
 
     pub fn on_hblank(&mut self) {
@@ -341,3 +382,37 @@ bitfield! {
   pub u8, byte_2, set_byte_2: 23, 16;
   pub u8, byte_3, set_byte_3: 31, 24;
 }
+
+impl SaveState for DmaController {
+    fn save_state(&self, state: &mut StateWriter) {
+        for channel in 0..4 {
+            state.write_u32(self.source_regs[channel].0);
+            state.write_u32(self.dest_regs[channel].0);
+            state.write_u32(self.control_regs[channel].0);
+
+            let (control_reg, source, dest) = &self.transfers[channel];
+            state.write_bool(self.transfers_active[channel]);
+            state.write_u32(control_reg.0);
+            state.write_u32(*source as u32);
+            state.write_u32(*dest as u32);
+        }
+        state.write_u32(self.latch);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for channel in 0..4 {
+            self.source_regs[channel].0 = state.read_u32()?;
+            self.dest_regs[channel].0 = state.read_u32()?;
+            self.control_regs[channel].0 = state.read_u32()?;
+
+            self.transfers_active[channel] = state.read_bool()?;
+            self.transfers[channel] = (
+                DmaControlReg(state.read_u32()?),
+                state.read_u32()? as usize,
+                state.read_u32()? as usize,
+            );
+        }
+        self.latch = state.read_u32()?;
+        Ok(())
+    }
+}
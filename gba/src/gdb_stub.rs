@@ -0,0 +1,440 @@
+use crate::{StopReason, WatchKind, GBA};
+
+use cpu::OperatingMode;
+use memory::Memory;
+
+use std::cmp;
+use std::collections::{hash_map::Entry, HashMap};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+// The cycles to run for between checks for an interrupt from the client, about a frame
+const RUN_CHUNK: u64 = 280896;
+
+// The most memory read by a single `m` packet, which keeps replies within the packet size
+const MAX_READ: usize = 0x1000;
+
+#[derive(Clone, Copy)]
+enum Register {
+    // A register in the current mode
+    Current(usize),
+    Cpsr,
+    Banked(OperatingMode, usize),
+    Spsr(OperatingMode),
+}
+
+// Serves a GDB client over the remote serial protocol, giving it control of a GBA. The stub
+// describes its registers to the client with a target description: the standard ARM core
+// registers, followed by each mode's banked registers and SPSR.
+pub struct GdbStub {
+    stream: TcpStream,
+    // Bytes received that haven't been handled yet
+    received: Vec<u8>,
+    // Packets are acknowledged until the client turns it off
+    no_ack: bool,
+    // In GDB's numbering
+    registers: Vec<(String, Register)>,
+    // The debugger IDs of the breakpoints and watchpoints the client has set, keyed by the type,
+    // address and kind given in the packets that set them
+    breakpoints: HashMap<(u8, u32, u32), usize>,
+}
+
+impl GdbStub {
+    pub fn new(stream: TcpStream) -> Self {
+        // Packets are small and each waits on a reply, so they shouldn't be held back to be
+        // combined
+        stream.set_nodelay(true).ok();
+        Self {
+            stream,
+            received: Vec::new(),
+            no_ack: false,
+            registers: registers(),
+            breakpoints: HashMap::new(),
+        }
+    }
+
+    // Serves the client until it detaches, kills the session or disconnects. The breakpoints and
+    // watchpoints it set are removed when it leaves.
+    pub fn run(&mut self, gba: &mut GBA) -> io::Result<()> {
+        let result = self.serve(gba);
+        for (_, id) in self.breakpoints.drain() {
+            gba.delete_breakpoint(id);
+        }
+        result
+    }
+
+    fn serve(&mut self, gba: &mut GBA) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match packet.as_str() {
+                "D" => return self.send("OK"),
+                "k" => return Ok(()),
+                _ => {}
+            }
+            let reply = self.handle(gba, &packet)?;
+            self.send(&reply)?;
+            if packet == "QStartNoAckMode" {
+                self.no_ack = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, gba: &mut GBA, packet: &str) -> io::Result<String> {
+        let command = packet.get(..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+        let reply = match command {
+            // Execution is always stopped by a trap when the client attaches
+            "?" => "S05".to_string(),
+            "g" => (0..self.registers.len())
+                .map(|n| hex_u32(self.read_register(gba, n)))
+                .collect(),
+            "G" => {
+                for n in 0..cmp::min(args.len() / 8, self.registers.len()) {
+                    match args.get(n * 8..n * 8 + 8).and_then(parse_hex_u32) {
+                        Some(value) => self.write_register(gba, n, value),
+                        None => return Ok("E01".to_string()),
+                    }
+                }
+                "OK".to_string()
+            }
+            "p" => match parse_hex(args).filter(|&n| (n as usize) < self.registers.len()) {
+                Some(n) => hex_u32(self.read_register(gba, n as usize)),
+                None => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    let n = parse_hex(n).filter(|&n| (n as usize) < self.registers.len())?;
+                    Some((n as usize, parse_hex_u32(value)?))
+                });
+                match parsed {
+                    Some((n, value)) => {
+                        self.write_register(gba, n, value);
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => gba
+                    .peek_memory(addr, cmp::min(len as usize, MAX_READ))
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(addr_len, data)| {
+                    let (addr, len) = parse_addr_len(addr_len)?;
+                    Some((addr, len, parse_bytes(data)?))
+                });
+                match parsed {
+                    Some((addr, len, data)) if data.len() == len as usize => {
+                        write_memory(gba, addr, &data);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => self.update_breakpoint(gba, command == "Z", args),
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(addr) => gba.cpu.borrow_mut().write_register(15, addr),
+                        None => return Ok("E01".to_string()),
+                    }
+                }
+                if command == "c" {
+                    gba.debug_continue();
+                } else {
+                    gba.debug_step(1);
+                }
+                self.resume(gba)?
+            }
+            // There's a single thread
+            "H" => "OK".to_string(),
+            "T" => "OK".to_string(),
+            "q" | "Q" => self.handle_query(packet),
+            // Unsupported packets get an empty reply
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn handle_query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_addr_len(args) {
+                Some((offset, len)) => {
+                    let description = self.target_description();
+                    let start = cmp::min(offset as usize, description.len());
+                    let end = cmp::min(start + len as usize, description.len());
+                    let more = if end < description.len() { "m" } else { "l" };
+                    format!("{}{}", more, &description[start..end])
+                }
+                None => "E01".to_string(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => "OK",
+            "qAttached" => "1",
+            "qC" => "QC1",
+            "qfThreadInfo" => "m1",
+            "qsThreadInfo" => "l",
+            _ => "",
+        }
+        .to_string()
+    }
+
+    fn target_description(&self) -> String {
+        let mut xml = String::from(
+            "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+             <target version=\"1.0\"><architecture>arm</architecture>\
+             <feature name=\"org.gnu.gdb.arm.core\">",
+        );
+        for (n, (name, _)) in self.registers.iter().enumerate() {
+            // The banked registers go in a feature of their own, after the core registers
+            if name == "r8_usr" {
+                xml.push_str("</feature><feature name=\"org.mineral.gba.banked\">");
+            }
+            let register_type = match n {
+                13 => "data_ptr",
+                15 => "code_ptr",
+                _ => "uint32",
+            };
+            xml.push_str(&format!(
+                "<reg name=\"{}\" bitsize=\"32\" type=\"{}\"/>",
+                name, register_type
+            ));
+        }
+        xml.push_str("</feature></target>");
+        xml
+    }
+
+    fn read_register(&self, gba: &GBA, n: usize) -> u32 {
+        let cpu = gba.cpu.borrow();
+        match self.registers[n].1 {
+            Register::Current(n) => cpu.register(n),
+            Register::Cpsr => cpu.cpsr(),
+            Register::Banked(mode, n) => cpu.banked_register(mode, n),
+            Register::Spsr(mode) => cpu.spsr(mode).unwrap_or(0),
+        }
+    }
+
+    // Registers are only written when their value changes, since `G` packets rewrite every
+    // register, and writing the PC or CPSR refills the pipeline
+    fn write_register(&self, gba: &GBA, n: usize, value: u32) {
+        if self.read_register(gba, n) == value {
+            return;
+        }
+        let mut cpu = gba.cpu.borrow_mut();
+        match self.registers[n].1 {
+            Register::Current(n) => cpu.write_register(n, value),
+            Register::Cpsr => cpu.write_cpsr(value),
+            Register::Banked(mode, n) => cpu.set_banked_register(mode, n, value),
+            Register::Spsr(mode) => cpu.set_spsr(mode, value),
+        }
+    }
+
+    // Handles `Z` and `z` packets, of the form `type,addr,kind`. For watchpoints, the kind is the
+    // number of bytes watched.
+    fn update_breakpoint(&mut self, gba: &mut GBA, insert: bool, args: &str) -> String {
+        let mut fields = args.splitn(3, ',');
+        let breakpoint_type = match fields.next().and_then(|field| field.parse::<u8>().ok()) {
+            Some(breakpoint_type) if breakpoint_type <= 4 => breakpoint_type,
+            // Unsupported types get an empty reply
+            _ => return String::new(),
+        };
+        let (addr, kind) = match (
+            fields.next().and_then(parse_hex),
+            fields.next().and_then(parse_hex),
+        ) {
+            (Some(addr), Some(kind)) => (addr, kind),
+            _ => return "E01".to_string(),
+        };
+
+        let key = (breakpoint_type, addr, kind);
+        if insert {
+            if let Entry::Vacant(entry) = self.breakpoints.entry(key) {
+                entry.insert(match breakpoint_type {
+                    0 | 1 => gba.add_breakpoint(addr, None),
+                    2 => gba.add_watchpoint(addr, kind, WatchKind::Write),
+                    3 => gba.add_watchpoint(addr, kind, WatchKind::Read),
+                    _ => gba.add_watchpoint(addr, kind, WatchKind::ReadWrite),
+                });
+            }
+        } else if let Some(id) = self.breakpoints.remove(&key) {
+            gba.delete_breakpoint(id);
+        }
+        "OK".to_string()
+    }
+
+    // Runs until the debugger stops or the client interrupts, returning the stop reply
+    fn resume(&mut self, gba: &mut GBA) -> io::Result<String> {
+        loop {
+            if let Some(reason) = gba.debug_run(RUN_CHUNK) {
+                return Ok(stop_reply(gba, reason));
+            }
+            if self.poll_interrupt()? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+
+    // Checks for the interrupt byte the client sends to stop a running target, without blocking.
+    // A disconnected client counts as an interrupt, and is noticed by the next read.
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        let mut buf = [0; 1024];
+        self.stream.set_nonblocking(true)?;
+        let result = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Ok(true),
+            Ok(n) => {
+                let interrupted = buf[..n].contains(&0x03);
+                self.received
+                    .extend(buf[..n].iter().filter(|&&byte| byte != 0x03));
+                Ok(interrupted)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Reads the next packet, acknowledging it. Returns None once the client disconnects.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Acknowledgements and interrupts outside of packets are skipped
+            match self.received.iter().position(|&byte| byte == b'$') {
+                Some(start) => {
+                    self.received.drain(..start);
+                }
+                None => self.received.clear(),
+            }
+
+            if let Some(end) = self.received.iter().position(|&byte| byte == b'#') {
+                if self.received.len() >= end + 3 {
+                    let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                    if self.no_ack {
+                        return Ok(Some(String::from_utf8_lossy(data).into_owned()));
+                    }
+                    if checksum == Some(packet_checksum(data)) {
+                        self.stream.write_all(b"+")?;
+                        return Ok(Some(String::from_utf8_lossy(data).into_owned()));
+                    }
+                    // The client resends packets that are rejected
+                    self.stream.write_all(b"-")?;
+                    continue;
+                }
+            }
+
+            let mut buf = [0; 4096];
+            match self.stream.read(&mut buf)? {
+                0 => return Ok(None),
+                n => self.received.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    // Sends a packet. Acknowledgements from the client aren't waited for, and resending packets
+    // isn't supported, since the connection is reliable.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, packet_checksum(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+fn registers() -> Vec<(String, Register)> {
+    let mut registers: Vec<_> = (0..13)
+        .map(|n| (format!("r{}", n), Register::Current(n)))
+        .collect();
+    for (n, name) in [(13, "sp"), (14, "lr"), (15, "pc")].iter() {
+        registers.push((name.to_string(), Register::Current(*n)));
+    }
+    registers.push(("cpsr".to_string(), Register::Cpsr));
+
+    // FIQ mode banks r8-r14, and the other exception modes bank r13 and r14
+    for n in 8..15 {
+        registers.push((
+            format!("r{}_usr", n),
+            Register::Banked(OperatingMode::User, n),
+        ));
+    }
+    let modes = [
+        (OperatingMode::FastInterrupt, "fiq", 8),
+        (OperatingMode::Interrupt, "irq", 13),
+        (OperatingMode::Supervisor, "svc", 13),
+        (OperatingMode::Abort, "abt", 13),
+        (OperatingMode::Undefined, "und", 13),
+    ];
+    for &(mode, suffix, first) in modes.iter() {
+        for n in first..15 {
+            registers.push((format!("r{}_{}", n, suffix), Register::Banked(mode, n)));
+        }
+        registers.push((format!("spsr_{}", suffix), Register::Spsr(mode)));
+    }
+    registers
+}
+
+fn stop_reply(gba: &GBA, reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint(id, hit) => {
+            let kind = match gba.watchpoints().get(&id).map(|watchpoint| watchpoint.kind) {
+                Some(WatchKind::Write) => "watch",
+                Some(WatchKind::Read) => "rwatch",
+                _ => "awatch",
+            };
+            format!("T05{}:{:x};", kind, hit.addr)
+        }
+        // Everything else stops with a trap, and the client works out why from the PC
+        _ => "S05".to_string(),
+    }
+}
+
+// Writes memory with the same side effects as a write from the CPU, but without triggering
+// watchpoints
+fn write_memory(gba: &GBA, addr: u32, data: &[u8]) {
+    let mut cpu = gba.cpu.borrow_mut();
+    for (i, &byte) in data.iter().enumerate() {
+        cpu.write(addr.wrapping_add(i as u32) as usize, byte);
+    }
+    cpu.take_watchpoint_hit();
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// Registers are sent as their bytes in target order, which is little-endian
+fn hex_u32(value: u32) -> String {
+    format!("{:08x}", value.swap_bytes())
+}
+
+fn parse_hex_u32(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok().map(u32::swap_bytes)
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+fn parse_addr_len(s: &str) -> Option<(u32, u32)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -1,4 +1,4 @@
-use memory::Memory;
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
 
 pub const IRQ_VBLANK: usize = 0x0;
 pub const IRQ_HBLANK: usize = 0x1;
@@ -31,7 +31,12 @@ impl InterruptController {
     }
 
     pub fn has_interrupt(&self) -> bool {
-        self.master_enable_reg.enable() && ((self.request_reg.0 & self.enable_reg.0) != 0)
+        self.master_enable_reg.enable() && self.pending() != 0
+    }
+
+    // The interrupts that are both enabled and requested, regardless of IME
+    pub fn pending(&self) -> u16 {
+        self.request_reg.0 & self.enable_reg.0
     }
 
     pub fn request(&mut self, offset: usize) {
@@ -46,7 +51,6 @@ impl Memory for InterruptController {
             0x201 => self.enable_reg.hi_byte(),
             0x202 => self.request_reg.lo_byte(),
             0x203 => self.request_reg.hi_byte(),
-            0x204 => 3,
 
             0x208 => self.master_enable_reg.byte_0(),
             0x209 => self.master_enable_reg.byte_1(),
@@ -136,3 +140,18 @@ bitfield! {
   pub u8, lo_byte, set_lo_byte: 7, 0;
   pub u8, hi_byte, set_hi_byte: 15, 8;
 }
+
+impl SaveState for InterruptController {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.master_enable_reg.0);
+        state.write_u16(self.enable_reg.0);
+        state.write_u16(self.request_reg.0);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.master_enable_reg.0 = state.read_u32()?;
+        self.enable_reg.0 = state.read_u16()?;
+        self.request_reg.0 = state.read_u16()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt;
+
+// The IO registers, as (offset from 0x04000000, size in bytes, name)
+const IO_REGISTERS: &[(u32, u32, &str)] = &[
+    // LCD
+    (0x000, 2, "DISPCNT"),
+    (0x002, 2, "GREENSWP"),
+    (0x004, 2, "DISPSTAT"),
+    (0x006, 2, "VCOUNT"),
+    (0x008, 2, "BG0CNT"),
+    (0x00A, 2, "BG1CNT"),
+    (0x00C, 2, "BG2CNT"),
+    (0x00E, 2, "BG3CNT"),
+    (0x010, 2, "BG0HOFS"),
+    (0x012, 2, "BG0VOFS"),
+    (0x014, 2, "BG1HOFS"),
+    (0x016, 2, "BG1VOFS"),
+    (0x018, 2, "BG2HOFS"),
+    (0x01A, 2, "BG2VOFS"),
+    (0x01C, 2, "BG3HOFS"),
+    (0x01E, 2, "BG3VOFS"),
+    (0x020, 2, "BG2PA"),
+    (0x022, 2, "BG2PB"),
+    (0x024, 2, "BG2PC"),
+    (0x026, 2, "BG2PD"),
+    (0x028, 4, "BG2X"),
+    (0x02C, 4, "BG2Y"),
+    (0x030, 2, "BG3PA"),
+    (0x032, 2, "BG3PB"),
+    (0x034, 2, "BG3PC"),
+    (0x036, 2, "BG3PD"),
+    (0x038, 4, "BG3X"),
+    (0x03C, 4, "BG3Y"),
+    (0x040, 2, "WIN0H"),
+    (0x042, 2, "WIN1H"),
+    (0x044, 2, "WIN0V"),
+    (0x046, 2, "WIN1V"),
+    (0x048, 2, "WININ"),
+    (0x04A, 2, "WINOUT"),
+    (0x04C, 2, "MOSAIC"),
+    (0x050, 2, "BLDCNT"),
+    (0x052, 2, "BLDALPHA"),
+    (0x054, 2, "BLDY"),
+    // Sound
+    (0x060, 2, "SOUND1CNT_L"),
+    (0x062, 2, "SOUND1CNT_H"),
+    (0x064, 2, "SOUND1CNT_X"),
+    (0x068, 2, "SOUND2CNT_L"),
+    (0x06C, 2, "SOUND2CNT_H"),
+    (0x070, 2, "SOUND3CNT_L"),
+    (0x072, 2, "SOUND3CNT_H"),
+    (0x074, 2, "SOUND3CNT_X"),
+    (0x078, 2, "SOUND4CNT_L"),
+    (0x07C, 2, "SOUND4CNT_H"),
+    (0x080, 2, "SOUNDCNT_L"),
+    (0x082, 2, "SOUNDCNT_H"),
+    (0x084, 2, "SOUNDCNT_X"),
+    (0x088, 2, "SOUNDBIAS"),
+    (0x090, 16, "WAVE_RAM"),
+    (0x0A0, 4, "FIFO_A"),
+    (0x0A4, 4, "FIFO_B"),
+    // DMA
+    (0x0B0, 4, "DMA0SAD"),
+    (0x0B4, 4, "DMA0DAD"),
+    (0x0B8, 2, "DMA0CNT_L"),
+    (0x0BA, 2, "DMA0CNT_H"),
+    (0x0BC, 4, "DMA1SAD"),
+    (0x0C0, 4, "DMA1DAD"),
+    (0x0C4, 2, "DMA1CNT_L"),
+    (0x0C6, 2, "DMA1CNT_H"),
+    (0x0C8, 4, "DMA2SAD"),
+    (0x0CC, 4, "DMA2DAD"),
+    (0x0D0, 2, "DMA2CNT_L"),
+    (0x0D2, 2, "DMA2CNT_H"),
+    (0x0D4, 4, "DMA3SAD"),
+    (0x0D8, 4, "DMA3DAD"),
+    (0x0DC, 2, "DMA3CNT_L"),
+    (0x0DE, 2, "DMA3CNT_H"),
+    // Timers
+    (0x100, 2, "TM0CNT_L"),
+    (0x102, 2, "TM0CNT_H"),
+    (0x104, 2, "TM1CNT_L"),
+    (0x106, 2, "TM1CNT_H"),
+    (0x108, 2, "TM2CNT_L"),
+    (0x10A, 2, "TM2CNT_H"),
+    (0x10C, 2, "TM3CNT_L"),
+    (0x10E, 2, "TM3CNT_H"),
+    // Serial communication
+    (0x120, 2, "SIOMULTI0"),
+    (0x122, 2, "SIOMULTI1"),
+    (0x124, 2, "SIOMULTI2"),
+    (0x126, 2, "SIOMULTI3"),
+    (0x128, 2, "SIOCNT"),
+    (0x12A, 2, "SIOMLT_SEND"),
+    // Keypad
+    (0x130, 2, "KEYINPUT"),
+    (0x132, 2, "KEYCNT"),
+    (0x134, 2, "RCNT"),
+    (0x140, 2, "JOYCNT"),
+    (0x150, 4, "JOY_RECV"),
+    (0x154, 4, "JOY_TRANS"),
+    (0x158, 2, "JOYSTAT"),
+    // Interrupts and system control
+    (0x200, 2, "IE"),
+    (0x202, 2, "IF"),
+    (0x204, 2, "WAITCNT"),
+    (0x208, 2, "IME"),
+    (0x300, 1, "POSTFLG"),
+    (0x301, 1, "HALTCNT"),
+];
+
+// The name of the IO register containing an address, and the address's offset into it
+pub fn io_register_name(addr: u32) -> Option<(&'static str, u32)> {
+    let offset = addr.checked_sub(0x04000000)?;
+    IO_REGISTERS
+        .iter()
+        .find(|&&(start, size, _)| (start..start + size).contains(&offset))
+        .map(|&(start, _, name)| (name, offset - start))
+}
+
+// What made an IO access
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessSource {
+    // The instruction at the given address
+    Cpu(u32),
+    // A transfer on the given DMA channel
+    Dma(usize),
+}
+
+// A read or write of the IO registers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IoAccess {
+    // The cycle count at the start of the instruction or transfer that made the access
+    pub time: u64,
+    pub source: AccessSource,
+    pub addr: u32,
+    // In bytes
+    pub size: u32,
+    pub write: bool,
+    pub value: u32,
+    // Whether the emulator implements the register. Writes to the rest are ignored, and reads of
+    // them return 0.
+    pub implemented: bool,
+}
+
+// Called with each access to the IO registers, once the instruction or transfer making it ends
+pub type IoObserver = Box<dyn FnMut(&IoAccess)>;
+
+impl IoAccess {
+    pub fn register(&self) -> Option<&'static str> {
+        io_register_name(self.addr).map(|(name, _)| name)
+    }
+}
+
+// e.g. `    280896 pc=08000124 W16 04000000 DISPCNT = 0403`
+impl fmt::Display for IoAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let source = match self.source {
+            AccessSource::Cpu(pc) => format!("pc={:08X}", pc),
+            AccessSource::Dma(channel) => format!("dma{}", channel),
+        };
+        let register = match io_register_name(self.addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => "?".to_string(),
+        };
+        write!(
+            f,
+            "{:>10} {:<11} {}{:<2} {:08X} {} = {:0width$X}",
+            self.time,
+            source,
+            if self.write { 'W' } else { 'R' },
+            self.size * 8,
+            self.addr,
+            register,
+            self.value,
+            width = self.size as usize * 2,
+        )?;
+        if !self.implemented {
+            write!(f, " (unimplemented)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ByteAccess {
+    addr: u32,
+    write: bool,
+    value: u8,
+    implemented: bool,
+}
+
+// Records the IO accesses made through the memory map. They arrive a byte at a time, so the bytes
+// an instruction or transfer accessed are grouped back into the accesses it made once it's done.
+#[derive(Default)]
+pub(crate) struct IoRecorder {
+    bytes: Vec<ByteAccess>,
+}
+
+impl IoRecorder {
+    pub fn record(&mut self, addr: u32, write: bool, value: u8, implemented: bool) {
+        self.bytes.push(ByteAccess {
+            addr,
+            write,
+            value,
+            implemented,
+        });
+    }
+
+    // Groups the bytes recorded since the last call into accesses of up to `max_size` bytes.
+    // Consecutive bytes read or written within the same word are taken to be one access, which
+    // is exact for the CPU since it only makes aligned accesses of one register at a time.
+    pub fn take(&mut self, time: u64, source: AccessSource, max_size: u32) -> Vec<IoAccess> {
+        let mut accesses: Vec<IoAccess> = Vec::new();
+        for byte in self.bytes.drain(..) {
+            if let Some(access) = accesses.last_mut() {
+                if access.write == byte.write
+                    && access.size < max_size
+                    && access.addr + access.size == byte.addr
+                    && access.addr & !0b11 == byte.addr & !0b11
+                {
+                    access.value |= (byte.value as u32) << (access.size * 8);
+                    access.size += 1;
+                    access.implemented &= byte.implemented;
+                    continue;
+                }
+            }
+            accesses.push(IoAccess {
+                time,
+                source,
+                addr: byte.addr,
+                size: 1,
+                write: byte.write,
+                value: byte.value as u32,
+                implemented: byte.implemented,
+            });
+        }
+        accesses
+    }
+}
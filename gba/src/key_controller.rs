@@ -1,32 +1,79 @@
-use memory::Memory;
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
 
 pub struct KeyController {
     state: u16,
+    control_reg: KeyControlReg,
 }
 
 impl KeyController {
     pub fn new() -> Self {
-        Self { state: 0xFF }
+        Self {
+            state: 0xFF,
+            control_reg: KeyControlReg(0),
+        }
     }
 
     pub fn set_state(&mut self, data: u16) {
         self.state = data;
     }
+
+    // Whether the pressed keys meet the keypad interrupt condition
+    pub fn irq_requested(&self) -> bool {
+        // Keys are active-low
+        let pressed = !self.state & 0x3FF;
+        let selected = self.control_reg.keys();
+        self.control_reg.irq_enable()
+            && if self.control_reg.irq_condition_and() {
+                selected != 0 && pressed & selected == selected
+            } else {
+                pressed & selected != 0
+            }
+    }
 }
 
 impl Memory for KeyController {
     fn peek(&self, addr: usize) -> u8 {
-        // TODO: Key Interrupt Control
-        if addr == 0x130 {
-            (self.state & 0xFF) as u8
-        } else if addr == 0x131 {
-            ((self.state >> 8) & 0xFF) as u8
-        } else {
-            0
+        match addr {
+            0x130 => (self.state & 0xFF) as u8,
+            0x131 => ((self.state >> 8) & 0xFF) as u8,
+            0x132 => self.control_reg.lo_byte(),
+            0x133 => self.control_reg.hi_byte(),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: usize, data: u8) {
+        match addr {
+            0x132 => self.control_reg.set_lo_byte(data),
+            0x133 => self.control_reg.set_hi_byte(data),
+            _ => {}
         }
     }
+}
+
+bitfield! {
+  /// 4000132h - KEYCNT - Key Interrupt Control
+  pub struct KeyControlReg(u16);
+  impl Debug;
+  pub u16, keys, _: 9, 0;
+  pub irq_enable, _: 14;
+  /// If set, the interrupt is requested when all of the selected keys are pressed, rather than
+  /// any of them
+  pub irq_condition_and, _: 15;
+
+  pub u8, lo_byte, set_lo_byte: 7, 0;
+  pub u8, hi_byte, set_hi_byte: 15, 8;
+}
+
+impl SaveState for KeyController {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.state);
+        state.write_u16(self.control_reg.0);
+    }
 
-    fn write(&mut self, _addr: usize, _data: u8) {
-        // TODO
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.state = state.read_u16()?;
+        self.control_reg.0 = state.read_u16()?;
+        Ok(())
     }
 }
@@ -1,36 +1,89 @@
 #[macro_use]
 extern crate bitfield;
 
+mod cheats;
+mod debugger;
 mod dma_controller;
+mod gdb_stub;
 mod interrupt_controller;
+mod io_log;
 mod key_controller;
+mod scheduler;
 mod timer_controller;
 
+use crate::cheats::CheatEngine;
+use crate::debugger::Debugger;
 use crate::dma_controller::DmaController;
 use crate::interrupt_controller::InterruptController;
+use crate::io_log::IoRecorder;
 use crate::key_controller::KeyController;
+use crate::scheduler::{Event, Scheduler};
 use crate::timer_controller::TimerController;
 
-use cpu::CPU;
-use memory::{Memory, RAM};
+pub use crate::cheats::{Cheat, CheatError, CheatFormat};
+pub use crate::debugger::{BreakCondition, Breakpoint, Comparison, Operand, StopReason};
+pub use crate::gdb_stub::GdbStub;
+pub use crate::io_log::{io_register_name, AccessSource, IoAccess, IoObserver};
+pub use cpu::{BackupType, Cartridge, CartridgeError, CartridgeHeader, Peripherals};
+pub use cpu::{OperatingMode, TraceEntry, TraceFilter, Tracer};
+pub use cpu::{WatchKind, Watchpoint, WatchpointHit};
+pub use memory::StateError;
+
+use cpu::{HaltMode, CPU};
+use memory::{Memory, SaveState, StateReader, StateWriter, RAM};
 use ppu::PPU;
 use sound::{AudioRingBuffer, SoundController};
 
 use std::cell::RefCell;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
+// Save states begin with a header of the magic number, the format version, and the CRC-32 of the
+// cartridge ROM they were saved with
+const SAVE_STATE_MAGIC: u32 = u32::from_le_bytes(*b"MNRL");
+const SAVE_STATE_VERSION: u32 = 10;
+
+// The output of a single emulated frame
+pub struct Frame {
+    pub framebuffer: [u8; 240 * 160 * 2],
+    // The audio samples produced while emulating the frame
+    pub audio: Vec<f32>,
+}
+
+// How often an idle loop was skipped, for debugging idle loop detection
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IdleLoopStats {
+    pub detections: u64,
+    pub cycles_skipped: u64,
+}
+
 pub struct GBA {
     cpu: Rc<RefCell<CPU>>,
+    mmu: Rc<RefCell<MemoryMap>>,
     ppu: Rc<RefCell<PPU>>,
 
+    vram: Rc<RefCell<RAM<0x18000>>>,
+    palette_ram: Rc<RefCell<RAM<0x400>>>,
+    oam: Rc<RefCell<RAM<0x400>>>,
+
     sound_controller: Rc<RefCell<SoundController>>,
     key_controller: Rc<RefCell<KeyController>>,
     dma_controller: Rc<RefCell<DmaController>>,
     timer_controller: Rc<RefCell<TimerController>>,
     interrupt_controller: Rc<RefCell<InterruptController>>,
 
+    scheduler: Rc<RefCell<Scheduler>>,
     audio_buffer: Arc<Mutex<AudioRingBuffer>>,
+    // If set, used instead of the backup type detected from the ROM
+    backup_type_override: Option<BackupType>,
+    // Keyed by the start address of each idle loop
+    idle_loop_stats: BTreeMap<u32, IdleLoopStats>,
+    debugger: Debugger,
+    cheats: CheatEngine,
+    io_observer: Option<IoObserver>,
 }
 
 impl GBA {
@@ -45,12 +98,13 @@ impl GBA {
             oam.clone(),
         )));
 
+        let scheduler = Rc::new(RefCell::new(Scheduler::new()));
         let audio_buffer = Arc::new(Mutex::new(AudioRingBuffer::new()));
 
         let sound_controller = Rc::new(RefCell::new(SoundController::new(audio_buffer.clone())));
         let key_controller = Rc::new(RefCell::new(KeyController::new()));
         let dma_controller = Rc::new(RefCell::new(DmaController::new()));
-        let timer_controller = Rc::new(RefCell::new(TimerController::new()));
+        let timer_controller = Rc::new(RefCell::new(TimerController::new(scheduler.clone())));
         let interrupt_controller = Rc::new(RefCell::new(InterruptController::new()));
 
         let mmu = Rc::new(RefCell::new(MemoryMap {
@@ -63,76 +117,317 @@ impl GBA {
             dma_controller: dma_controller.clone(),
             timer_controller: timer_controller.clone(),
             interrupt_controller: interrupt_controller.clone(),
+            scheduler: scheduler.clone(),
+            io_recorder: None,
         }));
 
         let cpu = Rc::new(RefCell::new(CPU::new(mmu.clone())));
 
+        {
+            let mut scheduler = scheduler.borrow_mut();
+            scheduler.schedule(Event::Ppu, ppu.borrow().cycles_until_event() as u64);
+            scheduler.schedule(Event::SoundSample, 0);
+        }
+
         Self {
             cpu,
+            mmu,
             ppu,
+            vram,
+            palette_ram,
+            oam,
             sound_controller,
             key_controller,
             dma_controller,
             timer_controller,
             interrupt_controller,
+            scheduler,
             audio_buffer,
+            backup_type_override: None,
+            idle_loop_stats: BTreeMap::new(),
+            debugger: Debugger::new(),
+            cheats: CheatEngine::new(),
+            io_observer: None,
         }
     }
 
+    // Runs the CPU (or DMA) until the next scheduled event, then handles all due events
     pub fn tick(&mut self) {
-        if !self.dma_controller.borrow().is_active() {
-            if self.interrupt_controller.borrow().has_interrupt() {
-                self.cpu.borrow_mut().irq();
+        while self.scheduler.borrow().now() < self.scheduler.borrow().next_event_time() {
+            if self.is_stopped() {
+                return;
             }
-
-            self.cpu.borrow_mut().tick();
+            self.step();
         }
+        self.handle_due_events();
 
-        // TODO: This loop is an artificial and highly imperfect compensation for the lack of CPU
-        // pipeline emulation. The imperfect timing breaks some visuals (like the 3D effect in
-        // Monkey Ball Jr., I believe) and leads to sound desync.
-        for _ in 0..3 {
-            let (vblank, hblank, vblank_irq, hblank_irq, vcounter_irq) =
-                self.ppu.borrow_mut().tick();
+        // TODO: When a frame is ready, the GBA should expose the framebuffer,
+        // TODO: and the frontend can read it AND THEN update keypad state
+    }
 
-            if vblank {
-                self.dma_controller.borrow_mut().on_vblank();
+    // Runs until the PPU completes a frame, returning it along with the audio produced meanwhile
+    pub fn run_frame(&mut self) -> Frame {
+        // A frame completed by `run_cycles` or `run_until` would otherwise be returned straight
+        // away, without any emulation
+        self.ppu.borrow_mut().discard_frame();
+        let audio_start = self.audio_buffer.lock().unwrap().write_cursor;
+        let framebuffer = loop {
+            self.tick();
+            if let Some(framebuffer) = self.try_get_framebuffer() {
+                break framebuffer;
             }
-            if hblank {
-                self.dma_controller.borrow_mut().on_hblank();
+            // The LCD is off in stop mode, and time doesn't pass until a key wakes the CPU
+            if self.is_stopped() {
+                break [0xFF; 240 * 160 * 2];
             }
+        };
+
+        let audio_buffer = self.audio_buffer.lock().unwrap();
+        let mask = audio_buffer.buffer.len() - 1;
+        let audio = (audio_start..audio_buffer.write_cursor)
+            .map(|i| audio_buffer.buffer[i & mask])
+            .collect();
+        Frame { framebuffer, audio }
+    }
 
-            if vblank_irq {
-                self.interrupt_controller
-                    .borrow_mut()
-                    .request(interrupt_controller::IRQ_VBLANK);
+    // Runs for at least the given number of cycles, stopping at the first instruction boundary
+    // at or after that point
+    pub fn run_cycles(&mut self, cycles: u64) {
+        let target = self.cycles() + cycles;
+        while self.cycles() < target && !self.is_stopped() {
+            let next_stop = cmp::min(self.scheduler.borrow().next_event_time(), target);
+            while self.scheduler.borrow().now() < next_stop {
+                self.step();
             }
-            if hblank_irq {
-                self.interrupt_controller
-                    .borrow_mut()
-                    .request(interrupt_controller::IRQ_HBLANK);
+            self.handle_due_events();
+        }
+    }
+
+    // Runs one instruction at a time until the predicate holds, checking after every instruction
+    pub fn run_until<F: FnMut(&GBA) -> bool>(&mut self, mut predicate: F) {
+        while !self.is_stopped() {
+            self.step();
+            self.handle_due_events();
+            if predicate(self) {
+                break;
             }
-            if vcounter_irq {
-                self.interrupt_controller
-                    .borrow_mut()
-                    .request(interrupt_controller::IRQ_VCOUNTER);
+        }
+    }
+
+    // The number of cycles elapsed since power-on
+    pub fn cycles(&self) -> u64 {
+        self.scheduler.borrow().now()
+    }
+
+    // Whether the CPU is in stop mode, in which nothing runs until a keypad interrupt
+    fn is_stopped(&self) -> bool {
+        self.cpu.borrow().halt_mode() == Some(HaltMode::Stop)
+    }
+
+    // Wakes the CPU from a low-power mode if an interrupt that ends it has been requested. This
+    // doesn't depend on IME.
+    fn check_wake(&mut self) {
+        let pending = self.interrupt_controller.borrow().pending();
+        let wakes = match self.cpu.borrow().halt_mode() {
+            None => false,
+            Some(HaltMode::Halt) => pending != 0,
+            Some(HaltMode::Stop) => {
+                let stop_wake_mask = (1 << interrupt_controller::IRQ_KEYPAD)
+                    | (1 << interrupt_controller::IRQ_GAMEPAK)
+                    | (1 << interrupt_controller::IRQ_SERIAL);
+                pending & stop_wake_mask != 0
             }
+        };
+        if wakes {
+            self.cpu.borrow_mut().wake();
+        }
+    }
+
+    // Runs a single CPU instruction, or a DMA transfer if one is active. While the CPU is halted,
+    // skips ahead to the next event, since only an event can request an interrupt.
+    fn step(&mut self) {
+        let _ = self.step_with(|_, _| None::<()>);
+    }
 
-            let sound_dma_requested = self.sound_controller.borrow_mut().tick();
-            if sound_dma_requested {
-                self.dma_controller.borrow_mut().on_dma_sound_request();
+    // Like `step`, but calls `check` at each point the step reaches, stopping there if it returns
+    // a reason to. Returns whether an instruction was executed.
+    fn step_with<T>(
+        &mut self,
+        mut check: impl FnMut(&mut GBA, StepPoint) -> Option<T>,
+    ) -> Result<bool, T> {
+        self.check_wake();
+        let active_dma = self.dma_controller.borrow().active_channel();
+        let mut executed = false;
+        let cycles = if let Some(channel) = active_dma {
+            if let Some(stop) = check(self, StepPoint::Dma(channel)) {
+                return Err(stop);
             }
-            self.timer_controller.borrow_mut().tick(
-                self.interrupt_controller.clone(),
-                self.sound_controller.clone(),
-            );
-            self.dma_controller
-                .borrow_mut()
-                .tick(self.cpu.clone(), self.interrupt_controller.clone());
+            self.run_dma()
+        } else if self.cpu.borrow().halt_mode().is_some() {
+            self.halted_cycles()
+        } else {
+            let irq_taken =
+                self.interrupt_controller.borrow().has_interrupt() && self.cpu.borrow_mut().irq();
+            if irq_taken {
+                if let Some(stop) = check(self, StepPoint::Irq) {
+                    return Err(stop);
+                }
+            }
+            let pc = self.cpu.borrow().pc();
+            if let Some(stop) = check(self, StepPoint::Instruction(pc)) {
+                return Err(stop);
+            }
+            executed = true;
+            self.run_instruction()
+        };
+        self.scheduler.borrow_mut().advance(cycles);
+        Ok(executed)
+    }
+
+    // Runs the active DMA transfer with the highest priority, returning the cycles it took
+    fn run_dma(&mut self) -> u32 {
+        let time = self.cycles();
+        let channel = self.dma_controller.borrow().active_channel();
+        let cycles = self
+            .dma_controller
+            .borrow_mut()
+            .tick(self.cpu.clone(), self.interrupt_controller.clone());
+        if let Some(channel) = channel {
+            let unit_size = self.dma_controller.borrow().unit_size(channel);
+            self.observe_io(time, AccessSource::Dma(channel), unit_size);
         }
+        cycles
+    }
 
-        // TODO: When a frame is ready, the GBA should expose the framebuffer,
-        // TODO: and the frontend can read it AND THEN update keypad state
+    // The cycles to skip while the CPU is halted, which is until the next event
+    fn halted_cycles(&self) -> u32 {
+        let scheduler = self.scheduler.borrow();
+        let until_event = scheduler.next_event_time().saturating_sub(scheduler.now());
+        cmp::max(1, cmp::min(until_event, u32::MAX as u64) as u32)
+    }
+
+    // Runs a single CPU instruction, returning the cycles it took along with any idle time skipped
+    fn run_instruction(&mut self) -> u32 {
+        let time = self.cycles();
+        let pc = self.cpu.borrow().pc();
+        let cycles = self.cpu.borrow_mut().tick();
+        self.observe_io(time, AccessSource::Cpu(pc), 4);
+        cycles + self.skip_idle_loop(cycles)
+    }
+
+    // Calls the observer with every access to the IO registers made by an instruction or DMA
+    // transfer from now on, to see which registers a game uses and when. The registers the CPU
+    // handles itself (WAITCNT, POSTFLG and HALTCNT) aren't observed.
+    pub fn set_io_observer(&mut self, observer: Option<IoObserver>) {
+        self.mmu.borrow_mut().io_recorder = observer.as_ref().map(|_| IoRecorder::default());
+        self.io_observer = observer;
+    }
+
+    // Passes the IO accesses made by the last instruction or transfer to the observer
+    fn observe_io(&mut self, time: u64, source: AccessSource, max_size: u32) {
+        if let Some(observer) = &mut self.io_observer {
+            let accesses = match &mut self.mmu.borrow_mut().io_recorder {
+                Some(recorder) => recorder.take(time, source, max_size),
+                None => return,
+            };
+            for access in accesses.iter() {
+                observer(access);
+            }
+        }
+    }
+
+    // If the last instruction closed an idle loop, nothing changes until the next event, so the
+    // time until then is skipped. Returns the number of cycles skipped.
+    fn skip_idle_loop(&mut self, cycles: u32) -> u32 {
+        let addr = match self.cpu.borrow_mut().take_idle_loop() {
+            Some(addr) => addr,
+            None => return 0,
+        };
+        if self.interrupt_controller.borrow().has_interrupt() {
+            return 0;
+        }
+
+        let scheduler = self.scheduler.borrow();
+        let until_event = scheduler
+            .next_event_time()
+            .saturating_sub(scheduler.now() + cycles as u64);
+        let skipped = cmp::min(until_event, u32::MAX as u64) as u32;
+
+        let stats = self.idle_loop_stats.entry(addr).or_default();
+        stats.detections += 1;
+        stats.cycles_skipped += skipped as u64;
+        skipped
+    }
+
+    fn handle_due_events(&mut self) {
+        loop {
+            let event = self.scheduler.borrow_mut().pop_due();
+            match event {
+                Some((time, event)) => {
+                    self.cpu.borrow_mut().on_event();
+                    self.handle_event(time, event);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn handle_event(&mut self, time: u64, event: Event) {
+        match event {
+            Event::Ppu => {
+                let (vblank, hblank, vblank_irq, hblank_irq, vcounter_irq) =
+                    self.ppu.borrow_mut().step();
+
+                if vblank {
+                    self.dma_controller.borrow_mut().on_vblank();
+                    self.apply_cheats();
+                }
+                if hblank {
+                    self.dma_controller.borrow_mut().on_hblank();
+                }
+
+                if vblank_irq {
+                    self.interrupt_controller
+                        .borrow_mut()
+                        .request(interrupt_controller::IRQ_VBLANK);
+                }
+                if hblank_irq {
+                    self.interrupt_controller
+                        .borrow_mut()
+                        .request(interrupt_controller::IRQ_HBLANK);
+                }
+                if vcounter_irq {
+                    self.interrupt_controller
+                        .borrow_mut()
+                        .request(interrupt_controller::IRQ_VCOUNTER);
+                }
+
+                let next = time + self.ppu.borrow().cycles_until_event() as u64;
+                self.scheduler.borrow_mut().schedule(Event::Ppu, next);
+            }
+            Event::SoundSample => {
+                let cycles_until_sample = {
+                    let mut sound_controller = self.sound_controller.borrow_mut();
+                    sound_controller.sync(time);
+                    sound_controller.sample()
+                };
+                let next = time + cycles_until_sample as u64;
+                self.scheduler
+                    .borrow_mut()
+                    .schedule(Event::SoundSample, next);
+            }
+            Event::TimerOverflow(i) => {
+                let sound_dma_requested = self.timer_controller.borrow_mut().on_overflow(
+                    i,
+                    time,
+                    &self.interrupt_controller,
+                    &self.sound_controller,
+                );
+                if sound_dma_requested {
+                    self.dma_controller.borrow_mut().on_dma_sound_request();
+                }
+            }
+        }
     }
 
     pub fn try_get_framebuffer(&mut self) -> Option<[u8; 240 * 160 * 2]> {
@@ -147,16 +442,200 @@ impl GBA {
         self.cpu.borrow_mut().flash_bios(data);
     }
 
-    // TODO: Replace with inserting/ejecting model
-    pub fn flash_cart(&mut self, data: Vec<u8>) {
-        self.cpu.borrow_mut().flash_cart(data);
+    // Idle loop detection is on by default, and can be turned off if it's suspected of causing
+    // inaccuracies
+    pub fn set_idle_loop_detection(&mut self, enabled: bool) {
+        self.cpu.borrow_mut().set_idle_loop_detection(enabled);
+    }
+
+    pub fn idle_loop_stats(&self) -> &BTreeMap<u32, IdleLoopStats> {
+        &self.idle_loop_stats
+    }
+
+    // Traces the instructions the CPU executes, or stops tracing if None
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.borrow_mut().set_tracer(tracer);
+    }
+
+    // Writes the most recent instructions traced, if there's a tracer
+    pub fn dump_trace(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        match self.cpu.borrow().tracer() {
+            Some(tracer) => tracer.dump(out),
+            None => Ok(()),
+        }
+    }
+
+    // Boots directly into the cartridge, skipping the BIOS intro. Call this before running.
+    pub fn skip_bios(&mut self) {
+        self.cpu.borrow_mut().skip_bios();
+    }
+
+    // Switches back to the built-in high-level emulation of the BIOS, which is used until a BIOS
+    // image is flashed
+    pub fn use_hle_bios(&mut self) {
+        self.cpu.borrow_mut().use_hle_bios();
+    }
+
+    // Inserts a cartridge, returning the previously inserted one (if any). The machine isn't
+    // reset, so this is normally done before running. A cartridge already holding a save of
+    // another size keeps its backup type, rather than the overridden one.
+    pub fn insert_cartridge(&mut self, mut cartridge: Cartridge) -> Option<Cartridge> {
+        if let Some(backup_type) = self.backup_type_override {
+            let _ = cartridge.set_backup_type(backup_type);
+        }
+        self.cpu.borrow_mut().insert_cartridge(cartridge)
+    }
+
+    pub fn eject_cartridge(&mut self) -> Option<Cartridge> {
+        self.cpu.borrow_mut().eject_cartridge()
+    }
+
+    pub fn cartridge_header(&self) -> Option<CartridgeHeader> {
+        self.cpu.borrow().cartridge().map(|c| c.header().clone())
+    }
+
+    pub fn backup_type(&self) -> Option<BackupType> {
+        self.cpu.borrow().cartridge().map(|c| c.backup_type())
+    }
+
+    // Forces the backup type instead of detecting it from the ROM, for the few games whose ID
+    // strings are misleading. This is normally done before importing the save, since the inserted
+    // cartridge's save is only kept if the new type is the same size, and otherwise the override
+    // is refused. Passing None restores detection for the next cartridge inserted.
+    pub fn override_backup_type(
+        &mut self,
+        backup_type: Option<BackupType>,
+    ) -> Result<(), CartridgeError> {
+        if let (Some(backup_type), Some(cartridge)) =
+            (backup_type, self.cpu.borrow_mut().cartridge_mut())
+        {
+            cartridge.set_backup_type(backup_type)?;
+        }
+        self.backup_type_override = backup_type;
+        Ok(())
+    }
+
+    // Exports the raw contents of the cartridge's battery-backed memory
+    pub fn export_backup(&self) -> Vec<u8> {
+        self.cpu
+            .borrow()
+            .cartridge()
+            .map_or_else(Vec::new, |c| c.backup().to_vec())
+    }
+
+    // Imports the raw contents of the cartridge's battery-backed memory, e.g. from a .sav file
+    pub fn import_backup(&mut self, data: &[u8]) {
+        if let Some(cartridge) = self.cpu.borrow_mut().cartridge_mut() {
+            cartridge.load_backup(data);
+        }
+    }
+
+    // Returns whether the battery-backed memory has changed since the last call, meaning it should
+    // be persisted again
+    pub fn take_backup_dirty(&mut self) -> bool {
+        self.cpu
+            .borrow_mut()
+            .cartridge_mut()
+            .is_some_and(|c| c.take_backup_dirty())
     }
 
     pub fn update_key_state(&mut self, state: u16) {
         self.key_controller.borrow_mut().set_state(state);
+        if self.key_controller.borrow().irq_requested() {
+            self.interrupt_controller
+                .borrow_mut()
+                .request(interrupt_controller::IRQ_KEYPAD);
+            self.check_wake();
+        }
+    }
+
+    // Serializes the complete machine state. The BIOS and cartridge ROM aren't included, so the
+    // state can only be loaded while running the same ROM.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.write_u32(SAVE_STATE_MAGIC);
+        state.write_u32(SAVE_STATE_VERSION);
+        state.write_u32(self.rom_checksum());
+        self.save_components(&mut state);
+        state.into_bytes()
+    }
+
+    // Restores a state produced by `save_state`. If the state is rejected, the machine is left
+    // as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data);
+        if state.read_u32().map_err(|_| StateError::BadMagic)? != SAVE_STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = state.read_u32()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if state.read_u32()? != self.rom_checksum() {
+            return Err(StateError::RomMismatch);
+        }
+
+        let mut backup = StateWriter::new();
+        self.save_components(&mut backup);
+        let result = self.load_components(&mut state).and_then(|_| {
+            if state.is_empty() {
+                Ok(())
+            } else {
+                Err(StateError::Corrupt)
+            }
+        });
+        if result.is_err() {
+            let backup = backup.into_bytes();
+            self.load_components(&mut StateReader::new(&backup))
+                .expect("failed to restore state after rejecting a save state");
+        }
+        result
+    }
+
+    // Identifies the inserted ROM in save states
+    fn rom_checksum(&self) -> u32 {
+        self.cpu.borrow().cartridge().map_or(0, |c| c.checksum())
+    }
+
+    fn save_components(&self, state: &mut StateWriter) {
+        self.cpu.borrow().save_state(state);
+        self.vram.borrow().save_state(state);
+        self.palette_ram.borrow().save_state(state);
+        self.oam.borrow().save_state(state);
+        self.ppu.borrow().save_state(state);
+        self.sound_controller.borrow().save_state(state);
+        self.key_controller.borrow().save_state(state);
+        self.dma_controller.borrow().save_state(state);
+        self.timer_controller.borrow().save_state(state);
+        self.interrupt_controller.borrow().save_state(state);
+        self.scheduler.borrow().save_state(state);
+    }
+
+    fn load_components(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cpu.borrow_mut().load_state(state)?;
+        self.vram.borrow_mut().load_state(state)?;
+        self.palette_ram.borrow_mut().load_state(state)?;
+        self.oam.borrow_mut().load_state(state)?;
+        self.ppu.borrow_mut().load_state(state)?;
+        self.sound_controller.borrow_mut().load_state(state)?;
+        self.key_controller.borrow_mut().load_state(state)?;
+        self.dma_controller.borrow_mut().load_state(state)?;
+        self.timer_controller.borrow_mut().load_state(state)?;
+        self.interrupt_controller.borrow_mut().load_state(state)?;
+        self.scheduler.borrow_mut().load_state(state)
     }
 }
 
+// The points in a step where the debugger can stop
+enum StepPoint {
+    // Before a transfer on the given DMA channel
+    Dma(usize),
+    // After the CPU takes an interrupt, before the handler's first instruction
+    Irq,
+    // Before the instruction at the given address
+    Instruction(u32),
+}
+
 struct MemoryMap {
     vram: Rc<RefCell<RAM<0x18000>>>,      // VRAM
     palette_ram: Rc<RefCell<RAM<0x400>>>, // Palette RAM
@@ -168,9 +647,48 @@ struct MemoryMap {
     dma_controller: Rc<RefCell<DmaController>>,
     timer_controller: Rc<RefCell<TimerController>>,
     interrupt_controller: Rc<RefCell<InterruptController>>,
+
+    scheduler: Rc<RefCell<Scheduler>>,
+    // Records IO accesses while they're being observed
+    io_recorder: Option<IoRecorder>,
+}
+
+impl MemoryMap {
+    // Whether an IO register is emulated. Writes to the rest are ignored, and reads of them
+    // return 0.
+    fn implements(addr: usize) -> bool {
+        matches!(
+            addr,
+            0x04000000..=0x04000057
+                | 0x04000060..=0x040000A8
+                | 0x040000B0..=0x040000E1
+                | 0x04000100..=0x04000111
+                | 0x04000130..=0x04000133
+                | 0x04000200..=0x0400020B
+        )
+    }
+
+    fn record_io(&mut self, addr: usize, write: bool, data: u8) {
+        if let Some(recorder) = &mut self.io_recorder {
+            if let 0x04000000..=0x04FFFFFF = addr {
+                recorder.record(addr as u32, write, data, Self::implements(addr));
+            }
+        }
+    }
 }
 
 impl Memory for MemoryMap {
+    fn read(&mut self, addr: usize) -> u8 {
+        if let 0x04000060..=0x040000A8 = addr {
+            self.sound_controller
+                .borrow_mut()
+                .sync(self.scheduler.borrow().now());
+        }
+        let data = self.peek(addr);
+        self.record_io(addr, false, data);
+        data
+    }
+
     fn peek(&self, addr: usize) -> u8 {
         match addr {
             0x05000000..=0x05FFFFFF => self.palette_ram.borrow().peek((addr - 0x05000000) % 0x400),
@@ -187,11 +705,23 @@ impl Memory for MemoryMap {
             0x04000100..=0x04000111 => self.timer_controller.borrow().peek(addr - 0x04000000),
             0x04000130..=0x04000133 => self.key_controller.borrow().peek(addr - 0x04000000),
             0x04000200..=0x0400020B => self.interrupt_controller.borrow().peek(addr - 0x04000000),
+            // Registers that exist but aren't emulated
             _ => 0,
         }
     }
 
+    fn is_mapped(&self, addr: usize) -> bool {
+        match addr {
+            0x05000000..=0x07FFFFFF => true,
+            0x04000000..=0x04FFFFFF => {
+                Self::implements(addr) || io_register_name(addr as u32).is_some()
+            }
+            _ => false,
+        }
+    }
+
     fn write(&mut self, addr: usize, data: u8) {
+        self.record_io(addr, true, data);
         match addr {
             0x05000000..=0x05FFFFFF => self
                 .palette_ram
@@ -208,10 +738,11 @@ impl Memory for MemoryMap {
 
             // IO map
             0x04000000..=0x04000057 => self.ppu.borrow_mut().write(addr - 0x04000000, data),
-            0x04000060..=0x040000A8 => self
-                .sound_controller
-                .borrow_mut()
-                .write(addr - 0x04000000, data),
+            0x04000060..=0x040000A8 => {
+                let mut sound_controller = self.sound_controller.borrow_mut();
+                sound_controller.sync(self.scheduler.borrow().now());
+                sound_controller.write(addr - 0x04000000, data)
+            }
             0x040000B0..=0x040000E1 => self
                 .dma_controller
                 .borrow_mut()
@@ -220,10 +751,17 @@ impl Memory for MemoryMap {
                 .timer_controller
                 .borrow_mut()
                 .write(addr - 0x04000000, data),
-            0x04000130..=0x04000133 => self
-                .key_controller
-                .borrow_mut()
-                .write(addr - 0x04000000, data),
+            0x04000130..=0x04000133 => {
+                let mut key_controller = self.key_controller.borrow_mut();
+                key_controller.write(addr - 0x04000000, data);
+                // Keys already held when KEYCNT is written can meet its condition straight away.
+                // The CPU is woken at the start of the next step.
+                if key_controller.irq_requested() {
+                    self.interrupt_controller
+                        .borrow_mut()
+                        .request(interrupt_controller::IRQ_KEYPAD);
+                }
+            }
             0x04000200..=0x0400020B => self
                 .interrupt_controller
                 .borrow_mut()
@@ -1,9 +1,18 @@
-use gba::GBA;
+mod repl;
+
+use gba::{
+    Cartridge, Cheat, CheatFormat, GdbStub, IoAccess, IoObserver, OperatingMode, TraceFilter,
+    Tracer, GBA,
+};
 use sound::AudioRingBuffer;
 
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::{env, fs::File, io::Read, time};
+use std::{env, fs, time};
 
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
 use sdl2::{event::Event, keyboard::Scancode, pixels::PixelFormatEnum};
@@ -34,10 +43,286 @@ impl AudioCallback for AudioBufferWrapper {
     }
 }
 
+fn write_backup(gba: &GBA, save_path: &Path) {
+    if let Err(e) = fs::write(save_path, gba.export_backup()) {
+        println!("error writing save file: {}", e);
+    }
+}
+
+// Builds a tracer from the trace options, if any are given
+fn parse_tracer(args: &[String]) -> Result<Option<Tracer>, String> {
+    let mut filter = TraceFilter::default();
+    let mut output: Option<Box<dyn Write>> = None;
+    let mut recent_len = 0;
+    let mut disassemble = true;
+    let mut enabled = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--trace") {
+            continue;
+        }
+        enabled = true;
+        if arg == "--trace-no-disasm" {
+            disassemble = false;
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", arg))?;
+        match arg.as_str() {
+            "--trace" if value == "-" => output = Some(Box::new(io::stdout())),
+            "--trace" => {
+                let file = fs::File::create(value)
+                    .map_err(|e| format!("error creating trace file: {}", e))?;
+                output = Some(Box::new(io::BufWriter::new(file)));
+            }
+            "--trace-recent" => {
+                recent_len = value
+                    .parse()
+                    .map_err(|_| format!("invalid instruction count: {}", value))?;
+            }
+            "--trace-range" => {
+                let parse =
+                    |addr: &str| u32::from_str_radix(addr.trim_start_matches("0x"), 16).ok();
+                let range = value
+                    .split_once('-')
+                    .and_then(|(start, end)| Some(parse(start)?..=parse(end)?))
+                    .ok_or_else(|| format!("invalid address range: {}", value))?;
+                filter.ranges.push(range);
+            }
+            "--trace-mode" => {
+                let mode = match value.as_str() {
+                    "usr" => OperatingMode::User,
+                    "fiq" => OperatingMode::FastInterrupt,
+                    "irq" => OperatingMode::Interrupt,
+                    "svc" => OperatingMode::Supervisor,
+                    "abt" => OperatingMode::Abort,
+                    "und" => OperatingMode::Undefined,
+                    "sys" => OperatingMode::System,
+                    _ => return Err(format!("invalid mode: {}", value)),
+                };
+                filter.modes.push(mode);
+            }
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+
+    if !enabled {
+        return Ok(None);
+    }
+    let mut tracer = Tracer::new(filter);
+    if let Some(output) = output {
+        tracer.set_output(output);
+    }
+    tracer.set_recent_len(recent_len);
+    tracer.set_disassemble(disassemble);
+    Ok(Some(tracer))
+}
+
+// Builds an observer of IO accesses from the IO options, if any are given, which logs them or
+// warns about writes to registers that aren't emulated
+fn parse_io_observer(args: &[String]) -> Result<Option<IoObserver>, String> {
+    let mut output: Option<Box<dyn Write>> = None;
+    let warn = args.iter().any(|arg| arg == "--io-warn");
+    if let Some(i) = args.iter().position(|arg| arg == "--io-log") {
+        output = match args.get(i + 1).map(String::as_str) {
+            Some("-") => Some(Box::new(io::stdout())),
+            Some(path) => {
+                let file = fs::File::create(path)
+                    .map_err(|e| format!("error creating IO log file: {}", e))?;
+                Some(Box::new(io::BufWriter::new(file)))
+            }
+            None => return Err("missing value for --io-log".to_string()),
+        };
+    }
+    if output.is_none() && !warn {
+        return Ok(None);
+    }
+
+    // Each unimplemented register is only warned about the first time it's written
+    let mut warned = BTreeSet::new();
+    Ok(Some(Box::new(move |access: &IoAccess| {
+        if let Some(out) = &mut output {
+            // A log that can't be written isn't worth stopping emulation for
+            if writeln!(out, "{}", access).is_err() {
+                output = None;
+            }
+        }
+        if warn && access.write && !access.implemented && warned.insert(access.addr) {
+            println!(
+                "warning: write to unimplemented IO register {} ({:08X})",
+                access.register().unwrap_or("?"),
+                access.addr
+            );
+        }
+    })))
+}
+
+// Reads the cheats given with `--cheat <format>:<file>`, where each file holds one cheat's codes
+fn parse_cheats(args: &[String]) -> Result<Vec<Cheat>, String> {
+    let mut cheats = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != "--cheat" {
+            continue;
+        }
+        let value = args.next().ok_or("missing value for --cheat")?;
+        let (format, path) = value
+            .split_once(':')
+            .ok_or_else(|| format!("invalid cheat: {}", value))?;
+        let format = match format {
+            "gs" => CheatFormat::GameShark,
+            "ar" | "gs3" => CheatFormat::ActionReplayMax,
+            "cb" => CheatFormat::CodeBreaker,
+            _ => return Err(format!("invalid cheat format: {}", format)),
+        };
+        let codes =
+            fs::read_to_string(path).map_err(|e| format!("error reading cheat file: {}", e))?;
+        let cheat = Cheat::parse(format, &codes).map_err(|e| format!("{}: {}", path, e))?;
+        cheats.push(cheat);
+    }
+    Ok(cheats)
+}
+
+const DEFAULT_GDB_PORT: u16 = 2345;
+
+// Waits for a GDB client to connect on localhost, then serves it until it leaves
+fn serve_gdb(gba: &mut GBA, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("waiting for GDB on 127.0.0.1:{}", port);
+    let (stream, addr) = listener.accept()?;
+    println!("GDB connected from {}", addr);
+    GdbStub::new(stream).run(gba)
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // `debug` runs the game headless under the interactive debugger, and `gdb` runs it headless
+    // under a GDB client instead
+    let subcommand = match args.get(1).map(String::as_str) {
+        Some("debug") | Some("gdb") => Some(args.remove(1)),
+        _ => None,
+    };
     if args.len() < 2 {
-        println!("usage: {} <GBA file>", args.get(0).unwrap(),);
+        println!(
+            "usage: {0} [debug] <GBA file> [--skip-bios] [options]\n       \
+             {0} gdb <GBA file> [--skip-bios] [--port <port>] [options]\n\n\
+             cheat options:\n  \
+             --cheat <format>:<file>  apply the codes in a file, where the format is gs (GameShark\n                           \
+             v1/v2), ar (Action Replay MAX or GameShark v3) or cb (CodeBreaker)\n\n\
+             trace options:\n  \
+             --trace <file>         write each instruction executed to a file, or - for stdout\n  \
+             --trace-recent <n>     keep the last n instructions, shown on a panic or breakpoint\n  \
+             --trace-range <a>-<b>  only trace the (hex) addresses from a to b\n  \
+             --trace-mode <mode>    only trace in a mode: usr, fiq, irq, svc, abt, und or sys\n  \
+             --trace-no-disasm      leave out the disassembly\n\n\
+             IO options:\n  \
+             --io-log <file>        write each IO register access to a file, or - for stdout\n  \
+             --io-warn              warn about writes to IO registers that aren't emulated",
+            args.get(0).unwrap(),
+        );
+        return;
+    }
+
+    let cart = match fs::read(&args[1])
+        .map_err(|e| e.to_string())
+        .and_then(|rom| Cartridge::new(rom).map_err(|e| e.to_string()))
+    {
+        Ok(cart) => cart,
+        Err(e) => {
+            println!("error loading cartridge: {}", e);
+            return;
+        }
+    };
+
+    let header = cart.header();
+    println!("{} ({})", header.title, header.game_code);
+    if !header.complement_check_valid {
+        println!("warning: cartridge header checksum is invalid");
+    }
+    let peripherals = cart.peripherals().names();
+    if !peripherals.is_empty() {
+        println!(
+            "warning: cartridge has hardware that isn't emulated: {}",
+            peripherals.join(", ")
+        );
+    }
+
+    let mut gba = GBA::new();
+    // Without a BIOS image, the built-in high-level emulation of the BIOS is used
+    match fs::read("gba_bios.bin") {
+        Ok(bios) => gba.flash_bios(bios),
+        Err(_) => println!("gba_bios.bin not found, using HLE BIOS"),
+    }
+    gba.insert_cartridge(cart);
+    if args[2..].iter().any(|arg| arg == "--skip-bios") {
+        gba.skip_bios();
+    }
+
+    match parse_tracer(&args[2..]) {
+        Ok(Some(tracer)) => {
+            // Idle loops are run in full, so that traces line up with other emulators'
+            gba.set_idle_loop_detection(false);
+            gba.set_tracer(Some(tracer));
+        }
+        Ok(None) => {}
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    match parse_io_observer(&args[2..]) {
+        Ok(observer) => gba.set_io_observer(observer),
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    match parse_cheats(&args[2..]) {
+        Ok(cheats) => {
+            for cheat in cheats {
+                gba.add_cheat(cheat);
+            }
+        }
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    // Battery-backed saves are kept in a .sav file next to the ROM
+    let save_path = Path::new(&args[1]).with_extension("sav");
+    if let Ok(save) = fs::read(&save_path) {
+        gba.import_backup(&save);
+    }
+
+    match subcommand.as_deref() {
+        Some("debug") => repl::run(&mut gba),
+        Some("gdb") => {
+            let port = match args.iter().position(|arg| arg == "--port") {
+                Some(i) => match args.get(i + 1).and_then(|port| port.parse().ok()) {
+                    Some(port) => port,
+                    None => {
+                        println!("invalid port");
+                        return;
+                    }
+                },
+                None => DEFAULT_GDB_PORT,
+            };
+            if let Err(e) = serve_gdb(&mut gba, port) {
+                println!("GDB connection error: {}", e);
+            }
+        }
+        _ => {}
+    }
+    if subcommand.is_some() {
+        if gba.take_backup_dirty() {
+            write_backup(&gba, &save_path);
+        }
         return;
     }
 
@@ -59,24 +344,6 @@ fn main() {
         .create_texture_target(PixelFormatEnum::BGR555, 240, 160)
         .unwrap();
 
-    let mut bios = vec![0; 0x4000];
-    let mut bios_file = File::open(r"gba_bios.bin").unwrap();
-    bios_file.read(&mut bios).expect("buffer overflow");
-
-    let mut cart = vec![0; 0x800000 * 0xC];
-    let mut cart_file = match File::open(args[1].clone()) {
-        Ok(file) => file,
-        Err(e) => {
-            println!("error opening file: {}", e);
-            return;
-        }
-    };
-    cart_file.read(&mut cart).expect("buffer overflow");
-
-    let mut gba = GBA::new();
-    gba.flash_bios(bios);
-    gba.flash_cart(cart);
-
     let audio_subsystem = sdl_context.audio().unwrap();
 
     let desired_spec = AudioSpecDesired {
@@ -111,50 +378,59 @@ fn main() {
     let mut now = time::Instant::now();
     let mut frame_count = 0;
     let frames_per_rate_check = 60;
+    let frames_per_backup_flush = 60;
     let checks_per_rate_report = 2;
     let get_fps = |micros| (1f32 / ((micros / frames_per_rate_check) as f32 * 0.000001)) as u32;
 
     let mut fps_timer = time::Instant::now();
     loop {
-        gba.tick();
-
-        if let Some(framebuffer) = gba.try_get_framebuffer() {
-            texture.update(None, &framebuffer, 240 * 2).unwrap();
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
-
-            if (frame_count + 1) % frames_per_rate_check == 0 {
-                if (frame_count + 1) % (frames_per_rate_check * checks_per_rate_report) == 0 {
-                    canvas
-                        .window_mut()
-                        .set_title(
-                            &format!("Mineral | {} fps", get_fps(now.elapsed().as_micros()))[..],
-                        )
-                        .unwrap();
-                }
-                now = time::Instant::now();
-            }
-            frame_count += 1;
+        let frame = gba.run_frame();
+        texture.update(None, &frame.framebuffer, 240 * 2).unwrap();
+        canvas.copy(&texture, None, None).unwrap();
+        canvas.present();
 
-            let elapsed = fps_timer.elapsed();
-            if elapsed < time::Duration::from_millis(16) {
-                thread::sleep(time::Duration::from_millis(16) - elapsed);
+        if (frame_count + 1) % frames_per_rate_check == 0 {
+            if (frame_count + 1) % (frames_per_rate_check * checks_per_rate_report) == 0 {
+                canvas
+                    .window_mut()
+                    .set_title(&format!("Mineral | {} fps", get_fps(now.elapsed().as_micros()))[..])
+                    .unwrap();
             }
-            fps_timer = time::Instant::now();
+            now = time::Instant::now();
+        }
+        frame_count += 1;
 
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. } => std::process::exit(0),
-                    _ => {}
+        if frame_count % frames_per_backup_flush == 0 && gba.take_backup_dirty() {
+            write_backup(&gba, &save_path);
+        }
+
+        let elapsed = fps_timer.elapsed();
+        if elapsed < time::Duration::from_millis(16) {
+            thread::sleep(time::Duration::from_millis(16) - elapsed);
+        }
+        fps_timer = time::Instant::now();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } => {
+                    if gba.take_backup_dirty() {
+                        write_backup(&gba, &save_path);
+                    }
+                    // Exiting skips destructors, so the tracer and IO log are dropped first to
+                    // flush them
+                    gba.set_tracer(None);
+                    gba.set_io_observer(None);
+                    std::process::exit(0)
                 }
+                _ => {}
             }
+        }
 
-            let kb_state = event_pump.keyboard_state();
-            let controller_data = controls.iter().fold(0, |acc, control| {
-                (acc << 1) | (!kb_state.is_scancode_pressed(*control)) as u16
-            });
+        let kb_state = event_pump.keyboard_state();
+        let controller_data = controls.iter().fold(0, |acc, control| {
+            (acc << 1) | (!kb_state.is_scancode_pressed(*control)) as u16
+        });
 
-            gba.update_key_state(controller_data);
-        }
+        gba.update_key_state(controller_data);
     }
 }
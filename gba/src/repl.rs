@@ -0,0 +1,295 @@
+use gba::{BreakCondition, Operand, StopReason, WatchKind, GBA};
+
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+  s, step [n]               execute n instructions (default 1)
+  n, next                   execute one instruction, stepping over calls
+  c, continue               run until a breakpoint or event
+  u, until <addr>           run until execution reaches an address
+  b, break <addr> [if <cond>]
+                            add a breakpoint, e.g. `b 0x08000120 if r0 == 0x10`
+  w, watch <addr> [len] [r|w|rw]
+                            add a watchpoint (default: 1 byte, writes)
+  d, delete <id>            delete a breakpoint or watchpoint
+  info                      list breakpoints and watchpoints
+  irq on|off                stop when an IRQ is taken
+  dma on|off                stop when a DMA transfer starts
+  r, regs                   show the registers
+  x <addr> [n]              show n bytes of memory (default 64)
+  dis [addr] [n]            disassemble n instructions (default: pc, 8)
+  t, trace                  show the most recent instructions (see --trace-recent)
+  q, quit                   exit
+Addresses and values can be numbers (hex with 0x), registers (r0-r15, sp, lr, pc,
+cpsr), or memory (u8[addr], u16[addr], [addr] for a word). An empty line repeats
+the last command.";
+
+// The cycles to run for with each call to `debug_run`, about a frame
+const RUN_CHUNK: u64 = 280896;
+
+// Runs an interactive debugger on stdin and stdout until it's quit
+pub fn run(gba: &mut GBA) {
+    println!("type `help` for a list of commands");
+    print_location(gba);
+
+    let stdin = io::stdin();
+    let mut last_line = String::new();
+    loop {
+        print!("(mineral) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let line = match line.trim() {
+            "" => last_line.clone(),
+            line => line.to_string(),
+        };
+        last_line = line.clone();
+
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => continue,
+        };
+        let args: Vec<&str> = args.collect();
+        match command {
+            "help" | "h" => println!("{}", HELP),
+            "s" | "step" => match args.first().map(|n| n.parse()) {
+                None => resume(gba, |gba| gba.debug_step(1)),
+                Some(Ok(n)) if n > 0 => resume(gba, |gba| gba.debug_step(n)),
+                Some(_) => println!("invalid instruction count"),
+            },
+            "n" | "next" => resume(gba, GBA::debug_step_over),
+            "c" | "continue" => resume(gba, GBA::debug_continue),
+            "u" | "until" => match args.first().and_then(|addr| value(gba, addr)) {
+                Some(addr) => resume(gba, |gba| gba.debug_run_to(addr)),
+                None => println!("usage: until <addr>"),
+            },
+            "b" | "break" => add_breakpoint(gba, &line, &args),
+            "w" | "watch" => add_watchpoint(gba, &args),
+            "d" | "delete" => match args.first().and_then(|id| id.parse().ok()) {
+                Some(id) if gba.delete_breakpoint(id) => println!("deleted {}", id),
+                Some(id) => println!("no breakpoint or watchpoint {}", id),
+                None => println!("usage: delete <id>"),
+            },
+            "info" => print_breakpoints(gba),
+            "irq" | "dma" => {
+                let enabled = match args.first() {
+                    Some(&"on") => true,
+                    Some(&"off") => false,
+                    _ => {
+                        println!("usage: {} on|off", command);
+                        continue;
+                    }
+                };
+                if command == "irq" {
+                    gba.set_break_on_irq(enabled);
+                } else {
+                    gba.set_break_on_dma(enabled);
+                }
+            }
+            "r" | "regs" => print_registers(gba),
+            "x" => {
+                let addr = args.first().and_then(|addr| value(gba, addr));
+                let len = args.get(1).map_or(Some(64), |len| value(gba, len));
+                match (addr, len) {
+                    (Some(addr), Some(len)) => print_memory(gba, addr, len),
+                    _ => println!("usage: x <addr> [n]"),
+                }
+            }
+            "dis" => {
+                let addr = args
+                    .first()
+                    .map_or(Some(gba.register(15)), |addr| value(gba, addr));
+                let n = args.get(1).map_or(Some(8), |n| value(gba, n));
+                match (addr, n) {
+                    (Some(addr), Some(n)) => print_disassembly(gba, addr, n),
+                    _ => println!("usage: dis [addr] [n]"),
+                }
+            }
+            "t" | "trace" => print_trace(gba),
+            "q" | "quit" => break,
+            _ => println!("unknown command `{}`, type `help` for a list", command),
+        }
+    }
+}
+
+fn value(gba: &GBA, s: &str) -> Option<u32> {
+    Operand::parse(s).map(|operand| operand.value(gba))
+}
+
+fn is_thumb(gba: &GBA) -> bool {
+    gba.cpsr() & (1 << 5) != 0
+}
+
+// Sets up the run, then runs until it stops
+fn resume<F: FnOnce(&mut GBA)>(gba: &mut GBA, setup: F) {
+    setup(gba);
+    let reason = loop {
+        if let Some(reason) = gba.debug_run(RUN_CHUNK) {
+            break reason;
+        }
+    };
+    match reason {
+        StopReason::Done => {}
+        StopReason::Breakpoint(id) => {
+            println!("breakpoint {}", id);
+            print_trace(gba);
+        }
+        StopReason::Watchpoint(id, hit) => {
+            let access = if hit.write { "write" } else { "read" };
+            println!(
+                "watchpoint {}: {} of {:#04X} at {:08X}",
+                id, access, hit.value, hit.addr
+            );
+        }
+        StopReason::Irq => println!("IRQ taken (IE & IF = {:04X})", irq_pending(gba)),
+        StopReason::DmaStart(channel) => println!("DMA {} starting", channel),
+        StopReason::Stopped => println!("the CPU is in stop mode"),
+    }
+    print_location(gba);
+}
+
+fn irq_pending(gba: &GBA) -> u16 {
+    let regs = gba.peek_memory(0x04000200, 4);
+    (regs[0] as u16 | (regs[1] as u16) << 8) & (regs[2] as u16 | (regs[3] as u16) << 8)
+}
+
+// Prints the most recent instructions, if they're being traced
+fn print_trace(gba: &GBA) {
+    gba.dump_trace(&mut io::stdout()).unwrap();
+}
+
+fn print_location(gba: &GBA) {
+    print_disassembly(gba, gba.register(15), 1);
+}
+
+fn print_disassembly(gba: &GBA, addr: u32, n: u32) {
+    let thumb = is_thumb(gba);
+    let width = if thumb { 2 } else { 4 };
+    let mut addr = addr & !(width - 1);
+    for _ in 0..n {
+        let (encoding, text) = gba.disassemble(addr, thumb);
+        if thumb {
+            println!("{:08X}: {:04X}      {}", addr, encoding, text);
+        } else {
+            println!("{:08X}: {:08X}  {}", addr, encoding, text);
+        }
+        addr = addr.wrapping_add(width);
+    }
+}
+
+fn print_registers(gba: &GBA) {
+    for row in 0..4 {
+        let line: Vec<String> = (0..4)
+            .map(|col| row * 4 + col)
+            .map(|n| format!("{:>3}: {:08X}", format!("r{}", n), gba.register(n)))
+            .collect();
+        println!("{}", line.join("  "));
+    }
+
+    let cpsr = gba.cpsr();
+    let flags: String = [
+        (31, 'N'),
+        (30, 'Z'),
+        (29, 'C'),
+        (28, 'V'),
+        (7, 'I'),
+        (6, 'F'),
+        (5, 'T'),
+    ]
+    .iter()
+    .map(|&(bit, flag)| if cpsr & (1 << bit) != 0 { flag } else { '-' })
+    .collect();
+    println!("cpsr: {:08X} [{}] mode {:02X}", cpsr, flags, cpsr & 0x1F);
+}
+
+fn print_memory(gba: &GBA, addr: u32, len: u32) {
+    let data = gba.peek_memory(addr, len as usize);
+    for (i, row) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let ascii: String = row
+            .iter()
+            .map(|&byte| match byte {
+                0x20..=0x7E => byte as char,
+                _ => '.',
+            })
+            .collect();
+        println!(
+            "{:08X}: {:<47}  {}",
+            addr.wrapping_add(i as u32 * 16),
+            bytes.join(" "),
+            ascii
+        );
+    }
+}
+
+fn print_breakpoints(gba: &GBA) {
+    for (id, breakpoint) in gba.breakpoints() {
+        match &breakpoint.condition {
+            Some(_) => println!("{}: break at {:08X} (conditional)", id, breakpoint.addr),
+            None => println!("{}: break at {:08X}", id, breakpoint.addr),
+        }
+    }
+    for (id, watchpoint) in gba.watchpoints() {
+        let kind = match watchpoint.kind {
+            WatchKind::Read => "reads",
+            WatchKind::Write => "writes",
+            WatchKind::ReadWrite => "accesses",
+        };
+        println!(
+            "{}: watch {} of {:08X}..{:08X}",
+            id,
+            kind,
+            watchpoint.addr,
+            watchpoint.addr.wrapping_add(watchpoint.len)
+        );
+    }
+}
+
+fn add_breakpoint(gba: &mut GBA, line: &str, args: &[&str]) {
+    let addr = match args.first().and_then(|addr| value(gba, addr)) {
+        Some(addr) => addr,
+        None => {
+            println!("usage: break <addr> [if <cond>]");
+            return;
+        }
+    };
+    // The condition is everything after `if`, spaces included
+    let condition = match line.find(" if ") {
+        Some(i) => match BreakCondition::parse(&line[i + 4..]) {
+            Some(condition) => Some(condition),
+            None => {
+                println!("invalid condition");
+                return;
+            }
+        },
+        None => None,
+    };
+    let id = gba.add_breakpoint(addr, condition);
+    println!("breakpoint {} at {:08X}", id, addr);
+}
+
+fn add_watchpoint(gba: &mut GBA, args: &[&str]) {
+    let addr = args.first().and_then(|addr| value(gba, addr));
+    let mut len = Some(1);
+    let mut kind = WatchKind::Write;
+    for arg in args.iter().skip(1) {
+        match *arg {
+            "r" => kind = WatchKind::Read,
+            "w" => kind = WatchKind::Write,
+            "rw" => kind = WatchKind::ReadWrite,
+            arg => len = value(gba, arg).filter(|&len| len > 0),
+        }
+    }
+    match (addr, len) {
+        (Some(addr), Some(len)) => {
+            let id = gba.add_watchpoint(addr, len, kind);
+            println!("watchpoint {} at {:08X}", id, addr);
+        }
+        _ => println!("usage: watch <addr> [len] [r|w|rw]"),
+    }
+}
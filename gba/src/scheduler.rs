@@ -0,0 +1,101 @@
+use memory::{SaveState, StateError, StateReader, StateWriter};
+
+// Components that run independently of the CPU schedule events at the cycle timestamps when they
+// next need attention, and the CPU runs uninterrupted until the next event is due
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Event {
+    Ppu,
+    SoundSample,
+    TimerOverflow(usize),
+}
+
+impl Event {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Ppu => 0,
+            Self::SoundSample => 1,
+            Self::TimerOverflow(i) => 2 + i as u8,
+        }
+    }
+
+    fn from_u8(val: u8) -> Option<Self> {
+        match val {
+            0 => Some(Self::Ppu),
+            1 => Some(Self::SoundSample),
+            2..=5 => Some(Self::TimerOverflow((val - 2) as usize)),
+            _ => None,
+        }
+    }
+}
+
+pub struct Scheduler {
+    now: u64,
+    // Pending events, sorted by timestamp. Each event is scheduled at most once.
+    events: Vec<(u64, Event)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            now: 0,
+            events: Vec::new(),
+        }
+    }
+
+    // The number of cycles elapsed since power-on
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self, cycles: u32) {
+        self.now += cycles as u64;
+    }
+
+    // Schedules an event at an absolute timestamp, replacing any pending instance of that event
+    pub fn schedule(&mut self, event: Event, time: u64) {
+        self.cancel(event);
+        let i = self
+            .events
+            .partition_point(|&(other_time, _)| other_time <= time);
+        self.events.insert(i, (time, event));
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|&(_, other)| other != event);
+    }
+
+    pub fn next_event_time(&self) -> u64 {
+        self.events.first().map_or(u64::MAX, |&(time, _)| time)
+    }
+
+    // Removes and returns the earliest event if it is due
+    pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+        if self.next_event_time() <= self.now {
+            Some(self.events.remove(0))
+        } else {
+            None
+        }
+    }
+}
+
+impl SaveState for Scheduler {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u64(self.now);
+        state.write_u32(self.events.len() as u32);
+        for &(time, event) in &self.events {
+            state.write_u64(time);
+            state.write_u8(event.to_u8());
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.now = state.read_u64()?;
+        self.events.clear();
+        for _ in 0..state.read_u32()? {
+            let time = state.read_u64()?;
+            let event = Event::from_u8(state.read_u8()?).ok_or(StateError::Corrupt)?;
+            self.schedule(event, time);
+        }
+        Ok(())
+    }
+}
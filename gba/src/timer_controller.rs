@@ -1,6 +1,7 @@
 use crate::interrupt_controller::{self, InterruptController};
+use crate::scheduler::{Event, Scheduler};
 
-use memory::Memory;
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
 use sound::{DmaSoundTimer, SoundController};
 
 use std::cell::RefCell;
@@ -8,12 +9,15 @@ use std::rc::Rc;
 
 pub struct TimerController {
     control_regs: [TimerControlReg; 4],
+    // Running timers aren't ticked every cycle. Instead, each counter value is stored along with
+    // the timestamp at which the counter had that value, and overflows are scheduled as events.
     counters: [u16; 4],
-    prescaler_counters: [u16; 4],
+    counter_timestamps: [u64; 4],
+    scheduler: Rc<RefCell<Scheduler>>,
 }
 
 impl TimerController {
-    pub fn new() -> Self {
+    pub fn new(scheduler: Rc<RefCell<Scheduler>>) -> Self {
         Self {
             control_regs: [
                 TimerControlReg(0),
@@ -22,112 +26,151 @@ impl TimerController {
                 TimerControlReg(0),
             ],
             counters: [0; 4],
-            prescaler_counters: [0; 4],
+            counter_timestamps: [0; 4],
+            scheduler,
         }
     }
 
-    pub fn tick(
+    // Handles a scheduled overflow of a timer, returning true if a sound DMA is to be requested
+    pub fn on_overflow(
         &mut self,
-        interrupt_controller: Rc<RefCell<InterruptController>>,
-        sound_controller: Rc<RefCell<SoundController>>,
-    ) {
-        for i in 0..4 {
-            if i == 0 || !self.control_regs[i].count_up() {
-                self.tick_timer(i, &interrupt_controller, &sound_controller);
-            }
-        }
+        i: usize,
+        time: u64,
+        interrupt_controller: &Rc<RefCell<InterruptController>>,
+        sound_controller: &Rc<RefCell<SoundController>>,
+    ) -> bool {
+        let request_dma = self.overflow(i, time, interrupt_controller, sound_controller);
+        self.schedule_overflow(i);
+        request_dma
     }
 
-    fn tick_timer(
+    fn overflow(
         &mut self,
         i: usize,
+        time: u64,
         interrupt_controller: &Rc<RefCell<InterruptController>>,
         sound_controller: &Rc<RefCell<SoundController>>,
-    ) {
-        if self.control_regs[i].enable() {
-            if i != 0 && self.control_regs[i].count_up() {
-                self.counters[i] = self.counters[i].wrapping_add(1);
-            } else {
-                self.prescaler_counters[i] = self.prescaler_counters[i].wrapping_sub(1);
-                if self.prescaler_counters[i] == 0 {
-                    self.reload_prescaler(i);
-                    self.counters[i] = self.counters[i].wrapping_add(1);
-                }
+    ) -> bool {
+        self.reload_counter(i);
+        self.counter_timestamps[i] = time;
+
+        if self.control_regs[i].irq() {
+            let mut irq = interrupt_controller.borrow_mut();
+            match i {
+                0 => irq.request(interrupt_controller::IRQ_TIMER0),
+                1 => irq.request(interrupt_controller::IRQ_TIMER1),
+                2 => irq.request(interrupt_controller::IRQ_TIMER2),
+                _ => irq.request(interrupt_controller::IRQ_TIMER3),
             }
+        }
+
+        let mut request_dma = match i {
+            0 => sound_controller
+                .borrow_mut()
+                .on_timer_overflow(DmaSoundTimer::Timer0),
+            1 => sound_controller
+                .borrow_mut()
+                .on_timer_overflow(DmaSoundTimer::Timer1),
+            _ => false,
+        };
 
-            if self.counters[i] == 0 {
-                self.reload_counter(i);
-
-                if self.control_regs[i].irq() {
-                    let mut irq = interrupt_controller.borrow_mut();
-                    match i {
-                        0 => irq.request(interrupt_controller::IRQ_TIMER0),
-                        1 => irq.request(interrupt_controller::IRQ_TIMER1),
-                        2 => irq.request(interrupt_controller::IRQ_TIMER2),
-                        3 | _ => irq.request(interrupt_controller::IRQ_TIMER3),
-                    }
-                }
-
-                match i {
-                    0 => sound_controller
-                        .borrow_mut()
-                        .on_timer_overflow(DmaSoundTimer::Timer0),
-                    1 => sound_controller
-                        .borrow_mut()
-                        .on_timer_overflow(DmaSoundTimer::Timer1),
-                    _ => {}
-                }
-
-                if i < 3 && self.control_regs[i + 1].count_up() {
-                    self.tick_timer(i + 1, interrupt_controller, sound_controller);
-                }
+        // Count-up timers are incremented whenever the previous timer overflows
+        if i < 3 && self.control_regs[i + 1].enable() && self.control_regs[i + 1].count_up() {
+            self.counters[i + 1] = self.counters[i + 1].wrapping_add(1);
+            if self.counters[i + 1] == 0 {
+                request_dma |= self.overflow(i + 1, time, interrupt_controller, sound_controller);
             }
         }
+
+        request_dma
+    }
+
+    // Whether a timer counts by itself, rather than being incremented by the previous timer
+    fn is_running(&self, timer_n: usize) -> bool {
+        self.control_regs[timer_n].enable()
+            && (timer_n == 0 || !self.control_regs[timer_n].count_up())
+    }
+
+    fn counter(&self, timer_n: usize) -> u16 {
+        if self.is_running(timer_n) {
+            let elapsed = self.scheduler.borrow().now() - self.counter_timestamps[timer_n];
+            let increments = elapsed / self.prescaler(timer_n);
+            self.counters[timer_n].wrapping_add(increments as u16)
+        } else {
+            self.counters[timer_n]
+        }
+    }
+
+    // Stores the current counter value, keeping the progress towards the next increment
+    fn sync_counter(&mut self, timer_n: usize) {
+        if self.is_running(timer_n) {
+            let now = self.scheduler.borrow().now();
+            let elapsed = now - self.counter_timestamps[timer_n];
+            self.counters[timer_n] = self.counter(timer_n);
+            self.counter_timestamps[timer_n] = now - elapsed % self.prescaler(timer_n);
+        }
+    }
+
+    fn schedule_overflow(&mut self, timer_n: usize) {
+        let mut scheduler = self.scheduler.borrow_mut();
+        if self.is_running(timer_n) {
+            let increments = 0x10000 - self.counters[timer_n] as u64;
+            let time = self.counter_timestamps[timer_n] + increments * self.prescaler(timer_n);
+            scheduler.schedule(Event::TimerOverflow(timer_n), time);
+        } else {
+            scheduler.cancel(Event::TimerOverflow(timer_n));
+        }
     }
 
     fn set_control_byte_2(&mut self, timer_n: usize, data: u8) {
+        self.sync_counter(timer_n);
+        let was_running = self.is_running(timer_n);
+
         let old_enable = self.control_regs[timer_n].enable();
         self.control_regs[timer_n].set_byte_2(data);
         let new_enable = self.control_regs[timer_n].enable();
 
         if !old_enable && new_enable {
             self.reload_counter(timer_n);
-            self.reload_prescaler(timer_n);
         }
+        if !was_running && self.is_running(timer_n) {
+            self.counter_timestamps[timer_n] = self.scheduler.borrow().now();
+        }
+        self.schedule_overflow(timer_n);
     }
 
     fn reload_counter(&mut self, timer_n: usize) {
         self.counters[timer_n] = self.control_regs[timer_n].reload();
     }
 
-    // The number of ticks before the timer itself should be ticked
-    fn reload_prescaler(&mut self, timer_n: usize) {
-        self.prescaler_counters[timer_n] = match self.control_regs[timer_n].prescaler() {
+    // The number of cycles per increment of the timer
+    fn prescaler(&self, timer_n: usize) -> u64 {
+        match self.control_regs[timer_n].prescaler() {
             0b00 => 1,
             0b01 => 64,
             0b10 => 256,
-            0b11 | _ => 1024,
-        };
+            _ => 1024,
+        }
     }
 }
 
 impl Memory for TimerController {
     fn peek(&self, addr: usize) -> u8 {
         match addr {
-            0x100 => self.counters[0] as u8,
-            0x101 => (self.counters[0] >> 8) as u8,
+            0x100 => self.counter(0) as u8,
+            0x101 => (self.counter(0) >> 8) as u8,
             0x102 => self.control_regs[0].byte_2(),
             0x103 => self.control_regs[0].byte_3(),
-            0x104 => self.counters[1] as u8,
-            0x105 => (self.counters[1] >> 8) as u8,
+            0x104 => self.counter(1) as u8,
+            0x105 => (self.counter(1) >> 8) as u8,
             0x106 => self.control_regs[1].byte_2(),
             0x107 => self.control_regs[1].byte_3(),
-            0x108 => self.counters[2] as u8,
-            0x109 => (self.counters[2] >> 8) as u8,
+            0x108 => self.counter(2) as u8,
+            0x109 => (self.counter(2) >> 8) as u8,
             0x10A => self.control_regs[2].byte_2(),
             0x10B => self.control_regs[2].byte_3(),
-            0x10C => self.counters[3] as u8,
-            0x10D => (self.counters[3] >> 8) as u8,
+            0x10C => self.counter(3) as u8,
+            0x10D => (self.counter(3) >> 8) as u8,
             0x10E => self.control_regs[3].byte_2(),
             0x10F => self.control_regs[3].byte_3(),
             _ => 0,
@@ -173,3 +216,23 @@ bitfield! {
   pub u8, byte_2, set_byte_2: 23, 16;
   pub u8, byte_3, set_byte_3: 31, 24;
 }
+
+// Pending overflows are restored along with the scheduler, so they aren't rescheduled here
+impl SaveState for TimerController {
+    fn save_state(&self, state: &mut StateWriter) {
+        for i in 0..4 {
+            state.write_u32(self.control_regs[i].0);
+            state.write_u16(self.counters[i]);
+            state.write_u64(self.counter_timestamps[i]);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for i in 0..4 {
+            self.control_regs[i].0 = state.read_u32()?;
+            self.counters[i] = state.read_u16()?;
+            self.counter_timestamps[i] = state.read_u64()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::boot;
+use gba::{BackupType, CartridgeError};
+
+#[test]
+fn override_backup_type() {
+    // Before a save is imported, the type can be changed freely
+    let mut gba = boot();
+    assert_eq!(gba.backup_type(), Some(BackupType::Sram));
+    gba.override_backup_type(Some(BackupType::Flash128K))
+        .unwrap();
+    assert_eq!(gba.backup_type(), Some(BackupType::Flash128K));
+    gba.import_backup(&[0x12; 0x20000]);
+
+    // Overriding with the same type keeps the save
+    gba.override_backup_type(Some(BackupType::Flash128K))
+        .unwrap();
+    assert_eq!(gba.export_backup(), vec![0x12; 0x20000]);
+
+    // And a type that can't hold it is refused, leaving the save in place
+    assert_eq!(
+        gba.override_backup_type(Some(BackupType::Eeprom8K)),
+        Err(CartridgeError::BackupInUse(BackupType::Flash128K))
+    );
+    assert_eq!(gba.backup_type(), Some(BackupType::Flash128K));
+    assert_eq!(gba.export_backup(), vec![0x12; 0x20000]);
+}
+
+#[test]
+fn save_states_without_a_save() {
+    // A state saved before the backup was written leaves the type free to change once loaded
+    let mut gba = boot();
+    let state = gba.save_state();
+    gba.load_state(&state).unwrap();
+    gba.override_backup_type(Some(BackupType::Flash128K))
+        .unwrap();
+    assert_eq!(gba.backup_type(), Some(BackupType::Flash128K));
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use common::boot;
+use gba::{Cheat, CheatError, CheatFormat, GBA};
+
+const GAMESHARK_SEEDS: [u32; 4] = [0x09F4FBBD, 0x9681884A, 0x352027E9, 0xF3DEE5A7];
+const ACTION_REPLAY_MAX_SEEDS: [u32; 4] = [0x7AA9648F, 0x7FAE6994, 0xC0EFAAD5, 0x42712C57];
+
+// Encrypts decrypted codes the way the devices' code lists are given
+fn encrypt(codes: &[(u32, u32)], seeds: &[u32; 4]) -> String {
+    let mut text = String::new();
+    for &(mut op1, mut op2) in codes {
+        let mut sum = 0u32;
+        for _ in 0..32 {
+            sum = sum.wrapping_add(0x9E3779B9);
+            op1 = op1.wrapping_add(
+                (op2 << 4).wrapping_add(seeds[0])
+                    ^ op2.wrapping_add(sum)
+                    ^ (op2 >> 5).wrapping_add(seeds[1]),
+            );
+            op2 = op2.wrapping_add(
+                (op1 << 4).wrapping_add(seeds[2])
+                    ^ op1.wrapping_add(sum)
+                    ^ (op1 >> 5).wrapping_add(seeds[3]),
+            );
+        }
+        text.push_str(&format!("{:08X} {:08X}\n", op1, op2));
+    }
+    text
+}
+
+fn read(gba: &GBA, addr: u32, len: usize) -> Vec<u8> {
+    gba.peek_memory(addr, len)
+}
+
+#[test]
+fn codebreaker() {
+    let mut gba = boot();
+    // The first conditional holds and the second doesn't, and the slide code writes 3 halfwords,
+    // adding 1 to the value and 4 to the address each time
+    let cheat = Cheat::parse(
+        CheatFormat::CodeBreaker,
+        "83000200 1234
+         33000204 0056
+         73000200 1234
+         83000206 BEEF
+         73000200 4321
+         83000208 BEEF
+         43000210 0010
+         00010003 0004",
+    )
+    .unwrap();
+    gba.add_cheat(cheat);
+    gba.run_frame();
+    assert_eq!(
+        read(&gba, 0x03000200, 10),
+        vec![0x34, 0x12, 0, 0, 0x56, 0, 0xEF, 0xBE, 0, 0]
+    );
+    assert_eq!(
+        read(&gba, 0x03000210, 10),
+        vec![0x10, 0, 0, 0, 0x11, 0, 0, 0, 0x12, 0]
+    );
+
+    assert_eq!(
+        Cheat::parse(CheatFormat::CodeBreaker, "91234567 89AB"),
+        Err(CheatError::Unsupported("91234567 89AB".to_string()))
+    );
+    assert_eq!(
+        Cheat::parse(CheatFormat::CodeBreaker, "43000210 0010"),
+        Err(CheatError::Incomplete("43000210 0010".to_string()))
+    );
+    assert_eq!(
+        Cheat::parse(CheatFormat::CodeBreaker, "8300020 1234"),
+        Err(CheatError::InvalidCode("8300020 1234".to_string()))
+    );
+}
+
+#[test]
+fn gameshark_and_rom_patches() {
+    let mut gba = boot();
+    let codes = encrypt(
+        &[
+            (0x23000220, 0xDEADBEEF),
+            // Patches the halfword at 0x08000008
+            (0x60000004, 0x00001234),
+        ],
+        &GAMESHARK_SEEDS,
+    );
+    let id = gba.add_cheat(Cheat::parse(CheatFormat::GameShark, &codes).unwrap());
+    assert_eq!(read(&gba, 0x08000008, 2), vec![0x34, 0x12]);
+    gba.run_frame();
+    assert_eq!(read(&gba, 0x03000220, 4), vec![0xEF, 0xBE, 0xAD, 0xDE]);
+
+    // Disabling the cheat undoes its ROM patches
+    assert!(gba.set_cheat_enabled(id, false));
+    assert_eq!(read(&gba, 0x08000008, 2), vec![0x1E, 0xFF]);
+    assert!(gba.remove_cheat(id));
+    assert!(!gba.set_cheat_enabled(id, true));
+}
+
+#[test]
+fn action_replay_max() {
+    let mut gba = boot();
+    let codes = encrypt(
+        &[
+            // 32-bit write to 0x03000230
+            (0x04300230, 0x12345678),
+            // Fills 4 bytes from 0x03000238
+            (0x00300238, 0x00000377),
+            // If the word at 0x03000230 is 0x87654321, write 0xAA, otherwise 0xBB
+            (0x8C300230, 0x87654321),
+            (0x00300240, 0x000000AA),
+            (0x00000000, 0x60000000),
+            (0x00300241, 0x000000BB),
+            (0x00000000, 0x40000000),
+            // If the halfword at 0x08040004, past the end of the ROM, is 0x0002, write 0xCC
+            (0x0A840004, 0x00000002),
+            (0x00300250, 0x000000CC),
+        ],
+        &ACTION_REPLAY_MAX_SEEDS,
+    );
+    gba.add_cheat(Cheat::parse(CheatFormat::ActionReplayMax, &codes).unwrap());
+    gba.run_frame();
+    assert_eq!(read(&gba, 0x03000230, 4), vec![0x78, 0x56, 0x34, 0x12]);
+    assert_eq!(read(&gba, 0x03000238, 5), vec![0x77, 0x77, 0x77, 0x77, 0]);
+    assert_eq!(read(&gba, 0x03000240, 2), vec![0, 0xBB]);
+    assert_eq!(read(&gba, 0x03000250, 1), vec![0xCC]);
+}
+
+#[test]
+fn gameshark_conditional_skips_lines() {
+    let mut gba = boot();
+    // If the halfword at 0x03000260 is 0x1234, which it isn't, run the next two lines, which are
+    // one code writing 0xAA to 0x03000264. The byte write to 0x03000268 after them still runs.
+    let codes = encrypt(
+        &[
+            (0xE0021234, 0x03000260),
+            (0x30000001, 0x000000AA),
+            (0x03000264, 0x00000000),
+            (0x03000268, 0x000000BB),
+        ],
+        &GAMESHARK_SEEDS,
+    );
+    gba.add_cheat(Cheat::parse(CheatFormat::GameShark, &codes).unwrap());
+    gba.run_frame();
+    assert_eq!(read(&gba, 0x03000264, 5), vec![0, 0, 0, 0, 0xBB]);
+}
+
+#[test]
+fn published_master_code() {
+    // The master code for Pokémon Emerald, as published for Action Replay MAX. It decrypts to a
+    // hook code and the game ID code, with Emerald's game code `BPEE`.
+    let format = CheatFormat::ActionReplayMax;
+    assert_eq!(
+        format.decrypt(0xD8BAE4D9, 0x4864DCE5),
+        (0xC40005EC, 0x00008401)
+    );
+    assert_eq!(
+        format.decrypt(0xA86CDBA5, 0x19BA49B3),
+        (0x45455042, 0x001DC0DE)
+    );
+
+    // Neither line does anything, since cheats are applied once a frame instead
+    let mut gba = boot();
+    let before = read(&gba, 0x02000000, 0x40000);
+    let master_code = "D8BAE4D9 4864DCE5
+                       A86CDBA5 19BA49B3";
+    gba.add_cheat(Cheat::parse(format, master_code).unwrap());
+    gba.run_frame();
+    assert_eq!(read(&gba, 0x02000000, 0x40000), before);
+}
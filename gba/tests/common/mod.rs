@@ -0,0 +1,43 @@
+// Each test file only uses some of these
+#![allow(dead_code)]
+
+use gba::{Cartridge, GBA};
+
+// Calls a function that increments r0 and stores it to 0x03000000 until it reaches 5, then
+// copies 4 words of the ROM to 0x03000100 with DMA3
+pub const PROGRAM: [u32; 22] = [
+    0xEA000001, // 08000000: b 0x0800000C
+    0xE2800001, // 08000004: add r0, r0, #0x1
+    0xE12FFF1E, // 08000008: bx lr
+    0xE3A00000, // 0800000C: mov r0, #0x0
+    0xE3A04403, // 08000010: mov r4, #0x3000000
+    0xEBFFFFFA, // 08000014: bl 0x08000004
+    0xE5840000, // 08000018: str r0, [r4]
+    0xE3500005, // 0800001C: cmp r0, #0x5
+    0x3AFFFFFB, // 08000020: bcc 0x08000014
+    0xE59F3020, // 08000024: ldr r3, [pc, #0x20]
+    0xE3A01302, // 08000028: mov r1, #0x8000000
+    0xE5831000, // 0800002C: str r1, [r3]
+    0xE59F1018, // 08000030: ldr r1, [pc, #0x18]
+    0xE5831004, // 08000034: str r1, [r3, #0x4]
+    0xE59F1014, // 08000038: ldr r1, [pc, #0x14]
+    0xE5831008, // 0800003C: str r1, [r3, #0x8]
+    0xE1A00000, // 08000040: mov r0, r0
+    0xE1A00000, // 08000044: mov r0, r0
+    0xEAFFFFFE, // 08000048: b 0x08000048
+    0x040000D4, 0x03000100, 0x84000004,
+];
+
+pub fn boot() -> GBA {
+    boot_rom(&PROGRAM)
+}
+
+// Boots straight into a cartridge holding the given ARM program, with the HLE BIOS
+pub fn boot_rom(program: &[u32]) -> GBA {
+    let rom = program.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let mut gba = GBA::new();
+    gba.use_hle_bios();
+    gba.insert_cartridge(Cartridge::new(rom).unwrap());
+    gba.skip_bios();
+    gba
+}
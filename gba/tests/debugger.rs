@@ -0,0 +1,93 @@
+mod common;
+
+use common::boot;
+use gba::{BreakCondition, Comparison, Operand, StopReason, WatchKind};
+
+const MAX_CYCLES: u64 = 1_000_000;
+
+#[test]
+fn condition_parsing() {
+    assert_eq!(
+        BreakCondition::parse("r0 == 0x10"),
+        Some(BreakCondition {
+            lhs: Operand::Register(0),
+            comparison: Comparison::Eq,
+            rhs: Operand::Immediate(0x10),
+        })
+    );
+    assert_eq!(
+        BreakCondition::parse("u16[0x04000006]>=160"),
+        Some(BreakCondition {
+            lhs: Operand::Memory {
+                addr: 0x04000006,
+                width: 2
+            },
+            comparison: Comparison::Ge,
+            rhs: Operand::Immediate(160),
+        })
+    );
+    assert_eq!(
+        BreakCondition::parse("lr < [0x03000000]").map(|condition| condition.comparison),
+        Some(Comparison::Lt)
+    );
+    assert_eq!(BreakCondition::parse("r16 == 0"), None);
+    assert_eq!(BreakCondition::parse("r0"), None);
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut gba = boot();
+    let condition = BreakCondition::parse("r0 == 3");
+    let id = gba.add_breakpoint(0x08000014, condition);
+
+    gba.debug_continue();
+    assert_eq!(gba.debug_run(MAX_CYCLES), Some(StopReason::Breakpoint(id)));
+    assert_eq!(gba.register(15), 0x08000014);
+    assert_eq!(gba.register(0), 3);
+
+    // Stepping over the call stops after it returns, while stepping into it stops at its start
+    gba.debug_step_over();
+    assert_eq!(gba.debug_run(MAX_CYCLES), Some(StopReason::Done));
+    assert_eq!(gba.register(15), 0x08000018);
+    assert_eq!(gba.register(0), 4);
+
+    gba.debug_step(3);
+    assert_eq!(gba.debug_run(MAX_CYCLES), Some(StopReason::Done));
+    assert_eq!(gba.register(15), 0x08000014);
+    gba.debug_step(1);
+    assert_eq!(gba.debug_run(MAX_CYCLES), Some(StopReason::Done));
+    assert_eq!(gba.register(15), 0x08000004);
+
+    assert!(gba.delete_breakpoint(id));
+    gba.debug_run_to(0x08000040);
+    assert_eq!(gba.debug_run(MAX_CYCLES), Some(StopReason::Done));
+    assert_eq!(gba.register(0), 5);
+}
+
+#[test]
+fn watchpoints_and_dma() {
+    let mut gba = boot();
+    let id = gba.add_watchpoint(0x03000000, 4, WatchKind::Write);
+
+    gba.debug_continue();
+    match gba.debug_run(MAX_CYCLES) {
+        Some(StopReason::Watchpoint(hit_id, hit)) => {
+            assert_eq!(hit_id, id);
+            assert_eq!((hit.addr, hit.write, hit.value), (0x03000000, true, 1));
+        }
+        reason => panic!("expected a watchpoint, got {:?}", reason),
+    }
+    assert_eq!(gba.register(15), 0x0800001C);
+    assert!(gba.delete_breakpoint(id));
+
+    // Watchpoints see writes made by DMA too
+    gba.set_break_on_dma(true);
+    let id = gba.add_watchpoint(0x03000108, 1, WatchKind::ReadWrite);
+    gba.debug_continue();
+    assert_eq!(gba.debug_run(MAX_CYCLES), Some(StopReason::DmaStart(3)));
+    assert!(matches!(
+        gba.debug_run(MAX_CYCLES),
+        Some(StopReason::Watchpoint(hit_id, _)) if hit_id == id
+    ));
+    assert_eq!(gba.peek_memory(0x03000100, 4), vec![0x01, 0x00, 0x00, 0xEA]);
+}
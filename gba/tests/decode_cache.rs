@@ -0,0 +1,37 @@
+mod common;
+
+use common::boot_rom;
+
+// Copies `mov r1, #0x1; bx lr` to 0x03000000 and calls it, then overwrites its first instruction
+// with `mov r1, #0x2` using DMA3 and calls it again, storing r1 after each call from 0x03000100
+const PROGRAM: [u32; 23] = [
+    0xE3A00403, // 08000000: mov r0, #0x3000000
+    0xE59F103C, // 08000004: ldr r1, [pc, #0x3C]
+    0xE5801000, // 08000008: str r1, [r0]
+    0xE59F103C, // 0800000C: ldr r1, [pc, #0x3C]
+    0xE5801004, // 08000010: str r1, [r0, #0x4]
+    0xE1A0E00F, // 08000014: mov lr, pc
+    0xE12FFF10, // 08000018: bx r0
+    0xE5801100, // 0800001C: str r1, [r0, #0x100]
+    0xE59F302C, // 08000020: ldr r3, [pc, #0x2C]
+    0xE28F1020, // 08000024: add r1, pc, #0x20
+    0xE5831000, // 08000028: str r1, [r3]
+    0xE5830004, // 0800002C: str r0, [r3, #0x4]
+    0xE59F1020, // 08000030: ldr r1, [pc, #0x20]
+    0xE5831008, // 08000034: str r1, [r3, #0x8]
+    0xE1A0E00F, // 08000038: mov lr, pc
+    0xE12FFF10, // 0800003C: bx r0
+    0xE5801104, // 08000040: str r1, [r0, #0x104]
+    0xEAFFFFFE, // 08000044: b .
+    0xE3A01001, // 08000048: mov r1, #0x1
+    0xE3A01002, // 0800004C: mov r1, #0x2
+    0xE12FFF1E, // 08000050: bx lr
+    0x040000D4, 0x84000001,
+];
+
+#[test]
+fn dma_overwriting_code() {
+    let mut gba = boot_rom(&PROGRAM);
+    gba.run_cycles(1000);
+    assert_eq!(gba.peek_memory(0x03000100, 8), [1, 0, 0, 0, 2, 0, 0, 0]);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::boot;
+use gba::GdbStub;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+// A minimal client, which sends a packet and waits for the acknowledgement and reply
+struct Client {
+    stream: TcpStream,
+    no_ack: bool,
+}
+
+impl Client {
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+
+        let mut received = Vec::new();
+        let mut byte = [0];
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            received.push(byte[0]);
+            // The reply ends with two checksum digits after the `#`
+            if received.len() >= 3 && received[received.len() - 3] == b'#' {
+                break;
+            }
+        }
+        if !self.no_ack {
+            self.stream.write_all(b"+").unwrap();
+            assert_eq!(received[0], b'+', "packet wasn't acknowledged");
+        }
+        let reply = String::from_utf8(received).unwrap();
+        let start = reply.find('$').unwrap();
+        reply[start + 1..reply.len() - 3].to_string()
+    }
+}
+
+// Serves a client running the given session on another thread, returning what it returns
+fn with_client<T: Send + 'static>(session: fn(&mut Client) -> T) -> T {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = thread::spawn(move || {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut client = Client {
+            stream,
+            no_ack: false,
+        };
+        session(&mut client)
+    });
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut gba = boot();
+    GdbStub::new(stream).run(&mut gba).unwrap();
+    client.join().unwrap()
+}
+
+#[test]
+fn registers_and_memory() {
+    with_client(|client| {
+        assert!(client
+            .request("qSupported:swbreak+")
+            .contains("qXfer:features:read+"));
+        assert_eq!(client.request("?"), "S05");
+
+        // The target description is read in chunks until the last one
+        let mut description = String::new();
+        loop {
+            let offset = description.len();
+            let chunk = client.request(&format!("qXfer:features:read:target.xml:{:x},100", offset));
+            description.push_str(&chunk[1..]);
+            if chunk.starts_with('l') {
+                break;
+            }
+        }
+        assert!(description.contains("org.gnu.gdb.arm.core"));
+        assert!(description.contains("<reg name=\"spsr_irq\""));
+
+        // r0-r15 and the CPSR, then the 27 banked registers
+        let registers = client.request("g");
+        assert_eq!(registers.len(), 44 * 8);
+        assert_eq!(&registers[15 * 8..16 * 8], "00000008");
+        assert_eq!(&registers[13 * 8..14 * 8], "007f0003");
+
+        assert_eq!(client.request("P0=78563412"), "OK");
+        assert_eq!(client.request("p0"), "78563412");
+        // sp_irq, as set up by the BIOS
+        assert_eq!(client.request("p20"), "a07f0003");
+
+        assert_eq!(client.request("M3000000,4:01020304"), "OK");
+        assert_eq!(client.request("m3000000,4"), "01020304");
+        assert_eq!(client.request("m8000000,4"), "010000ea");
+        assert_eq!(client.request("D"), "OK");
+    });
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    with_client(|client| {
+        assert_eq!(client.request("QStartNoAckMode"), "OK");
+        client.no_ack = true;
+
+        assert_eq!(client.request("Z0,8000014,4"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("pf"), "14000008");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("pf"), "04000008");
+        assert_eq!(client.request("z0,8000014,4"), "OK");
+
+        assert_eq!(client.request("Z2,3000000,4"), "OK");
+        assert_eq!(client.request("c"), "T05watch:3000000;");
+        assert_eq!(client.request("p0"), "01000000");
+        assert_eq!(client.request("D"), "OK");
+    });
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::boot_rom;
+use gba::GBA;
+
+// Enables the VBLANK and keypad interrupts, leaving IME off, then writes HALTCNT with the given
+// instruction before setting 0x03000000 to 1
+fn program(set_haltcnt: u32) -> [u32; 18] {
+    [
+        0xE3A00301,  // 08000000: mov r0, #0x4000000
+        0xE2802C02,  // 08000004: add r2, r0, #0x200
+        0xE3A01001,  // 08000008: mov r1, #0x1
+        0xE3811A01,  // 0800000C: orr r1, r1, #0x1000
+        0xE1C210B0,  // 08000010: strh r1, [r2]
+        0xE3A01008,  // 08000014: mov r1, #0x8
+        0xE1C010B4,  // 08000018: strh r1, [r0, #0x4]
+        0xE2803C01,  // 0800001C: add r3, r0, #0x100
+        0xE3A01901,  // 08000020: mov r1, #0x4000
+        0xE3811001,  // 08000024: orr r1, r1, #0x1
+        0xE1C313B2,  // 08000028: strh r1, [r3, #0x32]
+        0xE2802C03,  // 0800002C: add r2, r0, #0x300
+        set_haltcnt, // 08000030: mov r1, #...
+        0xE5C21001,  // 08000034: strb r1, [r2, #0x1]
+        0xE3A03403,  // 08000038: mov r3, #0x3000000
+        0xE3A01001,  // 0800003C: mov r1, #0x1
+        0xE5831000,  // 08000040: str r1, [r3]
+        0xEAFFFFFE,  // 08000044: b .
+    ]
+}
+
+fn resumed(gba: &GBA) -> bool {
+    gba.peek_memory(0x03000000, 1) == [1]
+}
+
+#[test]
+fn halt() {
+    // Writes 0 to HALTCNT, with `mov r1, #0x0`
+    let mut gba = boot_rom(&program(0xE3A01000));
+    gba.run_cycles(10_000);
+    assert!(!resumed(&gba));
+
+    // VBLANK ends the halt at the start of line 160, even though IME is off
+    gba.run_until(resumed);
+    assert!((160 * 1232..160 * 1232 + 100).contains(&gba.cycles()));
+    assert_eq!(gba.cpsr() & 0x1F, 0x1F);
+}
+
+#[test]
+fn stop() {
+    // Writes 0x80 to HALTCNT, with `mov r1, #0x80`
+    let mut gba = boot_rom(&program(0xE3A01080));
+    // The LCD is off, so a blank frame comes straight back and time stops
+    let frame = gba.run_frame();
+    assert!(frame.framebuffer.iter().all(|&byte| byte == 0xFF));
+    let cycles = gba.cycles();
+    gba.run_cycles(300 * 1232);
+    assert_eq!(gba.cycles(), cycles);
+    assert!(!resumed(&gba));
+
+    // Pressing A requests the keypad interrupt, which ends stop mode
+    gba.update_key_state(0x3FE);
+    gba.run_cycles(100);
+    assert!(resumed(&gba));
+}
+
+#[test]
+fn stop_with_keys_already_held() {
+    // A is held before the program enables the keypad interrupt, which is requested as soon as
+    // KEYCNT is written, so stop mode ends straight away
+    let mut gba = boot_rom(&program(0xE3A01080));
+    gba.update_key_state(0x3FE);
+    gba.run_cycles(1000);
+    assert!(resumed(&gba));
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::boot_rom;
+
+// Enables the VBLANK interrupt with a handler that acknowledges interrupts for IntrWait, then
+// counts calls to VBlankIntrWait at 0x03000000
+const VBLANK_COUNTER: [u32; 23] = [
+    0xE3A00301, // 08000000: mov r0, #0x4000000
+    0xE3A01008, // 08000004: mov r1, #0x8
+    0xE1C010B4, // 08000008: strh r1, [r0, #0x4]
+    0xE2802C02, // 0800000C: add r2, r0, #0x200
+    0xE3A01001, // 08000010: mov r1, #0x1
+    0xE1C210B0, // 08000014: strh r1, [r2]
+    0xE1C210B8, // 08000018: strh r1, [r2, #0x8]
+    0xE28F3018, // 0800001C: add r3, pc, #0x18
+    0xE5003004, // 08000020: str r3, [r0, #-0x4]
+    0xE3A04403, // 08000024: mov r4, #0x3000000
+    0xE3A05000, // 08000028: mov r5, #0x0
+    0xEF050000, // 0800002C: swi #0x50000
+    0xE2855001, // 08000030: add r5, r5, #0x1
+    0xE5845000, // 08000034: str r5, [r4]
+    0xEAFFFFFB, // 08000038: b 0x0800002C
+    // IRQ handler
+    0xE5901200, // 0800003C: ldr r1, [r0, #0x200]
+    0xE0011821, // 08000040: and r1, r1, r1, lsr #16
+    0xE2802C02, // 08000044: add r2, r0, #0x200
+    0xE1C210B2, // 08000048: strh r1, [r2, #0x2]
+    0xE15030B8, // 0800004C: ldrh r3, [r0, #-0x8]
+    0xE1833001, // 08000050: orr r3, r3, r1
+    0xE14030B8, // 08000054: strh r3, [r0, #-0x8]
+    0xE12FFF1E, // 08000058: bx lr
+];
+
+#[test]
+fn vblank_intr_wait() {
+    let mut gba = boot_rom(&VBLANK_COUNTER);
+    // VBLANK starts at line 160 of each frame, so three have started by the end of the third
+    gba.run_cycles(3 * 228 * 1232);
+    assert_eq!(gba.peek_memory(0x03000000, 4), vec![3, 0, 0, 0]);
+    // Until the next one, the CPU waits in the SWI
+    gba.run_cycles(150 * 1232);
+    assert_eq!(gba.peek_memory(0x03000000, 4), vec![3, 0, 0, 0]);
+    gba.run_cycles(20 * 1232);
+    assert_eq!(gba.peek_memory(0x03000000, 4), vec![4, 0, 0, 0]);
+}
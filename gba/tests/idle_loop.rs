@@ -0,0 +1,57 @@
+mod common;
+
+use common::boot_rom;
+use gba::GBA;
+
+// Waits for line 100 by polling VCOUNT, then stores it to 0x03000000
+const POLL_VCOUNT: [u32; 7] = [
+    0xE3A00301, // 08000000: mov r0, #0x4000000
+    0xE3A03403, // 08000004: mov r3, #0x3000000
+    0xE1D010B6, // 08000008: ldrh r1, [r0, #0x6]
+    0xE3510064, // 0800000C: cmp r1, #0x64
+    0x1AFFFFFC, // 08000010: bne 0x08000008
+    0xE5831000, // 08000014: str r1, [r3]
+    0xEAFFFFFE, // 08000018: b .
+];
+
+// The same, but also storing each VCOUNT read to 0x03000004
+const POLL_VCOUNT_AND_STORE: [u32; 8] = [
+    0xE3A00301, // 08000000: mov r0, #0x4000000
+    0xE3A03403, // 08000004: mov r3, #0x3000000
+    0xE1D010B6, // 08000008: ldrh r1, [r0, #0x6]
+    0xE5831004, // 0800000C: str r1, [r3, #0x4]
+    0xE3510064, // 08000010: cmp r1, #0x64
+    0x1AFFFFFB, // 08000014: bne 0x08000008
+    0xE5831000, // 08000018: str r1, [r3]
+    0xEAFFFFFE, // 0800001C: b .
+];
+
+fn reached_line_100(gba: &GBA) -> bool {
+    gba.peek_memory(0x03000000, 1) == [100]
+}
+
+#[test]
+fn vcount_polling() {
+    let mut gba = boot_rom(&POLL_VCOUNT);
+    gba.run_until(reached_line_100);
+    // Skipping ahead stops at each event, and an iteration an event interrupts isn't idle, so the
+    // loop still ends within an iteration of VCOUNT changing
+    assert!((100 * 1232..100 * 1232 + 100).contains(&gba.cycles()));
+    let stats = gba.idle_loop_stats()[&0x08000008];
+    assert!(stats.detections > 0);
+    assert!(stats.cycles_skipped > 50 * 1232);
+
+    let mut gba = boot_rom(&POLL_VCOUNT);
+    gba.set_idle_loop_detection(false);
+    gba.run_until(reached_line_100);
+    assert!((100 * 1232..100 * 1232 + 100).contains(&gba.cycles()));
+    assert!(gba.idle_loop_stats().is_empty());
+}
+
+#[test]
+fn loops_with_side_effects() {
+    let mut gba = boot_rom(&POLL_VCOUNT_AND_STORE);
+    gba.run_until(reached_line_100);
+    assert!((100 * 1232..100 * 1232 + 100).contains(&gba.cycles()));
+    assert!(gba.idle_loop_stats().is_empty());
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{boot, boot_rom};
+use gba::{io_register_name, AccessSource, IoAccess, GBA};
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Runs for a while, returning the IO accesses made
+fn observe(gba: &mut GBA) -> Vec<IoAccess> {
+    let accesses = Rc::new(RefCell::new(Vec::new()));
+    let observed = accesses.clone();
+    gba.set_io_observer(Some(Box::new(move |access: &IoAccess| {
+        observed.borrow_mut().push(*access)
+    })));
+    gba.run_cycles(10_000);
+    gba.set_io_observer(None);
+    accesses.take()
+}
+
+#[test]
+fn register_names() {
+    assert_eq!(io_register_name(0x04000000), Some(("DISPCNT", 0)));
+    assert_eq!(io_register_name(0x04000021), Some(("BG2PA", 1)));
+    assert_eq!(io_register_name(0x0400002A), Some(("BG2X", 2)));
+    assert_eq!(io_register_name(0x040000DE), Some(("DMA3CNT_H", 0)));
+    assert_eq!(io_register_name(0x04000058), None);
+    assert_eq!(io_register_name(0x03000000), None);
+}
+
+#[test]
+fn sized_accesses() {
+    let mut gba = boot();
+    let accesses = observe(&mut gba);
+
+    // Each word written to the DMA registers is one access, stamped with the store's address
+    let writes: Vec<(AccessSource, u32, u32, u32)> = accesses
+        .iter()
+        .filter(|access| access.write)
+        .map(|access| (access.source, access.addr, access.size, access.value))
+        .collect();
+    assert_eq!(
+        writes,
+        vec![
+            (AccessSource::Cpu(0x0800002C), 0x040000D4, 4, 0x08000000),
+            (AccessSource::Cpu(0x08000034), 0x040000D8, 4, 0x03000100),
+            (AccessSource::Cpu(0x0800003C), 0x040000DC, 4, 0x84000004),
+        ]
+    );
+    assert!(accesses.iter().all(|access| access.implemented));
+    assert!(accesses.windows(2).all(|pair| pair[0].time < pair[1].time));
+    assert!(accesses[0]
+        .to_string()
+        .ends_with("pc=0800002C W32 040000D4 DMA3SAD = 08000000"));
+}
+
+#[test]
+fn unimplemented_registers() {
+    let program: [u32; 6] = [
+        0xE3A03301, // 08000000: mov r3, #0x4000000
+        0xE3A01080, // 08000004: mov r1, #0x80
+        0xE2835C01, // 08000008: add r5, r3, #0x100
+        0xE1C512B8, // 0800000C: strh r1, [r5, #0x28]
+        0xE1D320B6, // 08000010: ldrh r2, [r3, #0x6]
+        0xEAFFFFFE, // 08000014: b 0x08000014
+    ];
+    let mut gba = boot_rom(&program);
+    let accesses = observe(&mut gba);
+    assert_eq!(accesses.len(), 2);
+    assert_eq!(accesses[0].register(), Some("SIOCNT"));
+    assert_eq!((accesses[0].size, accesses[0].value), (2, 0x80));
+    assert!(accesses[0].write && !accesses[0].implemented);
+    assert!(accesses[0].to_string().ends_with("(unimplemented)"));
+    assert_eq!(accesses[1].register(), Some("VCOUNT"));
+    assert_eq!(accesses[1].source, AccessSource::Cpu(0x08000010));
+    assert!(!accesses[1].write && accesses[1].implemented);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::boot_rom;
+use std::convert::TryInto;
+
+// Reads the BIOS and an unused IO register from the cartridge, then copies a word of the ROM and
+// a word of the BIOS with DMA3, storing all four results from 0x03000000
+const PROGRAM: [u32; 18] = [
+    0xE3A00403, // 08000000: mov r0, #0x3000000
+    0xE3A01000, // 08000004: mov r1, #0x0
+    0xE5912000, // 08000008: ldr r2, [r1]
+    0xE5802000, // 0800000C: str r2, [r0]
+    0xE3A01301, // 08000010: mov r1, #0x4000000
+    0xE5912058, // 08000014: ldr r2, [r1, #0x58]
+    0xE5802004, // 08000018: str r2, [r0, #0x4]
+    0xE28110D4, // 0800001C: add r1, r1, #0xD4
+    0xE28F301C, // 08000020: add r3, pc, #0x1C
+    0xE2804008, // 08000024: add r4, r0, #0x8
+    0xE59F5010, // 08000028: ldr r5, [pc, #0x10]
+    0xE8810038, // 0800002C: stm r1, {r3, r4, r5}
+    0xE3A03000, // 08000030: mov r3, #0x0
+    0xE280400C, // 08000034: add r4, r0, #0xC
+    0xE8810038, // 08000038: stm r1, {r3, r4, r5}
+    0xEAFFFFFE, // 0800003C: b 0x0800003C
+    0x84000001, 0x12345678,
+];
+
+#[test]
+fn protected_and_unmapped_reads() {
+    let mut gba = boot_rom(&PROGRAM);
+    gba.run_cycles(1000);
+    let results: Vec<u32> = gba
+        .peek_memory(0x03000000, 16)
+        .chunks(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+
+    // The BIOS reads as the last opcode it fetched, from just before jumping to the cartridge
+    assert_eq!(results[0], 0xE129F000);
+    // Unused IO reads as the opcode being fetched, two instructions ahead of the load
+    assert_eq!(results[1], PROGRAM[7]);
+    // DMA reads of the BIOS give the last value DMA read
+    assert_eq!(results[2], 0x12345678);
+    assert_eq!(results[3], 0x12345678);
+
+    // The debugger sees the same values as code outside the BIOS. Refilling the pipeline after
+    // each branch of the loop last fetches the word after it.
+    let open_bus = PROGRAM[16].to_le_bytes();
+    assert_eq!(gba.peek_memory(0x00000000, 4), [0x00, 0xF0, 0x29, 0xE1]);
+    assert_eq!(gba.peek_memory(0x04000058, 4), open_bus);
+    assert_eq!(gba.peek_memory(0x10000000, 4), open_bus);
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{boot, boot_rom};
+use gba::{StateError, GBA};
+
+// Counts up in r0, writing the count to the backdrop colour, so every frame depends on exactly
+// when each line was drawn
+const COUNTER: [u32; 5] = [
+    0xE3A01405, // 08000000: mov r1, #0x5000000
+    0xE3A00000, // 08000004: mov r0, #0x0
+    0xE2800001, // 08000008: add r0, r0, #0x1
+    0xE1C100B0, // 0800000C: strh r0, [r1]
+    0xEAFFFFFC, // 08000010: b 0x08000008
+];
+
+// The framebuffer, registers and time after running a couple of frames
+fn run(gba: &mut GBA) -> (Vec<u8>, Vec<u32>, u64) {
+    gba.run_frame();
+    let framebuffer = gba.run_frame().framebuffer.to_vec();
+    let registers = (0..16).map(|n| gba.register(n)).collect();
+    (framebuffer, registers, gba.cycles())
+}
+
+#[test]
+fn round_trip() {
+    let mut gba = boot_rom(&COUNTER);
+    for _ in 0..3 {
+        gba.run_frame();
+    }
+    gba.run_cycles(1000);
+    let state = gba.save_state();
+    let expected = run(&mut gba);
+    assert!(expected.0.chunks(2).any(|pixel| pixel != &expected.0[..2]));
+
+    gba.load_state(&state).unwrap();
+    assert_eq!(run(&mut gba), expected);
+
+    // The state can also be loaded into a fresh machine running the same ROM
+    let mut other = boot_rom(&COUNTER);
+    other.load_state(&state).unwrap();
+    assert_eq!(run(&mut other), expected);
+}
+
+#[test]
+fn rejected_states() {
+    let mut gba = boot_rom(&COUNTER);
+    gba.run_frame();
+    let state = gba.save_state();
+
+    let mut bad_magic = state.clone();
+    bad_magic[0] ^= 0xFF;
+    assert_eq!(gba.load_state(&bad_magic), Err(StateError::BadMagic));
+
+    let mut bad_version = state.clone();
+    bad_version[4..8].copy_from_slice(&1000u32.to_le_bytes());
+    assert_eq!(
+        gba.load_state(&bad_version),
+        Err(StateError::UnsupportedVersion(1000))
+    );
+
+    // A state saved while running a different ROM
+    assert_eq!(boot().load_state(&state), Err(StateError::RomMismatch));
+
+    assert_eq!(
+        gba.load_state(&state[..state.len() - 1]),
+        Err(StateError::Truncated)
+    );
+    assert_eq!(gba.load_state(&state[..6]), Err(StateError::Truncated));
+
+    let mut extended = state.clone();
+    extended.push(0);
+    assert_eq!(gba.load_state(&extended), Err(StateError::Corrupt));
+}
+
+#[test]
+fn failed_load_leaves_state_unchanged() {
+    let mut gba = boot_rom(&COUNTER);
+    gba.run_frame();
+    let old_state = gba.save_state();
+    gba.run_frame();
+    gba.run_cycles(1234);
+    let state = gba.save_state();
+
+    // Most of the components are loaded from the old state before it's found to be truncated
+    let truncated = &old_state[..old_state.len() - 1];
+    assert_eq!(gba.load_state(truncated), Err(StateError::Truncated));
+    assert_eq!(gba.save_state(), state);
+
+    let mut reference = boot_rom(&COUNTER);
+    reference.load_state(&state).unwrap();
+    assert_eq!(run(&mut gba), run(&mut reference));
+}
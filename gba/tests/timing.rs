@@ -0,0 +1,124 @@
+mod common;
+
+use common::boot_rom;
+
+// Runs from the WS2 mirror of the ROM with the prefetch buffer on, then lowers WS2's wait states
+// with the last register of a block store, so the buffer is partway through reading a halfword at
+// the old speed when the CPU next fetches. `muls` multiplies beforehand vary how far it's got.
+fn wait_state_change_program(muls: usize) -> Vec<u32> {
+    let mut program = vec![
+        0xE3A00303, // 08000000: mov r0, #0xC000000
+        0xE280F010, // 08000004: add pc, r0, #0x10
+        0xE1A00000, // 08000008: mov r0, r0
+        0xE1A00000, // 0800000C: mov r0, r0
+        0xE3A00301, // 08000010: mov r0, #0x4000000
+        0xE2800C02, // 08000014: add r0, r0, #0x200
+        0xE3A01901, // 08000018: mov r1, #0x4000
+        0xE1C010B4, // 0800001C: strh r1, [r0, #0x4]
+        0xE3818B01, // 08000020: orr r8, r1, #0x400
+        0xE3A07000, // 08000024: mov r7, #0x0
+        0xE2400018, // 08000028: sub r0, r0, #0x18
+    ];
+    program.extend(std::iter::repeat_n(0xE0020191, muls)); // mul r2, r1, r1
+    program.extend([
+        0xE88001FE, // stm r0, {r1-r8}
+        0xE3A03001, // mov r3, #0x1
+        0xE3A04403, // mov r4, #0x3000000
+        0xE5843000, // str r3, [r4]
+        0xEAFFFFFE, // b .
+    ]);
+    program
+}
+
+#[test]
+fn wait_state_change_during_prefetch() {
+    for muls in 0..8 {
+        let mut gba = boot_rom(&wait_state_change_program(muls));
+        gba.run_cycles(1000);
+        assert_eq!(
+            gba.peek_memory(0x03000000, 1),
+            vec![1],
+            "{} multiplies",
+            muls
+        );
+    }
+}
+
+#[test]
+fn first_frame_is_fully_drawn() {
+    let program = [
+        0xE3A00405, // 08000000: mov r0, #0x5000000
+        0xE3A0101F, // 08000004: mov r1, #0x1F
+        0xE1C010B0, // 08000008: strh r1, [r0]
+        0xEAFFFFFE, // 0800000C: b .
+    ];
+    let mut gba = boot_rom(&program);
+    // The backdrop is set to red well before the first line ends, so it should cover every line
+    // of the first frame, including line 0
+    let frame = gba.run_frame();
+    assert!(frame
+        .framebuffer
+        .chunks(2)
+        .all(|pixel| pixel == [0x1F, 0x00]));
+}
+
+#[test]
+fn run_frame_after_run_cycles() {
+    let program = [
+        0xEAFFFFFE, // 08000000: b .
+    ];
+    let mut gba = boot_rom(&program);
+    // Stop just after the first frame's VBLANK starts, leaving it untaken
+    gba.run_cycles(160 * 1232 + 100);
+    let start = gba.cycles();
+    gba.run_frame();
+    // The next frame is a whole frame later
+    assert!(gba.cycles() - start > 227 * 1232);
+}
+
+#[test]
+fn run_until() {
+    let program = [
+        0xE2800001, // 08000000: add r0, r0, #0x1
+        0xEAFFFFFD, // 08000004: b 0x08000000
+    ];
+    let mut gba = boot_rom(&program);
+    // The predicate is checked after every instruction, so it stops as soon as it holds
+    gba.run_until(|gba| gba.register(0) == 50);
+    assert_eq!(gba.register(0), 50);
+    assert_eq!(gba.register(15), 0x08000004);
+}
+
+#[test]
+fn scheduled_events() {
+    let program = [
+        0xE3A00301, // 08000000: mov r0, #0x4000000
+        0xE2800C01, // 08000004: add r0, r0, #0x100
+        0xE59F1004, // 08000008: ldr r1, [pc, #0x4]
+        0xE5801000, // 0800000C: str r1, [r0]
+        0xEAFFFFFE, // 08000010: b .
+        0x00C0FFF0, // Timer 0 counting every cycle from 0xFFF0, with its IRQ enabled
+    ];
+    let mut gba = boot_rom(&program);
+    gba.run_cycles(100);
+    let read = |gba: &gba::GBA, addr| {
+        let bytes = gba.peek_memory(addr, 2);
+        u16::from_le_bytes([bytes[0], bytes[1]])
+    };
+
+    // The timer overflows every 16 cycles, requesting its interrupt
+    assert_ne!(read(&gba, 0x04000202) & (1 << 3), 0);
+    let (start, counter) = (gba.cycles(), read(&gba, 0x04000100));
+    for cycles in [7, 100, 1000, 5000] {
+        gba.run_cycles(cycles);
+        let elapsed = (gba.cycles() - start) as u16;
+        let expected = 0xFFF0 + (counter - 0xFFF0 + elapsed) % 16;
+        assert_eq!(read(&gba, 0x04000100), expected);
+    }
+
+    // Each line takes 1232 cycles, and a frame 228 lines
+    for cycles in [1232, 100 * 1232 + 500, 200 * 1232] {
+        gba.run_cycles(cycles);
+        assert_eq!(read(&gba, 0x04000006) as u64, gba.cycles() / 1232 % 228);
+    }
+}
@@ -1,10 +1,12 @@
 pub mod mmu;
 pub mod ram;
 pub mod rom;
+pub mod save_state;
 
 pub use self::mmu::MMU;
 pub use self::ram::RAM;
 pub use self::rom::ROM;
+pub use self::save_state::{SaveState, StateError, StateReader, StateWriter};
 
 pub trait Memory {
     fn read(&mut self, addr: usize) -> u8 {
@@ -14,6 +16,11 @@ pub trait Memory {
     fn peek(&self, addr: usize) -> u8;
     fn write(&mut self, addr: usize, data: u8);
 
+    // Whether anything responds at an address, so reads of it don't return open bus
+    fn is_mapped(&self, _addr: usize) -> bool {
+        true
+    }
+
     fn read_u16(&mut self, addr: usize) -> u16 {
         let lo = self.read(addr) as u16;
         let hi = self.read(addr + 1) as u16;
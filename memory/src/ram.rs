@@ -1,4 +1,4 @@
-use crate::Memory;
+use crate::{Memory, SaveState, StateError, StateReader, StateWriter};
 
 pub struct RAM<const LENGTH: usize> {
     memory: Vec<u8>,
@@ -21,3 +21,13 @@ impl<const LENGTH: usize> Memory for RAM<LENGTH> {
         self.memory[addr] = data
     }
 }
+
+impl<const LENGTH: usize> SaveState for RAM<LENGTH> {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.memory);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.read_bytes_into(&mut self.memory)
+    }
+}
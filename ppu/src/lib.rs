@@ -12,7 +12,8 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::{cmp, iter};
 
-use memory::Memory;
+use bitfield::BitRange;
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
 
 pub struct PPU {
     vram: Rc<RefCell<dyn Memory>>,
@@ -94,12 +95,25 @@ impl PPU {
         }
     }
 
+    // Advances to the next point in the scanline where something happens: the start of the
+    // scanline, the start of HBLANK, or the end of HBLANK
     // Returns (vblank, hblank, vblank_irq, hblank_irq, vcounter_irq)
-    pub fn tick(&mut self) -> (bool, bool, bool, bool, bool) {
-        if self.scan_cycle == 0 && self.scan_line < 160 {
+    pub fn step(&mut self) -> (bool, bool, bool, bool, bool) {
+        let events = self.increment_scan(self.cycles_until_event());
+        // Visible lines are drawn over HDRAW, so they're done as HBLANK starts
+        if self.scan_cycle == 960 && self.scan_line < 160 {
             self.draw_scanline();
         }
-        self.increment_scan()
+        events
+    }
+
+    // The number of cycles until the next call to `step` is due
+    pub fn cycles_until_event(&self) -> u32 {
+        match self.scan_cycle {
+            0..=959 => 960 - self.scan_cycle,
+            960..=1230 => 1231 - self.scan_cycle,
+            _ => 1232 - self.scan_cycle,
+        }
     }
 
     pub fn try_get_framebuffer(&mut self) -> Option<[u8; 240 * 160 * 2]> {
@@ -108,15 +122,20 @@ impl PPU {
         temp.then(|| self.framebuffer)
     }
 
+    // Forgets a completed frame that hasn't been taken
+    pub fn discard_frame(&mut self) {
+        self.frame_ready = false;
+    }
+
     pub fn vcount(&self) -> u8 {
         self.scan_line
     }
 
-    fn increment_scan(&mut self) -> (bool, bool, bool, bool, bool) {
+    fn increment_scan(&mut self, cycles: u32) -> (bool, bool, bool, bool, bool) {
         let (mut vblank, mut hblank) = (false, false); // Whether region was just entered
         let (mut vblank_irq, mut hblank_irq, mut vcounter_irq) = (false, false, false);
 
-        self.scan_cycle = (self.scan_cycle + 1) % 1232;
+        self.scan_cycle = (self.scan_cycle + cycles) % 1232;
 
         if self.scan_cycle == 0 {
             self.scan_line = (self.scan_line + 1) % 228;
@@ -541,3 +560,103 @@ impl Memory for PPU {
         }
     }
 }
+
+impl SaveState for PPU {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.scan_line);
+        state.write_u32(self.scan_cycle);
+
+        state.write_u16(self.lcd_control_reg.bit_range(15, 0));
+        state.write_u16(self.lcd_status_reg.bit_range(15, 0));
+
+        for bg_control_reg in &self.bg_control_regs {
+            state.write_u16(bg_control_reg.bit_range(15, 0));
+        }
+        for (x, y) in &self.scroll_regs {
+            state.write_u16(x.bit_range(15, 0));
+            state.write_u16(y.bit_range(15, 0));
+        }
+        for (x, y) in &self.bg_ref_regs {
+            state.write_u32(x.bit_range(31, 0));
+            state.write_u32(y.bit_range(31, 0));
+        }
+        for (pa, pb, pc, pd) in &self.bg_aff_param_regs {
+            state.write_u16(pa.bit_range(15, 0));
+            state.write_u16(pb.bit_range(15, 0));
+            state.write_u16(pc.bit_range(15, 0));
+            state.write_u16(pd.bit_range(15, 0));
+        }
+
+        state.write_u16(self.mosaic_reg.bit_range(15, 0));
+        state.write_u16(self.blend_control_reg.bit_range(15, 0));
+        state.write_u16(self.blend_alpha_reg.bit_range(15, 0));
+        state.write_u16(self.blend_fade_reg.bit_range(15, 0));
+
+        for (x, y) in [&self.win0_coords, &self.win1_coords] {
+            state.write_u16(x.bit_range(15, 0));
+            state.write_u16(y.bit_range(15, 0));
+        }
+        state.write_u16(self.win_inside.bit_range(15, 0));
+        state.write_u16(self.win_outside.bit_range(15, 0));
+
+        for (x, y) in &self.bg_ref_internal {
+            state.write_i32(*x);
+            state.write_i32(*y);
+        }
+
+        // The window masks are recomputed before each scanline is drawn, so they aren't saved
+        state.write_bytes(&self.framebuffer);
+        state.write_bool(self.frame_ready);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.scan_line = state.read_u8()?;
+        self.scan_cycle = state.read_u32()?;
+        if self.scan_line >= 228 || self.scan_cycle >= 1232 {
+            return Err(StateError::Corrupt);
+        }
+
+        self.lcd_control_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.lcd_status_reg.set_bit_range(15, 0, state.read_u16()?);
+
+        for bg_control_reg in &mut self.bg_control_regs {
+            bg_control_reg.set_bit_range(15, 0, state.read_u16()?);
+        }
+        for (x, y) in &mut self.scroll_regs {
+            x.set_bit_range(15, 0, state.read_u16()?);
+            y.set_bit_range(15, 0, state.read_u16()?);
+        }
+        for (x, y) in &mut self.bg_ref_regs {
+            x.set_bit_range(31, 0, state.read_u32()?);
+            y.set_bit_range(31, 0, state.read_u32()?);
+        }
+        for (pa, pb, pc, pd) in &mut self.bg_aff_param_regs {
+            pa.set_bit_range(15, 0, state.read_u16()?);
+            pb.set_bit_range(15, 0, state.read_u16()?);
+            pc.set_bit_range(15, 0, state.read_u16()?);
+            pd.set_bit_range(15, 0, state.read_u16()?);
+        }
+
+        self.mosaic_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.blend_control_reg
+            .set_bit_range(15, 0, state.read_u16()?);
+        self.blend_alpha_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.blend_fade_reg.set_bit_range(15, 0, state.read_u16()?);
+
+        for (x, y) in [&mut self.win0_coords, &mut self.win1_coords] {
+            x.set_bit_range(15, 0, state.read_u16()?);
+            y.set_bit_range(15, 0, state.read_u16()?);
+        }
+        self.win_inside.set_bit_range(15, 0, state.read_u16()?);
+        self.win_outside.set_bit_range(15, 0, state.read_u16()?);
+
+        for (x, y) in &mut self.bg_ref_internal {
+            *x = state.read_i32()?;
+            *y = state.read_i32()?;
+        }
+
+        state.read_bytes_into(&mut self.framebuffer)?;
+        self.frame_ready = state.read_bool()?;
+        Ok(())
+    }
+}
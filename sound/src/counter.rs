@@ -0,0 +1,12 @@
+// Advances a counter that counts down to 0 and is then reloaded with `period` on the following
+// tick, as if it were ticked `cycles` times. Returns the number of times it was reloaded.
+pub fn advance_counter(counter: &mut u32, period: u32, cycles: u32) -> u32 {
+    if cycles <= *counter {
+        *counter -= cycles;
+        return 0;
+    }
+
+    let remaining = cycles - *counter - 1;
+    *counter = period - remaining % (period + 1);
+    1 + remaining / (period + 1)
+}
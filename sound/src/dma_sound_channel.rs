@@ -1,3 +1,5 @@
+use memory::{SaveState, StateError, StateReader, StateWriter};
+
 use std::collections::VecDeque;
 
 pub struct DmaSoundChannel {
@@ -53,3 +55,26 @@ impl DmaSoundChannel {
         }
     }
 }
+
+impl SaveState for DmaSoundChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u32(self.fifo.len() as u32);
+        for &entry in &self.fifo {
+            state.write_u32(entry);
+        }
+        state.write_u32(self.fifo_octet_i as u32);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let len = state.read_u32()?;
+        self.fifo.clear();
+        for _ in 0..len {
+            self.fifo.push_back(state.read_u32()?);
+        }
+        self.fifo_octet_i = state.read_u32()? as usize;
+        if self.fifo_octet_i > 3 {
+            return Err(StateError::Corrupt);
+        }
+        Ok(())
+    }
+}
@@ -2,6 +2,7 @@
 extern crate bitfield;
 
 mod consts;
+mod counter;
 mod dma_sound_channel;
 mod noise_channel;
 mod registers;
@@ -16,7 +17,8 @@ use crate::tone_channel::*;
 use crate::wave_channel::*;
 use crate::noise_channel::*;
 
-use memory::Memory;
+use bitfield::BitRange;
+use memory::{Memory, SaveState, StateError, StateReader, StateWriter};
 
 use std::sync::{Arc, Mutex};
 
@@ -44,8 +46,8 @@ pub struct SoundController {
     psg_left_right_reg: PsgLeftRightReg,
     dma_control_reg: DmaControlMixReg,
     master_enable: bool,
-    sample_divider: u32,
-    request_dma: bool,
+    // The timestamp up to which the channels have been advanced
+    timestamp: u64,
     audio_buffer: Arc<Mutex<AudioRingBuffer>>,
 }
 
@@ -59,77 +61,81 @@ impl SoundController {
             psg_left_right_reg: PsgLeftRightReg(0),
             dma_control_reg: DmaControlMixReg(0),
             master_enable: false,
-            sample_divider: 0,
-            request_dma: false,
+            timestamp: 0,
             audio_buffer,
         }
     }
 
-    pub fn tick(&mut self) -> bool {
+    // Advances the channels up to the given cycle timestamp. This must be done before register
+    // writes, since they can restart the channels.
+    pub fn sync(&mut self, now: u64) {
+        let cycles = (now - self.timestamp) as u32;
+        self.timestamp = now;
+
         for tone_channel in &mut self.tone_channels {
-            tone_channel.tick();
+            tone_channel.tick(cycles);
         }
 
-        self.wave_channel.tick();
-        self.noise_channel.tick();
-
-        if self.sample_divider > 0 {
-            self.sample_divider -= 1;
-        } else {
-            self.sample_divider = 16_777_216 / 44_100;
-            let mut audio_buffer = self.audio_buffer.lock().unwrap();
-            let write_i = audio_buffer.write_cursor & (audio_buffer.buffer.len() - 1);
-            audio_buffer.buffer[write_i] = 0.0;
-            if self.master_enable {
-                let psg_multiplier = 0.25 * self.dma_control_reg.psg_vol_multiplier();
-
-                for i in [0, 1] {
-                    let psg_enabled = self.psg_left_right_reg.channel_enabled(i);
-                    // TODO: Separate left and right audio
-                    if !(psg_enabled.left || psg_enabled.right) {
-                        continue;
-                    }
-                    audio_buffer.buffer[write_i] += psg_multiplier * self.tone_channels[i].sample();
-                }
+        self.wave_channel.tick(cycles);
+        self.noise_channel.tick(cycles);
+    }
 
-                let wave_enabled = self.psg_left_right_reg.channel_enabled(2);
-                // TODO: Separate left and right audio
-                if wave_enabled.left || wave_enabled.right {
-                    audio_buffer.buffer[write_i] += psg_multiplier * self.wave_channel.sample();
-                }
+    // Mixes the channels into a sample in the audio buffer, returning the number of cycles until
+    // the next sample is due
+    pub fn sample(&mut self) -> u32 {
+        let mut audio_buffer = self.audio_buffer.lock().unwrap();
+        let write_i = audio_buffer.write_cursor & (audio_buffer.buffer.len() - 1);
+        audio_buffer.buffer[write_i] = 0.0;
+        if self.master_enable {
+            let psg_multiplier = 0.25 * self.dma_control_reg.psg_vol_multiplier();
 
-                let noise_enabled = self.psg_left_right_reg.channel_enabled(3);
+            for i in [0, 1] {
+                let psg_enabled = self.psg_left_right_reg.channel_enabled(i);
                 // TODO: Separate left and right audio
-                if noise_enabled.left || noise_enabled.right {
-                    audio_buffer.buffer[write_i] += psg_multiplier * self.noise_channel.sample();
+                if !(psg_enabled.left || psg_enabled.right) {
+                    continue;
                 }
+                audio_buffer.buffer[write_i] += psg_multiplier * self.tone_channels[i].sample();
+            }
 
-                for i in [0, 1] {
-                    let dma_enabled = self.dma_control_reg.dma_sound_enabled(i);
-                    // TODO: Separate left and right audio
-                    if !(dma_enabled.left || dma_enabled.right) {
-                        continue;
-                    }
-                    let dma_multiplier = 0.5 * self.dma_control_reg.dma_sound_vol_multiplier(i);
-                    audio_buffer.buffer[write_i] +=
-                        dma_multiplier * self.dma_sound_channels[i].sample();
+            let wave_enabled = self.psg_left_right_reg.channel_enabled(2);
+            // TODO: Separate left and right audio
+            if wave_enabled.left || wave_enabled.right {
+                audio_buffer.buffer[write_i] += psg_multiplier * self.wave_channel.sample();
+            }
+
+            let noise_enabled = self.psg_left_right_reg.channel_enabled(3);
+            // TODO: Separate left and right audio
+            if noise_enabled.left || noise_enabled.right {
+                audio_buffer.buffer[write_i] += psg_multiplier * self.noise_channel.sample();
+            }
+
+            for i in [0, 1] {
+                let dma_enabled = self.dma_control_reg.dma_sound_enabled(i);
+                // TODO: Separate left and right audio
+                if !(dma_enabled.left || dma_enabled.right) {
+                    continue;
                 }
+                let dma_multiplier = 0.5 * self.dma_control_reg.dma_sound_vol_multiplier(i);
+                audio_buffer.buffer[write_i] +=
+                    dma_multiplier * self.dma_sound_channels[i].sample();
             }
-            audio_buffer.write_cursor += 1;
         }
+        audio_buffer.write_cursor += 1;
 
-        let request_dma = self.request_dma;
-        self.request_dma = false;
-        request_dma
+        consts::MASTER_CLOCK_HZ / 44_100 + 1
     }
 
-    pub fn on_timer_overflow(&mut self, timer: DmaSoundTimer) {
+    // Returns true if a DMA is to be requested to refill a FIFO
+    pub fn on_timer_overflow(&mut self, timer: DmaSoundTimer) -> bool {
+        let mut request_dma = false;
         if self.dma_control_reg.dma_a_timer() == timer {
-            self.request_dma |= self.dma_sound_channels[0].tick_fifo();
+            request_dma |= self.dma_sound_channels[0].tick_fifo();
         }
         if self.dma_control_reg.dma_b_timer() == timer {
-            self.request_dma |= self.dma_sound_channels[1].tick_fifo();
+            request_dma |= self.dma_sound_channels[1].tick_fifo();
         }
+        request_dma
     }
 }
 
@@ -223,3 +229,39 @@ impl Memory for SoundController {
         }
     }
 }
+
+impl SaveState for SoundController {
+    fn save_state(&self, state: &mut StateWriter) {
+        for tone_channel in &self.tone_channels {
+            tone_channel.save_state(state);
+        }
+        self.wave_channel.save_state(state);
+        self.noise_channel.save_state(state);
+        for dma_sound_channel in &self.dma_sound_channels {
+            dma_sound_channel.save_state(state);
+        }
+
+        state.write_u16(self.psg_left_right_reg.bit_range(15, 0));
+        state.write_u16(self.dma_control_reg.bit_range(15, 0));
+        state.write_bool(self.master_enable);
+        state.write_u64(self.timestamp);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        for tone_channel in &mut self.tone_channels {
+            tone_channel.load_state(state)?;
+        }
+        self.wave_channel.load_state(state)?;
+        self.noise_channel.load_state(state)?;
+        for dma_sound_channel in &mut self.dma_sound_channels {
+            dma_sound_channel.load_state(state)?;
+        }
+
+        self.psg_left_right_reg
+            .set_bit_range(15, 0, state.read_u16()?);
+        self.dma_control_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.master_enable = state.read_bool()?;
+        self.timestamp = state.read_u64()?;
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 use crate::consts::*;
+use crate::counter::advance_counter;
 use crate::registers::*;
 
+use bitfield::BitRange;
+use memory::{SaveState, StateError, StateReader, StateWriter};
+
 pub struct NoiseChannel {
     control_reg: ToneControlReg,
     frequency_reg: NoiseFrequencyReg,
@@ -33,17 +37,17 @@ impl NoiseChannel {
     }
 
     // TODO: Deduplicate a lot of this from ToneChannel
-    pub fn tick(&mut self) {
-        self.tick_wave();
-        self.tick_length();
-        self.tick_envelope();
+    // Advances the channel by the given number of cycles
+    pub fn tick(&mut self, cycles: u32) {
+        self.tick_wave(cycles);
+        self.tick_length(cycles);
+        self.tick_envelope(cycles);
     }
 
-    fn tick_wave(&mut self) {
-        if self.counter > 0 {
-            self.counter -= 1;
-        } else {
-            self.counter = self.period();
+    fn tick_wave(&mut self, cycles: u32) {
+        let period = self.period();
+        let steps = advance_counter(&mut self.counter, period, cycles);
+        for _ in 0..steps {
             self.progress_playback();
         }
     }
@@ -56,33 +60,29 @@ impl NoiseChannel {
         }
     }
 
-    fn tick_length(&mut self) {
-        if self.length_divider > 0 {
-            self.length_divider -= 1;
-        } else {
-            self.length_divider = LENGTH_UNIT_PERIOD;
-            if self.length_counter > 0 {
-                self.length_counter -= 1;
-            }
+    fn tick_length(&mut self, cycles: u32) {
+        let steps = advance_counter(&mut self.length_divider, LENGTH_UNIT_PERIOD, cycles);
+        self.length_counter = self.length_counter.saturating_sub(steps);
+    }
+
+    fn tick_envelope(&mut self, cycles: u32) {
+        let steps = advance_counter(&mut self.envelope_divider, ENVELOPE_UNIT_PERIOD, cycles);
+        for _ in 0..steps {
+            self.step_envelope();
         }
     }
 
-    fn tick_envelope(&mut self) {
-        if self.envelope_divider > 0 {
-            self.envelope_divider -= 1;
+    fn step_envelope(&mut self) {
+        if self.envelope_counter > 0 {
+            self.envelope_counter -= 1;
         } else {
-            self.envelope_divider = ENVELOPE_UNIT_PERIOD;
-            if self.envelope_counter > 0 {
-                self.envelope_counter -= 1;
-            } else {
-                self.envelope_counter = self.control_reg.envelope_step_time() as u32;
-                if self.envelope_counter == 0 {
-                    return;
-                }
-                self.curr_vol = match self.control_reg.envelope_dir() {
-                    EnvelopeDirection::Decrease => self.curr_vol.saturating_sub(1),
-                    EnvelopeDirection::Increase => std::cmp::min(self.curr_vol + 1, 15),
-                }
+            self.envelope_counter = self.control_reg.envelope_step_time() as u32;
+            if self.envelope_counter == 0 {
+                return;
+            }
+            self.curr_vol = match self.control_reg.envelope_dir() {
+                EnvelopeDirection::Decrease => self.curr_vol.saturating_sub(1),
+                EnvelopeDirection::Increase => std::cmp::min(self.curr_vol + 1, 15),
             }
         }
     }
@@ -165,3 +165,34 @@ impl NoiseChannel {
         MASTER_CLOCK_HZ / (base_freq / factor)
     }
 }
+
+impl SaveState for NoiseChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.control_reg.bit_range(15, 0));
+        state.write_u16(self.frequency_reg.bit_range(15, 0));
+
+        state.write_u32(self.counter);
+        state.write_bool(self.output_high);
+        state.write_u16(self.polynomial_shift_reg);
+        state.write_u16(self.curr_vol);
+        state.write_u32(self.length_counter);
+        state.write_u32(self.length_divider);
+        state.write_u32(self.envelope_counter);
+        state.write_u32(self.envelope_divider);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.control_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.frequency_reg.set_bit_range(15, 0, state.read_u16()?);
+
+        self.counter = state.read_u32()?;
+        self.output_high = state.read_bool()?;
+        self.polynomial_shift_reg = state.read_u16()?;
+        self.curr_vol = state.read_u16()?;
+        self.length_counter = state.read_u32()?;
+        self.length_divider = state.read_u32()?;
+        self.envelope_counter = state.read_u32()?;
+        self.envelope_divider = state.read_u32()?;
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 use crate::consts::*;
+use crate::counter::advance_counter;
 use crate::registers::*;
 
+use bitfield::BitRange;
+use memory::{SaveState, StateError, StateReader, StateWriter};
+
 pub struct ToneChannel {
     // Channel 2 doesn't support tone sweep, so this register is unmodifiable via IO for that channel.
     pub sweep_reg: ToneSweepReg,
@@ -37,69 +41,65 @@ impl ToneChannel {
         }
     }
 
-    pub fn tick(&mut self) {
-        self.tick_wave();
-        self.tick_length();
-        self.tick_envelope();
-        self.tick_sweep();
+    // Advances the channel by the given number of cycles
+    pub fn tick(&mut self, cycles: u32) {
+        // Sweep steps change the period, so the channel is advanced one sweep step at a time
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let chunk = std::cmp::min(remaining, self.sweep_divider + 1);
+            let period = self.period();
+            advance_counter(&mut self.counter, period, chunk);
+            self.tick_length(chunk);
+            self.tick_envelope(chunk);
+            self.tick_sweep(chunk);
+            remaining -= chunk;
+        }
     }
 
-    fn tick_wave(&mut self) {
-        if self.counter > 0 {
-            self.counter -= 1;
-        } else {
-            self.counter = self.period();
+    fn tick_length(&mut self, cycles: u32) {
+        let steps = advance_counter(&mut self.length_divider, LENGTH_UNIT_PERIOD, cycles);
+        self.length_counter = self.length_counter.saturating_sub(steps);
+    }
+
+    fn tick_envelope(&mut self, cycles: u32) {
+        let steps = advance_counter(&mut self.envelope_divider, ENVELOPE_UNIT_PERIOD, cycles);
+        for _ in 0..steps {
+            self.step_envelope();
         }
     }
 
-    fn tick_length(&mut self) {
-        if self.length_divider > 0 {
-            self.length_divider -= 1;
+    fn step_envelope(&mut self) {
+        if self.envelope_counter > 0 {
+            self.envelope_counter -= 1;
         } else {
-            self.length_divider = LENGTH_UNIT_PERIOD;
-            if self.length_counter > 0 {
-                self.length_counter -= 1;
+            self.envelope_counter = self.control_reg.envelope_step_time() as u32;
+            if self.envelope_counter == 0 {
+                return;
+            }
+            self.curr_vol = match self.control_reg.envelope_dir() {
+                EnvelopeDirection::Decrease => self.curr_vol.saturating_sub(1),
+                EnvelopeDirection::Increase => std::cmp::min(self.curr_vol + 1, 15),
             }
         }
     }
 
-    fn tick_envelope(&mut self) {
-        if self.envelope_divider > 0 {
-            self.envelope_divider -= 1;
-        } else {
-            self.envelope_divider = ENVELOPE_UNIT_PERIOD;
-            if self.envelope_counter > 0 {
-                self.envelope_counter -= 1;
-            } else {
-                self.envelope_counter = self.control_reg.envelope_step_time() as u32;
-                if self.envelope_counter == 0 {
-                    return;
-                }
-                self.curr_vol = match self.control_reg.envelope_dir() {
-                    EnvelopeDirection::Decrease => self.curr_vol.saturating_sub(1),
-                    EnvelopeDirection::Increase => std::cmp::min(self.curr_vol + 1, 15),
-                }
-            }
+    fn tick_sweep(&mut self, cycles: u32) {
+        let steps = advance_counter(&mut self.sweep_divider, SWEEP_UNIT_PERIOD, cycles);
+        for _ in 0..steps {
+            self.step_sweep();
         }
     }
 
-    fn tick_sweep(&mut self) {
-        if self.sweep_divider > 0 {
-            self.sweep_divider -= 1;
+    fn step_sweep(&mut self) {
+        if self.sweep_counter > 0 {
+            self.sweep_counter -= 1;
         } else {
-            self.sweep_divider = SWEEP_UNIT_PERIOD;
-            if self.sweep_counter > 0 {
-                self.sweep_counter -= 1;
-            } else {
-                self.sweep_counter = self.sweep_reg.sweep_time() as u32;
-                if self.sweep_counter != 0 {
-                    let delta_rate = self.curr_rate / (1 << self.sweep_reg.sweep_shift_n());
-                    self.curr_rate = match self.sweep_reg.sweep_dir() {
-                        SweepDirection::Decrease => self.curr_rate.saturating_sub(delta_rate),
-                        SweepDirection::Increase => {
-                            std::cmp::min(self.curr_rate + delta_rate, 2047)
-                        }
-                    }
+            self.sweep_counter = self.sweep_reg.sweep_time() as u32;
+            if self.sweep_counter != 0 {
+                let delta_rate = self.curr_rate / (1 << self.sweep_reg.sweep_shift_n());
+                self.curr_rate = match self.sweep_reg.sweep_dir() {
+                    SweepDirection::Decrease => self.curr_rate.saturating_sub(delta_rate),
+                    SweepDirection::Increase => std::cmp::min(self.curr_rate + delta_rate, 2047),
                 }
             }
         }
@@ -185,3 +185,38 @@ impl ToneChannel {
         }
     }
 }
+
+impl SaveState for ToneChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.sweep_reg.bit_range(15, 0));
+        state.write_u16(self.control_reg.bit_range(15, 0));
+        state.write_u16(self.frequency_reg.bit_range(15, 0));
+
+        state.write_u16(self.curr_rate);
+        state.write_u32(self.counter);
+        state.write_u16(self.curr_vol);
+        state.write_u32(self.length_counter);
+        state.write_u32(self.length_divider);
+        state.write_u32(self.envelope_counter);
+        state.write_u32(self.envelope_divider);
+        state.write_u32(self.sweep_counter);
+        state.write_u32(self.sweep_divider);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.sweep_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.control_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.frequency_reg.set_bit_range(15, 0, state.read_u16()?);
+
+        self.curr_rate = state.read_u16()?;
+        self.counter = state.read_u32()?;
+        self.curr_vol = state.read_u16()?;
+        self.length_counter = state.read_u32()?;
+        self.length_divider = state.read_u32()?;
+        self.envelope_counter = state.read_u32()?;
+        self.envelope_divider = state.read_u32()?;
+        self.sweep_counter = state.read_u32()?;
+        self.sweep_divider = state.read_u32()?;
+        Ok(())
+    }
+}
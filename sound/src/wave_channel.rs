@@ -1,6 +1,10 @@
 use crate::consts::*;
+use crate::counter::advance_counter;
 use crate::registers::*;
 
+use bitfield::BitRange;
+use memory::{SaveState, StateError, StateReader, StateWriter};
+
 pub struct WaveChannel {
     pub control_reg: WaveControlReg,
     pub length_volume_reg: WaveLengthVolumeReg,
@@ -64,11 +68,12 @@ impl WaveChannel {
         }
     }
 
-    pub fn tick(&mut self) {
+    // Advances the channel by the given number of cycles
+    pub fn tick(&mut self, cycles: u32) {
         if self.control_reg.enable() {
-            self.tick_wave();
+            self.tick_wave(cycles);
         }
-        self.tick_length();
+        self.tick_length(cycles);
     }
 
     // `octet_i` represents the i'th pattern RAM register (4000090h, 4000091h, 4000092h, etc.),
@@ -86,11 +91,10 @@ impl WaveChannel {
         self.pattern_ram[bank_i] |= (data as u128) << offset;
     }
 
-    fn tick_wave(&mut self) {
-        if self.counter > 0 {
-            self.counter -= 1;
-        } else {
-            self.counter = self.period();
+    fn tick_wave(&mut self, cycles: u32) {
+        let period = self.period();
+        let steps = advance_counter(&mut self.counter, period, cycles);
+        for _ in 0..steps {
             self.progress_playback();
         }
     }
@@ -108,15 +112,9 @@ impl WaveChannel {
         }
     }
 
-    fn tick_length(&mut self) {
-        if self.length_divider > 0 {
-            self.length_divider -= 1;
-        } else {
-            self.length_divider = LENGTH_UNIT_PERIOD;
-            if self.length_counter > 0 {
-                self.length_counter -= 1;
-            }
-        }
+    fn tick_length(&mut self, cycles: u32) {
+        let steps = advance_counter(&mut self.length_divider, LENGTH_UNIT_PERIOD, cycles);
+        self.length_counter = self.length_counter.saturating_sub(steps);
     }
 
     pub fn set_frequency_reg_lo(&mut self, data: u8) {
@@ -150,3 +148,34 @@ impl WaveChannel {
         MASTER_CLOCK_HZ / (2_097_152 / (2048 - self.frequency_reg.rate() as u32))
     }
 }
+
+impl SaveState for WaveChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u16(self.control_reg.bit_range(15, 0));
+        state.write_u16(self.length_volume_reg.bit_range(15, 0));
+        state.write_u16(self.frequency_reg.bit_range(15, 0));
+
+        state.write_u128(self.pattern_ram[0]);
+        state.write_u128(self.pattern_ram[1]);
+        state.write_u32(self.playing_octet as u32);
+        state.write_bool(self.playing_other_bank);
+        state.write_u32(self.counter);
+        state.write_u32(self.length_counter);
+        state.write_u32(self.length_divider);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.control_reg.set_bit_range(15, 0, state.read_u16()?);
+        self.length_volume_reg
+            .set_bit_range(15, 0, state.read_u16()?);
+        self.frequency_reg.set_bit_range(15, 0, state.read_u16()?);
+
+        self.pattern_ram = [state.read_u128()?, state.read_u128()?];
+        self.playing_octet = state.read_u32()? as usize;
+        self.playing_other_bank = state.read_bool()?;
+        self.counter = state.read_u32()?;
+        self.length_counter = state.read_u32()?;
+        self.length_divider = state.read_u32()?;
+        Ok(())
+    }
+}